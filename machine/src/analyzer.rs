@@ -0,0 +1,406 @@
+pub mod analyzer {
+    use crate::config::config::{ConfigError, MachineConfig};
+    use crate::machine::{branch_condition, execute, operate, Instruction, OpCode,
+                         ProgramLoadWord, Semantics};
+    use std::fmt;
+
+    /// The words a register or memory word may hold: every word from low
+    /// to high, as unsigned numbers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Range {
+        pub low: u16,
+        pub high: u16,
+    }
+
+    // bounds a growing range jumps to, so loops settle: the addresses, the
+    // positive words, then everything
+    const WIDENING: [u16; 3] = [0x00FF, 0x7FFF, 0xFFFF];
+
+    // times a word is analyzed before its ranges start to widen
+    const WIDEN_AFTER: u32 = 3;
+
+    impl Range {
+        pub const ANY: Range = Range { low: 0, high: 0xFFFF };
+
+        pub fn point(word: u16) -> Self {
+            Self { low: word, high: word }
+        }
+        pub fn single(&self) -> Option<u16> {
+            (self.low == self.high).then_some(self.low)
+        }
+        pub fn contains(&self, word: u16) -> bool {
+            (self.low..=self.high).contains(&word)
+        }
+        fn overlaps(&self, low: u16, high: u16) -> bool {
+            self.low <= high && low <= self.high
+        }
+        fn join(&self, other: &Range) -> Range {
+            Range { low: self.low.min(other.low), high: self.high.max(other.high) }
+        }
+        // self joined with newer, with any bound that moved pushed out
+        fn widen(&self, newer: &Range) -> Range {
+            let low = if newer.low < self.low { 0 } else { self.low };
+            let high = if newer.high > self.high {
+                WIDENING.iter().copied().find(|&bound| bound >= newer.high).unwrap_or(0xFFFF)
+            } else {
+                self.high
+            };
+            Range { low, high }
+        }
+    }
+
+    impl fmt::Display for Range {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.single() {
+                Some(word) => write!(f, "{:04X}", word),
+                None => write!(f, "{:04X}..{:04X}", self.low, self.high),
+            }
+        }
+    }
+
+    // R[s] op R[t] on ranges: exact for single words, using the machine's
+    // own arithmetic, and a range sure to hold every result otherwise
+    fn operate_ranges(op: OpCode, a: Range, b: Range) -> Range {
+        if let (Some(a), Some(b)) = (a.single(), b.single()) {
+            return Range::point(operate(op, a, b));
+        }
+        let all_ones_to = |word: u16| if word == 0 { 0 } else { u16::MAX >> word.leading_zeros() };
+        match op {
+            OpCode::Add => match a.high.checked_add(b.high) {
+                Some(high) => Range { low: a.low + b.low, high },
+                None => Range::ANY,
+            },
+            OpCode::Subtract if a.low >= b.high => Range { low: a.low - b.high, high: a.high - b.low },
+            OpCode::And => Range { low: 0, high: a.high.min(b.high) },
+            OpCode::Xor => Range { low: 0, high: all_ones_to(a.high.max(b.high)) },
+            // a shift of a positive word by a known count keeps the order
+            OpCode::ShiftRight if a.high < 0x8000 => match b.single() {
+                Some(count) => Range { low: operate(op, a.low, count), high: operate(op, a.high, count) },
+                None => Range { low: 0, high: a.high },
+            },
+            OpCode::ShiftLeft => match b.single().filter(|&count| count < 16) {
+                Some(count) if (a.high as u32) << count <= 0xFFFF =>
+                    Range { low: operate(op, a.low, count), high: operate(op, a.high, count) },
+                _ => Range::ANY,
+            },
+            _ => Range::ANY,
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct State {
+        regs: [Range; 16],
+        memory: Vec<Range>,
+    }
+
+    impl State {
+        fn join(&self, other: &State, widen: bool) -> State {
+            let merge = |old: &Range, new: &Range| if widen { old.widen(&old.join(new)) } else { old.join(new) };
+            let mut regs = self.regs;
+            for (reg, other) in regs.iter_mut().zip(&other.regs) {
+                *reg = merge(reg, other);
+            }
+            let memory = self.memory.iter().zip(&other.memory).map(|(old, new)| merge(old, new)).collect();
+            State { regs, memory }
+        }
+    }
+
+    // one instruction run on ranges, collecting where control can go next
+    struct Step<'a> {
+        state: &'a mut State,
+        io_address: u16,
+        next: u16,
+        falls_through: bool,
+        targets: Vec<u16>,
+    }
+
+    impl Semantics for Step<'_> {
+        type Value = Range;
+        fn constant(&self, word: u16) -> Range {
+            Range::point(word)
+        }
+        fn read_reg(&self, r: usize) -> Range {
+            self.state.regs[r]
+        }
+        fn write_reg(&mut self, r: usize, value: Range) {
+            self.state.regs[r] = value;
+        }
+        // any word can come in from the input
+        fn read_memory(&mut self, address: Range) -> Range {
+            if address.contains(self.io_address) {
+                return Range::ANY;
+            }
+            let words = &self.state.memory[address.low as usize..=address.high as usize];
+            words.iter().skip(1).fold(words[0], |range, word| range.join(word))
+        }
+        fn write_memory(&mut self, address: Range, value: Range) {
+            match address.single() {
+                Some(address) => self.state.memory[address as usize] = value,
+                None => for word in &mut self.state.memory[address.low as usize..=address.high as usize] {
+                    *word = word.join(&value);
+                },
+            }
+        }
+        fn operate(&self, op: OpCode, a: Range, b: Range) -> Range {
+            operate_ranges(op, a, b)
+        }
+        fn branch(&mut self, op: OpCode, value: Range, target: u16) {
+            let (taken, not_taken) = match value.single() {
+                Some(word) => (branch_condition(op, word), !branch_condition(op, word)),
+                None if op == OpCode::BranchZero => (value.contains(0), true),
+                None => (value.overlaps(1, 0x7FFF), value.contains(0) || value.overlaps(0x8000, 0xFFFF)),
+            };
+            if taken {
+                self.targets.push(target);
+            }
+            self.falls_through = not_taken;
+        }
+        // a jump to a range of addresses is left unresolved
+        fn jump(&mut self, target: Range) {
+            self.targets.extend(target.single());
+            self.falls_through = false;
+        }
+        fn return_address(&self) -> Range {
+            Range::point(self.next)
+        }
+    }
+
+    /// Something that may go wrong at pc for some run of the program.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Warning {
+        // a shift count that can be 16 or more, which shifts every bit out
+        LargeShift { pc: u16, count: Range },
+        // an indirect load or store whose address can be a word of code
+        IndirectIntoCode { pc: u16, address: Range },
+        // an indirect load or store whose address can be the i/o word
+        IndirectIo { pc: u16, address: Range },
+        // a jump through a register that is not one known address
+        UnresolvedJump { pc: u16, target: Range },
+    }
+
+    impl fmt::Display for Warning {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Warning::LargeShift { pc, count } =>
+                    write!(f, "{:02X}: the shift count may be 16 or more ({})", pc, count),
+                Warning::IndirectIntoCode { pc, address } =>
+                    write!(f, "{:02X}: the indirect address may point into code ({})", pc, address),
+                Warning::IndirectIo { pc, address } =>
+                    write!(f, "{:02X}: the indirect address may be the i/o word ({})", pc, address),
+                Warning::UnresolvedJump { pc, target } =>
+                    write!(f, "{:02X}: the jump target is not known ({})", pc, target),
+            }
+        }
+    }
+
+    /// The ranges the registers can hold before each instruction the
+    /// program can reach from the entry pc, found by running it on ranges
+    /// instead of words, with what may go wrong. Registers and memory start
+    /// at zero apart from the program, and input can be any word. The
+    /// program is taken not to change its own instructions.
+    #[derive(Debug, Clone)]
+    pub struct Analysis {
+        states: Vec<Option<State>>,
+        warnings: Vec<Warning>,
+    }
+
+    impl Analysis {
+        pub fn get_warnings(&self) -> &[Warning] {
+            &self.warnings
+        }
+        // None for a word that cannot run
+        pub fn registers_at(&self, pc: u16) -> Option<&[Range; 16]> {
+            self.states.get(pc as usize)?.as_ref().map(|state| &state.regs)
+        }
+        pub fn is_reachable(&self, pc: u16) -> bool {
+            self.states.get(pc as usize).is_some_and(|state| state.is_some())
+        }
+    }
+
+    // Analyze a program for a machine of the given shape, which must be one
+    // a Machine can be built with.
+    pub fn analyze(loads: &[ProgramLoadWord], config: &MachineConfig) -> Result<Analysis, ConfigError> {
+        config.validate(16, 4)?;
+        let mask = config.address_mask();
+        let mut memory = vec![0u16; config.memory_size()];
+        for load in loads {
+            memory[(load.get_address() as u16 & mask) as usize] = load.get_content();
+        }
+        let mut states: Vec<Option<State>> = vec![None; memory.len()];
+        states[config.entry_pc as usize] = Some(State {
+            regs: [Range::point(0); 16],
+            memory: memory.iter().map(|word| Range::point(*word)).collect(),
+        });
+        let mut visits = vec![0u32; memory.len()];
+        let mut pending = vec![config.entry_pc];
+        while let Some(pc) = pending.pop() {
+            visits[pc as usize] += 1;
+            let mut state = states[pc as usize].clone().expect("pending words have a state");
+            let next = pc.wrapping_add(1) & mask;
+            let instruction = Instruction::decode(memory[pc as usize]);
+            let mut step = Step { state: &mut state, io_address: config.io_address, next,
+                falls_through: true, targets: vec![] };
+            let running = execute(&mut step, &instruction, mask);
+            let mut successors = std::mem::take(&mut step.targets);
+            if step.falls_through {
+                successors.push(next);
+            }
+            // a register the machine does not have traps, as does halt
+            let register_count = config.register_count as u8;
+            let missing = (register_count..16).any(|r|
+                instruction.reads_register(r) || instruction.register_written() == Some(r));
+            if !running || missing {
+                successors.clear();
+            }
+            // fetching the i/o word traps, so nothing runs after it
+            for successor in successors.into_iter().filter(|&successor| successor != config.io_address) {
+                let slot = &mut states[successor as usize];
+                let merged = match slot {
+                    None => state.clone(),
+                    Some(old) => old.join(&state, visits[successor as usize] >= WIDEN_AFTER),
+                };
+                if slot.as_ref() != Some(&merged) {
+                    *slot = Some(merged);
+                    if !pending.contains(&successor) {
+                        pending.push(successor);
+                    }
+                }
+            }
+        }
+        let warnings = (0..memory.len() as u16)
+            .filter_map(|pc| Some((pc, states[pc as usize].as_ref()?)))
+            .flat_map(|(pc, state)| warnings_at(pc, &Instruction::decode(memory[pc as usize]), state,
+                                                &states, config))
+            .collect();
+        Ok(Analysis { states, warnings })
+    }
+
+    fn warnings_at(pc: u16, instruction: &Instruction, state: &State, states: &[Option<State>],
+                   config: &MachineConfig) -> Vec<Warning> {
+        let mask = Range::point(config.address_mask());
+        let (op, d, _, t, _) = instruction.get_values();
+        let mut warnings = vec![];
+        match op {
+            OpCode::ShiftLeft | OpCode::ShiftRight => {
+                let count = state.regs[t as usize];
+                if count.high >= 16 {
+                    warnings.push(Warning::LargeShift { pc, count });
+                }
+            },
+            OpCode::LoadIndirect | OpCode::StoreIndirect => {
+                let address = operate_ranges(OpCode::And, state.regs[t as usize], mask);
+                let code = (address.low..=address.high).any(|address| states[address as usize].is_some());
+                if code {
+                    warnings.push(Warning::IndirectIntoCode { pc, address });
+                }
+                if address.contains(config.io_address) {
+                    warnings.push(Warning::IndirectIo { pc, address });
+                }
+            },
+            OpCode::JumpRegister => {
+                let target = operate_ranges(OpCode::And, state.regs[d as usize], mask);
+                if target.single().is_none() {
+                    warnings.push(Warning::UnresolvedJump { pc, target });
+                }
+            },
+            _ => {},
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod analyzer_tests {
+    use super::analyzer::{analyze, Analysis, Range, Warning};
+    use crate::config::config::{ConfigError, MachineConfig};
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::machine::{Machine, ProgramLoadWord};
+    use crate::test_programs::{loads, MULTIPLY};
+    use crate::trace::trace::NoTrace;
+
+    fn analyze_toy(loads: &[ProgramLoadWord]) -> Analysis {
+        analyze(loads, &MachineConfig::toy()).unwrap()
+    }
+
+    #[test]
+    fn multiply_is_clean() {
+        let analysis = analyze_toy(&loads(&MULTIPLY));
+        assert!(analysis.get_warnings().is_empty());
+        assert_eq!(analysis.registers_at(0x14).unwrap()[1], Range::point(1));
+        assert!(analysis.is_reachable(0x19));
+        assert!(!analysis.is_reachable(0x1A));
+    }
+
+    #[test]
+    fn finds_each_kind() {
+        let analysis = analyze_toy(&loads(&[
+            (0x10, 0x8AFF),   // read R[A]
+            (0x11, 0x7101),   // R[1] <- 1
+            (0x12, 0x521A),   // R[2] <- R[1] << R[A], by any count
+            (0x13, 0x7330),   // R[3] <- 30
+            (0x14, 0xA403),   // R[4] <- mem[R[3]], the data at 30
+            (0x15, 0x9A40),   // mem[40] <- R[A]
+            (0x16, 0xA54A),   // R[5] <- mem[R[A]], anywhere
+            (0x17, 0xE400),   // goto R[4], the data read from 30
+            (0x30, 0x0019),   // data: 19
+            (0x19, 0xEA00),   // goto R[A], any address
+        ]));
+        assert_eq!(analysis.get_warnings(), &[
+            Warning::LargeShift { pc: 0x12, count: Range::ANY },
+            Warning::IndirectIntoCode { pc: 0x16, address: Range { low: 0, high: 0xFF } },
+            Warning::IndirectIo { pc: 0x16, address: Range { low: 0, high: 0xFF } },
+            Warning::UnresolvedJump { pc: 0x19, target: Range { low: 0, high: 0xFF } },
+        ]);
+        assert_eq!(analysis.registers_at(0x19).unwrap()[4], Range::point(0x19));
+    }
+
+    #[test]
+    fn agrees_with_the_machine() {
+        // every op code that computes, with no input, so each range is one word
+        let program = loads(&[(0x10, 0x71F3), (0x11, 0x7204), (0x12, 0x1312), (0x13, 0x2423),
+                              (0x14, 0x3512), (0x15, 0x4612), (0x16, 0x5712), (0x17, 0x6812),
+                              (0x18, 0x9830), (0x19, 0x7A30), (0x1A, 0xA90A), (0x1B, 0xFB1D),
+                              (0x1C, 0x0000), (0x1D, 0xEB00)]);
+        let analysis = analyze_toy(&program);
+        let mut machine = Machine::new();
+        machine.load(program);
+        machine.run_traced(&mut ExternalEnv::new(vec![]), &mut NoTrace).unwrap();
+        let ranges = analysis.registers_at(0x1C).unwrap();
+        let words: Vec<_> = ranges.iter().map(|range| range.single().unwrap()).collect();
+        assert_eq!(words, machine.get_regs());
+    }
+
+    #[test]
+    fn loops_settle() {
+        // 10: 7101 R[1] <- 1; 11: 7A00 R[A] <- 0
+        // 12: 1AA1 R[A] <- R[A] + 1; 13: BA0A mem[R[A]] <- R[A]; 14: C012 goto 12
+        // the counter goes on past FFFF and wraps, so it can be any word
+        let analysis = analyze_toy(&loads(&[(0x10, 0x7101), (0x11, 0x7A00), (0x12, 0x1AA1),
+                                         (0x13, 0xBA0A), (0x14, 0xC012)]));
+        assert_eq!(analysis.registers_at(0x13).unwrap()[0xA], Range::ANY);
+        assert_eq!(analysis.registers_at(0x13).unwrap()[1], Range::point(1));
+        let everywhere = Range { low: 0, high: 0xFF };
+        assert_eq!(analysis.get_warnings(), &[Warning::IndirectIntoCode { pc: 0x13, address: everywhere },
+                                               Warning::IndirectIo { pc: 0x13, address: everywhere }]);
+    }
+
+    #[test]
+    fn follows_the_config() {
+        // 20: 82FE read R[2] through FE; 21: B202 mem[R[2]] <- R[2]
+        // 22: 7101 R[1] <- 1; 23: 1991 R[9] <- R[9] + R[1], a missing register
+        // 24: 0000 halt, never reached
+        let program = loads(&[(0x20, 0x82FE), (0x21, 0xB202), (0x22, 0x7101), (0x23, 0x1991),
+                              (0x24, 0x0000)]);
+        let config = MachineConfig { address_bits: 12, register_count: 8, entry_pc: 0x20, io_address: 0xFE,
+            ..MachineConfig::toy() };
+        let analysis = analyze(&program, &config).unwrap();
+        assert!(analysis.is_reachable(0x23));
+        assert!(!analysis.is_reachable(0x24));
+        assert!(!analysis.is_reachable(0x1000));
+        let everywhere = Range { low: 0, high: 0xFFF };
+        assert_eq!(analysis.get_warnings(), &[Warning::IndirectIntoCode { pc: 0x21, address: everywhere },
+                                               Warning::IndirectIo { pc: 0x21, address: everywhere }]);
+        let config = MachineConfig { word_bits: 8, ..MachineConfig::toy() };
+        assert_eq!(analyze(&program, &config).err(), Some(ConfigError::WordBits(8)));
+    }
+}
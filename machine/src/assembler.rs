@@ -0,0 +1,867 @@
+pub mod assembler {
+    use crate::disassembler::disassembler::ENTRY;
+    use crate::machine::{Instruction, OpCode, ProgramLoadWord};
+    use std::collections::HashMap;
+    use std::fmt;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum AssemblyErrorKind {
+        UnknownMnemonic(String),
+        UnknownDirective(String),
+        BadRegister(String),
+        RegisterOutOfRange(String),
+        BadNumber(String),
+        ValueOutOfRange(String),
+        BadLabel(String),
+        DuplicateLabel(String),
+        UndefinedLabel(String),
+        WrongOperandCount { mnemonic: String, expected: usize, found: usize },
+        // the program ran past mem[FF]
+        AddressOverflow,
+        // a second word assembled to the same address
+        Overlap(u8),
+        // push or pop of the stack pointer or the scratch register
+        ReservedRegister(String),
+        DuplicateMacro(String),
+        UnterminatedMacro(String),
+        // an .endm with no .macro before it
+        StrayEndm,
+        // a macro that calls itself without end
+        MacroTooDeep(String),
+    }
+
+    impl fmt::Display for AssemblyErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                AssemblyErrorKind::UnknownMnemonic(text) => write!(f, "unknown mnemonic `{}`", text),
+                AssemblyErrorKind::UnknownDirective(text) => write!(f, "unknown directive `{}`", text),
+                AssemblyErrorKind::BadRegister(text) => write!(f, "`{}` is not a register", text),
+                AssemblyErrorKind::RegisterOutOfRange(text) => write!(
+                    f, "register `{}` is out of range; registers are R[0] to R[F]", text),
+                AssemblyErrorKind::BadNumber(text) => write!(f, "`{}` is not a hex number", text),
+                AssemblyErrorKind::ValueOutOfRange(text) => write!(f, "`{}` does not fit", text),
+                AssemblyErrorKind::BadLabel(text) => write!(f, "`{}` is not a label name", text),
+                AssemblyErrorKind::DuplicateLabel(text) => write!(f, "label `{}` is already defined", text),
+                AssemblyErrorKind::UndefinedLabel(text) => write!(f, "undefined label `{}`", text),
+                AssemblyErrorKind::WrongOperandCount { mnemonic, expected, found } => write!(
+                    f, "`{}` takes {} operands, found {}", mnemonic, expected, found),
+                AssemblyErrorKind::AddressOverflow => write!(f, "program runs past address FF"),
+                AssemblyErrorKind::Overlap(address) => write!(f, "address {:02X} is already used", address),
+                AssemblyErrorKind::ReservedRegister(text) => write!(
+                    f, "`{}` is used by push and pop and cannot be pushed or popped", text),
+                AssemblyErrorKind::DuplicateMacro(text) => write!(
+                    f, "`{}` is already a mnemonic or macro", text),
+                AssemblyErrorKind::UnterminatedMacro(text) => write!(f, "macro `{}` has no .endm", text),
+                AssemblyErrorKind::StrayEndm => write!(f, ".endm without .macro"),
+                AssemblyErrorKind::MacroTooDeep(text) => write!(
+                    f, "macro `{}` expands more than {} levels deep", text, MAX_MACRO_DEPTH),
+            }
+        }
+    }
+
+    /// A problem found in the source, at a 1-based line and column.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AssemblyError {
+        pub line: usize,
+        pub column: usize,
+        pub kind: AssemblyErrorKind,
+    }
+
+    impl fmt::Display for AssemblyError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+        }
+    }
+
+    impl std::error::Error for AssemblyError {}
+
+    // a word of source text and the column it starts at
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Token {
+        text: String,
+        column: usize,
+    }
+
+    impl Token {
+        fn error(&self, line: usize, kind: AssemblyErrorKind) -> AssemblyError {
+            AssemblyError { line, column: self.column, kind }
+        }
+    }
+
+    // one line of source: an optional label, then an optional mnemonic or
+    // directive with its operands
+    #[derive(Debug, Clone)]
+    struct Statement {
+        line: usize,
+        label: Option<Token>,
+        operation: Option<Token>,
+        operands: Vec<Token>,
+        // the pseudo-instruction or macro call this was expanded from
+        origin: Option<String>,
+    }
+
+    impl Statement {
+        // the operation and operands as written, without label or comment
+        fn source_text(&self) -> String {
+            let operands: Vec<&str> = self.operands.iter().map(|token| token.text.as_str()).collect();
+            match &self.operation {
+                Some(operation) if operands.is_empty() => operation.text.clone(),
+                Some(operation) => format!("{} {}", operation.text, operands.join(", ")),
+                None => String::new(),
+            }
+        }
+    }
+
+    // Split a line into tokens at spaces and commas, dropping a comment
+    // that starts with ; or #.
+    fn tokenize(text: &str) -> Vec<Token> {
+        let mut tokens = vec![];
+        let mut current: Option<Token> = None;
+        for (index, character) in text.chars().enumerate() {
+            if character == ';' || character == '#' {
+                break;
+            }
+            if character.is_whitespace() || character == ',' {
+                tokens.extend(current.take());
+            } else {
+                current.get_or_insert_with(|| Token { text: String::new(), column: index + 1 })
+                    .text.push(character);
+            }
+        }
+        tokens.extend(current);
+        tokens
+    }
+
+    fn parse_statement(line: usize, text: &str) -> Statement {
+        let mut tokens = tokenize(text).into_iter().peekable();
+        let label = tokens.next_if(|token| token.text.ends_with(':')).map(|mut token| {
+            token.text.pop();
+            token
+        });
+        let operation = tokens.next();
+        Statement { line, label, operation, operands: tokens.collect(), origin: None }
+    }
+
+    fn is_label_name(text: &str) -> bool {
+        let mut characters = text.chars();
+        characters.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && characters.all(|character| character.is_ascii_alphanumeric() || character == '_')
+    }
+
+    // R[C], RC or rC
+    fn parse_register(line: usize, token: &Token) -> Result<u8, AssemblyError> {
+        let text = token.text.as_str();
+        let digits = text.strip_prefix(['R', 'r'])
+            .map(|rest| rest.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).unwrap_or(rest))
+            .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| token.error(line, AssemblyErrorKind::BadRegister(text.into())))?;
+        match u32::from_str_radix(digits, 16) {
+            Ok(register) if register < 16 => Ok(register as u8),
+            _ => Err(token.error(line, AssemblyErrorKind::RegisterOutOfRange(text.into()))),
+        }
+    }
+
+    fn parse_number(line: usize, token: &Token, max: u16) -> Result<u16, AssemblyError> {
+        let value = u32::from_str_radix(&token.text, 16)
+            .map_err(|_| token.error(line, AssemblyErrorKind::BadNumber(token.text.clone())))?;
+        if value > max as u32 {
+            return Err(token.error(line, AssemblyErrorKind::ValueOutOfRange(token.text.clone())));
+        }
+        Ok(value as u16)
+    }
+
+    // A label, or failing that a hex number; labels win, since a name such
+    // as `add` is also hex.
+    fn parse_value(line: usize, token: &Token, max: u16, labels: &HashMap<String, u8>)
+        -> Result<u16, AssemblyError> {
+        if let Some(address) = labels.get(&token.text) {
+            return Ok(*address as u16);
+        }
+        if token.text.chars().all(|c| c.is_ascii_hexdigit()) {
+            return parse_number(line, token, max);
+        }
+        if is_label_name(&token.text) {
+            Err(token.error(line, AssemblyErrorKind::UndefinedLabel(token.text.clone())))
+        } else {
+            Err(token.error(line, AssemblyErrorKind::BadNumber(token.text.clone())))
+        }
+    }
+
+    // the operands a mnemonic takes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Form {
+        // no operands
+        None,
+        // R[d], R[s], R[t]
+        Three,
+        // R[d], R[t]
+        Indirect,
+        // R[d], addr
+        Address,
+        // R[d]
+        Register,
+    }
+
+    const MNEMONICS: [(&str, OpCode, Form); 16] = [
+        ("hlt", OpCode::Halt, Form::None),
+        ("add", OpCode::Add, Form::Three),
+        ("sub", OpCode::Subtract, Form::Three),
+        ("and", OpCode::And, Form::Three),
+        ("xor", OpCode::Xor, Form::Three),
+        ("shl", OpCode::ShiftLeft, Form::Three),
+        ("shr", OpCode::ShiftRight, Form::Three),
+        ("lda", OpCode::LoadAddress, Form::Address),
+        ("ld", OpCode::Load, Form::Address),
+        ("st", OpCode::Store, Form::Address),
+        ("ldi", OpCode::LoadIndirect, Form::Indirect),
+        ("sti", OpCode::StoreIndirect, Form::Indirect),
+        ("brz", OpCode::BranchZero, Form::Address),
+        ("brp", OpCode::BranchPositive, Form::Address),
+        ("jr", OpCode::JumpRegister, Form::Register),
+        ("jl", OpCode::JumpAndLink, Form::Address),
+    ];
+
+    fn mnemonic(text: &str) -> Option<(OpCode, Form)> {
+        let text = text.to_ascii_lowercase();
+        let text = if text == "halt" { "hlt" } else { text.as_str() };
+        MNEMONICS.iter()
+            .find(|(name, ..)| *name == text)
+            .map(|(_, op, form)| (*op, *form))
+    }
+
+    // the instruction in the mnemonic form the assembler reads
+    pub fn mnemonic_text(instruction: &Instruction) -> String {
+        let (op, d, s, t, address) = instruction.get_values();
+        let (name, _, form) = MNEMONICS.iter()
+            .find(|(_, mnemonic_op, _)| mnemonic_op == op)
+            .expect("every op code has a mnemonic");
+        match form {
+            Form::None => name.to_string(),
+            Form::Register => format!("{} R[{:X}]", name, d),
+            Form::Three => format!("{} R[{:X}], R[{:X}], R[{:X}]", name, d, s, t),
+            Form::Indirect => format!("{} R[{:X}], R[{:X}]", name, d, t),
+            Form::Address => format!("{} R[{:X}], {:02X}", name, d, address),
+        }
+    }
+
+    fn operand_count(form: Form) -> usize {
+        match form {
+            Form::None => 0,
+            Form::Register => 1,
+            Form::Indirect | Form::Address => 2,
+            Form::Three => 3,
+        }
+    }
+
+    // a word to be assembled at an address once every label is known
+    #[derive(Debug, Clone)]
+    enum Item {
+        Instruction { operation: Token, op: OpCode, form: Form, operands: Vec<Token> },
+        Word(Token),
+    }
+
+    /// A word produced by the assembler with the source line it came from.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AssembledWord {
+        pub address: u8,
+        pub word: u16,
+        pub line: usize,
+        // the real instruction or .word, in mnemonic form
+        pub text: String,
+        // the pseudo-instruction or macro call, when the word came from one
+        pub expanded_from: Option<String>,
+    }
+
+    /// The register push and pop keep the stack pointer in. The stack grows
+    /// down and the pointer holds the address of the last word pushed, so
+    /// set it to one past the top of the stack before the first push.
+    pub const STACK_POINTER: u8 = 0xE;
+    /// The register push and pop overwrite with the constant 1.
+    pub const SCRATCH: u8 = 0xD;
+    // how deeply macros may call macros before expansion gives up
+    const MAX_MACRO_DEPTH: usize = 16;
+
+    const PSEUDO_INSTRUCTIONS: [(&str, usize); 6] =
+        [("mov", 2), ("jmp", 1), ("read", 1), ("write", 1), ("push", 1), ("pop", 1)];
+
+    // .macro name param, ... up to .endm; the parameters are replaced
+    // wherever they appear as a whole operand or mnemonic in the body
+    #[derive(Debug, Clone)]
+    struct Macro {
+        params: Vec<String>,
+        body: Vec<Statement>,
+        // expansions so far, to give the labels in each a name of its own
+        uses: usize,
+    }
+
+    fn register_token(register: u8, column: usize) -> Token {
+        Token { text: format!("R[{:X}]", register), column }
+    }
+
+    // A real instruction standing in for part of a pseudo-instruction. Its
+    // generated tokens point at the pseudo-instruction for diagnostics.
+    fn generated(statement: &Statement, mnemonic: &str, operands: Vec<Token>) -> Statement {
+        let column = statement.operation.as_ref().map_or(1, |operation| operation.column);
+        Statement {
+            line: statement.line,
+            label: None,
+            operation: Some(Token { text: mnemonic.into(), column }),
+            operands,
+            origin: statement.origin.clone().or_else(|| Some(statement.source_text())),
+        }
+    }
+
+    fn check_not_reserved(line: usize, token: &Token) -> Result<(), AssemblyError> {
+        match parse_register(line, token) {
+            Ok(register) if register == STACK_POINTER || register == SCRATCH =>
+                Err(token.error(line, AssemblyErrorKind::ReservedRegister(token.text.clone()))),
+            // a bad register is reported when the expansion is encoded
+            _ => Ok(()),
+        }
+    }
+
+    // The real statements for a pseudo-instruction, or None when the
+    // statement is not one.
+    fn expand_pseudo(statement: &Statement) -> Result<Option<Vec<Statement>>, AssemblyError> {
+        let operation = match &statement.operation {
+            Some(operation) => operation,
+            None => return Ok(None),
+        };
+        let name = operation.text.to_ascii_lowercase();
+        let expected = match PSEUDO_INSTRUCTIONS.iter().find(|(pseudo, _)| *pseudo == name) {
+            Some((_, expected)) => *expected,
+            None => return Ok(None),
+        };
+        let line = statement.line;
+        check_count(line, operation, &statement.operands, expected)?;
+        let operand = statement.operands[0].clone();
+        let column = operation.column;
+        let register = |register: u8| register_token(register, column);
+        let number = |text: &str| Token { text: text.into(), column };
+        let stack_pointer = || register(STACK_POINTER);
+        let expansion = match name.as_str() {
+            "mov" => vec![generated(statement, "add",
+                                    vec![operand, statement.operands[1].clone(), register(0)])],
+            "jmp" => vec![generated(statement, "brz", vec![register(0), operand])],
+            "read" => vec![generated(statement, "ld", vec![operand, number("FF")])],
+            "write" => vec![generated(statement, "st", vec![operand, number("FF")])],
+            "push" => {
+                check_not_reserved(line, &operand)?;
+                vec![
+                    generated(statement, "lda", vec![register(SCRATCH), number("01")]),
+                    generated(statement, "sub", vec![stack_pointer(), stack_pointer(), register(SCRATCH)]),
+                    generated(statement, "sti", vec![operand, stack_pointer()]),
+                ]
+            },
+            _ => {
+                check_not_reserved(line, &operand)?;
+                vec![
+                    generated(statement, "ldi", vec![operand, stack_pointer()]),
+                    generated(statement, "lda", vec![register(SCRATCH), number("01")]),
+                    generated(statement, "add", vec![stack_pointer(), stack_pointer(), register(SCRATCH)]),
+                ]
+            },
+        };
+        Ok(Some(expansion))
+    }
+
+    // Replace macro definitions, macro calls and pseudo-instructions with
+    // the real statements they stand for.
+    fn expand(statements: Vec<Statement>, errors: &mut Vec<AssemblyError>) -> Vec<Statement> {
+        let mut macros: HashMap<String, Macro> = HashMap::new();
+        let mut expanded = vec![];
+        let mut statements = statements.into_iter();
+        while let Some(statement) = statements.next() {
+            let line = statement.line;
+            let operation = match &statement.operation {
+                Some(operation) => operation.clone(),
+                None => {
+                    expanded.push(statement);
+                    continue;
+                },
+            };
+            match operation.text.to_ascii_lowercase().as_str() {
+                ".macro" => {
+                    let mut body = vec![];
+                    let mut closed = false;
+                    for inner in statements.by_ref() {
+                        if inner.operation.as_ref().is_some_and(|op| op.text.eq_ignore_ascii_case(".endm")) {
+                            closed = true;
+                            break;
+                        }
+                        body.push(inner);
+                    }
+                    let (name, params) = match statement.operands.split_first() {
+                        Some(split) => split,
+                        None => {
+                            errors.push(operation.error(line, AssemblyErrorKind::WrongOperandCount {
+                                mnemonic: operation.text.clone(), expected: 1, found: 0 }));
+                            continue;
+                        },
+                    };
+                    let taken = mnemonic(&name.text).is_some()
+                        || PSEUDO_INSTRUCTIONS.iter().any(|(pseudo, _)| name.text.eq_ignore_ascii_case(pseudo))
+                        || macros.contains_key(&name.text);
+                    if !is_label_name(&name.text) {
+                        errors.push(name.error(line, AssemblyErrorKind::BadLabel(name.text.clone())));
+                    } else if taken {
+                        errors.push(name.error(line, AssemblyErrorKind::DuplicateMacro(name.text.clone())));
+                    } else if !closed {
+                        errors.push(name.error(line, AssemblyErrorKind::UnterminatedMacro(name.text.clone())));
+                    } else {
+                        let params = params.iter().map(|param| param.text.clone()).collect();
+                        macros.insert(name.text.clone(), Macro { params, body, uses: 0 });
+                    }
+                    if statement.label.is_some() {
+                        expanded.push(Statement { operation: None, operands: vec![], ..statement });
+                    }
+                },
+                ".endm" => errors.push(operation.error(line, AssemblyErrorKind::StrayEndm)),
+                _ => expand_statement(statement, &mut macros, 0, &mut expanded, errors),
+            }
+        }
+        expanded
+    }
+
+    fn expand_statement(statement: Statement, macros: &mut HashMap<String, Macro>, depth: usize,
+                        expanded: &mut Vec<Statement>, errors: &mut Vec<AssemblyError>) {
+        match expand_pseudo(&statement) {
+            Ok(Some(mut statements)) => {
+                statements[0].label = statement.label;
+                expanded.extend(statements);
+                return;
+            },
+            Ok(None) => {},
+            Err(error) => {
+                errors.push(error);
+                return;
+            },
+        }
+        let operation = match &statement.operation {
+            Some(operation) if macros.contains_key(&operation.text) => operation.clone(),
+            _ => {
+                expanded.push(statement);
+                return;
+            },
+        };
+        let line = statement.line;
+        if depth >= MAX_MACRO_DEPTH {
+            errors.push(operation.error(line, AssemblyErrorKind::MacroTooDeep(operation.text.clone())));
+            return;
+        }
+        let definition = macros.get_mut(&operation.text).expect("macro is defined");
+        if statement.operands.len() != definition.params.len() {
+            errors.push(operation.error(line, AssemblyErrorKind::WrongOperandCount {
+                mnemonic: operation.text.clone(),
+                expected: definition.params.len(),
+                found: statement.operands.len() }));
+            return;
+        }
+        definition.uses += 1;
+        let prefix = format!("_{}{}_", operation.text, definition.uses);
+        let locals: Vec<String> = definition.body.iter()
+            .filter_map(|inner| inner.label.as_ref().map(|label| label.text.clone()))
+            .collect();
+        let origin = statement.origin.clone().or_else(|| Some(statement.source_text()));
+        // arguments keep their own columns; everything else points at the call
+        let substitute = |token: &Token| -> Token {
+            if let Some(index) = definition.params.iter().position(|param| *param == token.text) {
+                statement.operands[index].clone()
+            } else if locals.contains(&token.text) {
+                Token { text: format!("{}{}", prefix, token.text), column: operation.column }
+            } else {
+                Token { text: token.text.clone(), column: operation.column }
+            }
+        };
+        let body: Vec<Statement> = definition.body.iter()
+            .map(|inner| Statement {
+                line,
+                label: inner.label.as_ref().map(substitute),
+                operation: inner.operation.as_ref().map(substitute),
+                operands: inner.operands.iter().map(substitute).collect(),
+                origin: origin.clone(),
+            })
+            .collect();
+        if statement.label.is_some() {
+            expanded.push(Statement { operation: None, operands: vec![], ..statement });
+        }
+        for inner in body {
+            expand_statement(inner, macros, depth + 1, expanded, errors);
+        }
+    }
+
+    fn check_count(line: usize, operation: &Token, operands: &[Token], expected: usize)
+        -> Result<(), AssemblyError> {
+        if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(operation.error(line, AssemblyErrorKind::WrongOperandCount {
+                mnemonic: operation.text.clone(), expected, found: operands.len() }))
+        }
+    }
+
+    fn encode(line: usize, item: &Item, labels: &HashMap<String, u8>) -> Result<u16, AssemblyError> {
+        let (op, form, operands) = match item {
+            Item::Word(token) => return parse_value(line, token, 0xFFFF, labels),
+            Item::Instruction { op, form, operands, .. } => (*op, *form, operands),
+        };
+        let register = |index: usize| parse_register(line, &operands[index]);
+        let instruction = match form {
+            Form::None => Instruction::halt(),
+            Form::Register => Instruction::jump_register(register(0)?),
+            Form::Three => Instruction::rr(op, register(0)?, register(1)?, register(2)?),
+            Form::Indirect => Instruction::rr(op, register(0)?, 0, register(1)?),
+            Form::Address => {
+                let address = parse_value(line, &operands[1], 0xFF, labels)?;
+                Instruction::addr(op, register(0)?, address as u8)
+            },
+        };
+        Ok(instruction.encode())
+    }
+
+    // Assemble into words tagged with their source lines. Every error found
+    // is reported, in source order.
+    pub fn assemble_words(source: &str) -> Result<Vec<AssembledWord>, Vec<AssemblyError>> {
+        let mut errors = vec![];
+        let statements = source.lines().enumerate()
+            .map(|(index, text)| parse_statement(index + 1, text))
+            .collect();
+        let statements = expand(statements, &mut errors);
+        let mut labels: HashMap<String, u8> = HashMap::new();
+        let mut items: Vec<(u8, usize, Item, Option<String>)> = vec![];
+        // the next address, or None once the program has run past FF
+        let mut location = Some(ENTRY);
+        for statement in statements {
+            let line = statement.line;
+            if let Some(label) = &statement.label {
+                if !is_label_name(&label.text) {
+                    errors.push(label.error(line, AssemblyErrorKind::BadLabel(label.text.clone())));
+                } else if labels.contains_key(&label.text) {
+                    errors.push(label.error(line, AssemblyErrorKind::DuplicateLabel(label.text.clone())));
+                } else if let Some(address) = location {
+                    labels.insert(label.text.clone(), address);
+                }
+            }
+            let operation = match statement.operation {
+                Some(operation) => operation,
+                None => continue,
+            };
+            let mut words = vec![];
+            match operation.text.to_ascii_lowercase().as_str() {
+                ".org" => {
+                    match check_count(line, &operation, &statement.operands, 1)
+                        .and_then(|_| parse_number(line, &statement.operands[0], 0xFF)) {
+                        Ok(address) => location = Some(address as u8),
+                        Err(error) => errors.push(error),
+                    }
+                    continue;
+                },
+                ".word" => {
+                    words.extend(statement.operands.into_iter().map(Item::Word));
+                },
+                ".macro" | ".endm" => {
+                    // only reached from inside a macro body
+                    errors.push(operation.error(line, AssemblyErrorKind::UnknownDirective(operation.text.clone())));
+                    continue;
+                },
+                text if text.starts_with('.') => {
+                    errors.push(operation.error(line, AssemblyErrorKind::UnknownDirective(operation.text.clone())));
+                    continue;
+                },
+                _ => match mnemonic(&operation.text) {
+                    Some((op, form)) => {
+                        if let Err(error) = check_count(line, &operation, &statement.operands, operand_count(form)) {
+                            errors.push(error);
+                            continue;
+                        }
+                        words.push(Item::Instruction { operation, op, form, operands: statement.operands });
+                    },
+                    None => {
+                        errors.push(operation.error(line, AssemblyErrorKind::UnknownMnemonic(operation.text.clone())));
+                        continue;
+                    },
+                },
+            }
+            for item in words {
+                match location {
+                    Some(address) => {
+                        items.push((address, line, item, statement.origin.clone()));
+                        location = address.checked_add(1);
+                    },
+                    None => {
+                        let column = match &item {
+                            Item::Word(token) => token.column,
+                            Item::Instruction { operation, .. } => operation.column,
+                        };
+                        errors.push(AssemblyError { line, column, kind: AssemblyErrorKind::AddressOverflow });
+                        break;
+                    },
+                }
+            }
+        }
+        let mut used = [false; 256];
+        let mut assembled = vec![];
+        for (address, line, item, origin) in &items {
+            if used[*address as usize] {
+                let column = match item {
+                    Item::Word(token) => token.column,
+                    Item::Instruction { operation, .. } => operation.column,
+                };
+                errors.push(AssemblyError { line: *line, column, kind: AssemblyErrorKind::Overlap(*address) });
+                continue;
+            }
+            used[*address as usize] = true;
+            match encode(*line, item, &labels) {
+                Ok(word) => {
+                    let text = match item {
+                        Item::Word(_) => format!(".word {:04X}", word),
+                        Item::Instruction { .. } => mnemonic_text(&Instruction::decode(word)),
+                    };
+                    assembled.push(AssembledWord {
+                        address: *address, word, line: *line, text, expanded_from: origin.clone() });
+                },
+                Err(error) => errors.push(error),
+            }
+        }
+        if errors.is_empty() {
+            Ok(assembled)
+        } else {
+            errors.sort_by_key(|error| (error.line, error.column));
+            Err(errors)
+        }
+    }
+
+    // A listing in the `XX: YYYY   ...` layout that `ProgramReader` reads,
+    // giving each word as the real instruction it became. The first word
+    // of each expansion also shows the pseudo-instruction or macro call.
+    pub fn listing(words: &[AssembledWord]) -> String {
+        let mut text = String::new();
+        let mut previous: Option<&AssembledWord> = None;
+        for word in words {
+            if previous.is_some_and(|previous| word.address != previous.address.wrapping_add(1)) {
+                text.push('\n');
+            }
+            let starts_expansion = previous.is_none_or(|previous| {
+                previous.line != word.line || previous.expanded_from != word.expanded_from
+            });
+            let line = match &word.expanded_from {
+                Some(origin) if starts_expansion => format!(
+                    "{:02X}: {:04X}   {:<24}; {}", word.address, word.word, word.text, origin),
+                _ => format!("{:02X}: {:04X}   {}", word.address, word.word, word.text),
+            };
+            text.push_str(&line);
+            text.push('\n');
+            previous = Some(word);
+        }
+        text
+    }
+
+    pub fn assemble_listing(source: &str) -> Result<String, Vec<AssemblyError>> {
+        Ok(listing(&assemble_words(source)?))
+    }
+
+    // Assemble mnemonic source into words ready for `Machine::load`.
+    pub fn assemble(source: &str) -> Result<Vec<ProgramLoadWord>, Vec<AssemblyError>> {
+        let words = assemble_words(source)?;
+        Ok(words.iter().map(|word| ProgramLoadWord::new(word.address, word.word)).collect())
+    }
+}
+
+#[cfg(test)]
+mod assembler_tests {
+    use super::assembler::{assemble, assemble_listing, assemble_words, AssemblyError, AssemblyErrorKind};
+    use crate::program_reader::program_reader::ProgramReader;
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::machine::Machine;
+    use crate::test_programs;
+
+    const MULTIPLY: &str = "\
+; multiply two numbers from the input
+        .org 10
+        ld   R[A], FF        ; a = StdIn.readInt();
+        ld   R[B], FF
+        lda  R[C], 00
+        lda  R[1], 01
+loop:   brz  R[A], done      # while (a != 0) {
+        add  R[C], R[C], R[B]
+        sub  R[A], R[A], R[1]
+        brz  R0, loop
+done:   st   RC, FF
+        hlt
+";
+
+    fn words(source: &str) -> Vec<(u8, u16)> {
+        assemble(source).unwrap().iter()
+            .map(|load| (load.get_address(), load.get_content()))
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<AssemblyError> {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn assembles_multiply() {
+        assert_eq!(words(MULTIPLY), test_programs::MULTIPLY.to_vec());
+        let mut machine = Machine::new();
+        machine.load(assemble(MULTIPLY).unwrap());
+        let mut env = ExternalEnv::new(vec![6, 7]);
+        machine.run_with_limit(&mut env, 1000).unwrap();
+        assert_eq!(env.get_output(), &[42]);
+    }
+
+    #[test]
+    fn directives_and_forward_labels() {
+        let source = "
+            ld R1, value
+            jl RF, sub
+            hlt
+        sub: ldi R2, R1
+            jr RF
+            .org 30
+        value: .word FFFF, value, 7
+        ";
+        assert_eq!(words(source), vec![
+            (0x10, 0x8130), (0x11, 0xFF13), (0x12, 0x0000), (0x13, 0xA201), (0x14, 0xEF00),
+            (0x30, 0xFFFF), (0x31, 0x0030), (0x32, 0x0007)]);
+    }
+
+    #[test]
+    fn labels_win_over_hex() {
+        assert_eq!(words("brz R0, add\nadd: hlt"), vec![(0x10, 0xC011), (0x11, 0x0000)]);
+    }
+
+    #[test]
+    fn diagnostics_have_line_and_column() {
+        let found = errors("  mul R1, R2, R3\n  add R1, R[10], R2\nloop: brz R1, nowhere\n  ld R1");
+        let described: Vec<String> = found.iter().map(|error| error.to_string()).collect();
+        assert_eq!(described, vec![
+            "1:3: unknown mnemonic `mul`",
+            "2:11: register `R[10]` is out of range; registers are R[0] to R[F]",
+            "3:15: undefined label `nowhere`",
+            "4:3: `ld` takes 2 operands, found 1",
+        ]);
+    }
+
+    #[test]
+    fn more_diagnostics() {
+        let kinds = |source: &str| -> Vec<AssemblyErrorKind> {
+            errors(source).into_iter().map(|error| error.kind).collect()
+        };
+        assert_eq!(kinds("a: hlt\na: hlt"), vec![AssemblyErrorKind::DuplicateLabel("a".into())]);
+        assert_eq!(kinds("lda R1, 100"), vec![AssemblyErrorKind::ValueOutOfRange("100".into())]);
+        assert_eq!(kinds("add R1, X2, R3"), vec![AssemblyErrorKind::BadRegister("X2".into())]);
+        assert_eq!(kinds(".data 1"), vec![AssemblyErrorKind::UnknownDirective(".data".into())]);
+        assert_eq!(kinds(".org FF\nhlt\nhlt"), vec![AssemblyErrorKind::AddressOverflow]);
+        assert_eq!(kinds(".org 10\nhlt\n.org 10\nhlt"), vec![AssemblyErrorKind::Overlap(0x10)]);
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let source = "
+            read RA
+            mov RB, RA
+            jmp out
+        out: write RB
+            hlt
+        ";
+        assert_eq!(words(source), vec![
+            (0x10, 0x8AFF), (0x11, 0x1BA0), (0x12, 0xC013), (0x13, 0x9BFF), (0x14, 0x0000)]);
+    }
+
+    #[test]
+    fn push_and_pop_use_the_stack() {
+        // reverse two words read from the input
+        let source = "
+            lda RE, F0     ; stack below F0
+            read R1
+            push R1
+            read R1
+            push R1
+            pop R2
+            write R2
+            pop R2
+            write R2
+            hlt
+        ";
+        let mut machine = Machine::new();
+        machine.load(assemble(source).unwrap());
+        let mut env = ExternalEnv::new(vec![1, 2]);
+        machine.run_with_limit(&mut env, 1000).unwrap();
+        assert_eq!(env.get_output(), &[2, 1]);
+        assert_eq!(machine.get_regs()[0xE], 0xF0);
+        assert_eq!(machine.get_memory_word(0xEF), 1);
+        let kinds: Vec<_> = errors("push RE\npop R[D]").into_iter().map(|error| error.kind).collect();
+        assert_eq!(kinds, vec![AssemblyErrorKind::ReservedRegister("RE".into()),
+                               AssemblyErrorKind::ReservedRegister("R[D]".into())]);
+    }
+
+    #[test]
+    fn macros_with_parameters_and_local_labels() {
+        let source = "
+        .macro abs reg
+            brp reg, skip
+            sub reg, R0, reg
+        skip:
+        .endm
+        .macro double dst, src
+            add dst, src, src
+        .endm
+            read R1
+            abs R1
+            double R2, R1
+            abs R2
+            write R2
+            hlt
+        ";
+        let mut machine = Machine::new();
+        machine.load(assemble(source).unwrap());
+        let mut env = ExternalEnv::new(vec![0xFFFD]);
+        machine.run_with_limit(&mut env, 1000).unwrap();
+        assert_eq!(env.get_output(), &[6]);
+    }
+
+    #[test]
+    fn macro_diagnostics() {
+        let kinds = |source: &str| -> Vec<AssemblyErrorKind> {
+            errors(source).into_iter().map(|error| error.kind).collect()
+        };
+        assert_eq!(kinds(".macro loop\nloop\n.endm\nloop"),
+                   vec![AssemblyErrorKind::MacroTooDeep("loop".into())]);
+        assert_eq!(kinds(".macro add a\n.endm"), vec![AssemblyErrorKind::DuplicateMacro("add".into())]);
+        assert_eq!(kinds(".macro open\nhlt"), vec![AssemblyErrorKind::UnterminatedMacro("open".into())]);
+        assert_eq!(kinds(".endm"), vec![AssemblyErrorKind::StrayEndm]);
+        let found = errors(".macro two a, b\n.endm\n  two R1");
+        assert_eq!(found[0].to_string(), "3:3: `two` takes 2 operands, found 1");
+        // an argument error points at the argument in the call
+        let found = errors(".macro inc r\n  add r, r, R1\n.endm\n  inc R[10]");
+        assert_eq!(found[0].to_string(), "4:7: register `R[10]` is out of range; registers are R[0] to R[F]");
+    }
+
+    #[test]
+    fn listing_shows_expansions() {
+        let source = "
+        .macro twice r
+            add r, r, r
+        .endm
+            read RA
+            push RA
+            twice RA
+            hlt
+        ";
+        let listing = assemble_listing(source).unwrap();
+        assert_eq!(listing, "\
+10: 8AFF   ld R[A], FF             ; read RA
+11: 7D01   lda R[D], 01            ; push RA
+12: 2EED   sub R[E], R[E], R[D]
+13: BA0E   sti R[A], R[E]
+14: 1AAA   add R[A], R[A], R[A]    ; twice RA
+15: 0000   hlt
+");
+        let mut reader = ProgramReader::new();
+        reader.load_from_vec(listing.lines().map(String::from).collect());
+        let read_back: Vec<_> = reader.parse().iter().map(|load| load.get_content()).collect();
+        let assembled: Vec<_> = assemble_words(source).unwrap().iter().map(|word| word.word).collect();
+        assert_eq!(read_back, assembled);
+    }
+}
@@ -0,0 +1,246 @@
+pub mod batch {
+    use crate::block_engine::block_engine::BlockEngine;
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::machine::{Machine, ProgramLoadWord, RunLimits};
+    use crate::outcome::outcome::{MachineError, StepOutcome};
+    use crate::trace::trace::escape;
+    use std::fmt;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// A program to run, by the name its results are reported under. One
+    /// that could not be loaded still gets its rows, saying why.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BatchProgram {
+        pub name: String,
+        pub loads: Result<Vec<ProgramLoadWord>, String>,
+    }
+
+    impl BatchProgram {
+        pub fn new(name: impl Into<String>, loads: Vec<ProgramLoadWord>) -> Self {
+            Self { name: name.into(), loads: Ok(loads) }
+        }
+        pub fn unloadable(name: impl Into<String>, message: impl Into<String>) -> Self {
+            Self { name: name.into(), loads: Err(message.into()) }
+        }
+    }
+
+    /// Why a run stopped.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum HaltReason {
+        Halted,
+        StepLimit,
+        Trap(MachineError),
+        // the program never ran, for this reason
+        LoadError(String),
+    }
+
+    impl HaltReason {
+        // the column value in the results
+        pub fn kind(&self) -> &'static str {
+            match self {
+                HaltReason::Halted => "halted",
+                HaltReason::StepLimit => "step_limit",
+                HaltReason::Trap(_) => "trap",
+                HaltReason::LoadError(_) => "load_error",
+            }
+        }
+        // what went wrong, for a trap or a program that did not load
+        pub fn error(&self) -> Option<String> {
+            match self {
+                HaltReason::Trap(error) => Some(error.to_string()),
+                HaltReason::LoadError(message) => Some(message.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    impl fmt::Display for HaltReason {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.error() {
+                Some(error) => write!(f, "{}", error),
+                None => write!(f, "{}", self.kind()),
+            }
+        }
+    }
+
+    /// One program run on one input: which, by their places in the batch,
+    /// and what came of it.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BatchRun {
+        pub program: usize,
+        pub input: usize,
+        pub output: Vec<u16>,
+        pub steps: u64,
+        pub halt: HaltReason,
+    }
+
+    /// The runs of a batch, ordered by program and then by input.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BatchResults {
+        names: Vec<String>,
+        runs: Vec<BatchRun>,
+    }
+
+    fn hex_words(words: &[u16]) -> String {
+        words.iter().map(|word| format!("{:04X}", word)).collect::<Vec<_>>().join(" ")
+    }
+
+    fn csv_field(text: &str) -> String {
+        if text.contains([',', '"', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    }
+
+    impl BatchResults {
+        pub fn get_runs(&self) -> &[BatchRun] {
+            &self.runs
+        }
+        pub fn program_name(&self, run: &BatchRun) -> &str {
+            &self.names[run.program]
+        }
+
+        // A JSON array with an object for each run on a line of its own.
+        // Output words are numbers; error is null unless the run trapped or
+        // the program did not load.
+        pub fn to_json(&self) -> String {
+            let mut json = String::from("[\n");
+            for (index, run) in self.runs.iter().enumerate() {
+                let output: Vec<String> = run.output.iter().map(|word| word.to_string()).collect();
+                let error = run.halt.error()
+                    .map_or_else(|| "null".into(), |error| format!("\"{}\"", escape(&error)));
+                let _ = write!(json, r#"  {{"program":"{}","input":{},"output":[{}],"steps":{},"halt":"{}","error":{}}}"#,
+                               escape(self.program_name(run)), run.input, output.join(","), run.steps,
+                               run.halt.kind(), error);
+                json.push_str(if index + 1 < self.runs.len() { ",\n" } else { "\n" });
+            }
+            json.push_str("]\n");
+            json
+        }
+
+        // A header line, then a line for each run. Output words are hex,
+        // separated by spaces.
+        pub fn to_csv(&self) -> String {
+            let mut csv = String::from("program,input,output,steps,halt,error\n");
+            for run in &self.runs {
+                let error = run.halt.error().unwrap_or_default();
+                let _ = writeln!(csv, "{},{},{},{},{},{}", csv_field(self.program_name(run)), run.input,
+                                 hex_words(&run.output), run.steps, run.halt.kind(), csv_field(&error));
+            }
+            csv
+        }
+    }
+
+    fn run_one(program: &BatchProgram, input: &[u16], max_steps: u64) -> (Vec<u16>, u64, HaltReason) {
+        let loads = match &program.loads {
+            Ok(loads) => loads.clone(),
+            Err(message) => return (vec![], 0, HaltReason::LoadError(message.clone())),
+        };
+        let mut machine = Machine::new();
+        machine.load(loads);
+        let mut env = ExternalEnv::new(input.to_vec());
+        let limits = RunLimits { max_steps: Some(max_steps), ..RunLimits::default() };
+        let halt = match BlockEngine::new().run_with_limits(&mut machine, &mut env, &limits) {
+            Ok(StepOutcome::StepLimit { .. }) => HaltReason::StepLimit,
+            Ok(_) => HaltReason::Halted,
+            Err(error) => HaltReason::Trap(error),
+        };
+        (env.get_output().to_vec(), machine.get_step_count(), halt)
+    }
+
+    // Run every program on every input, each run stopped after max_steps,
+    // on up to threads threads; 0 takes as many as the machine has cores.
+    pub fn run_batch(programs: &[BatchProgram], inputs: &[Vec<u16>], max_steps: u64, threads: usize)
+        -> BatchResults {
+        let jobs: Vec<(usize, usize)> = (0..programs.len())
+            .flat_map(|program| (0..inputs.len()).map(move |input| (program, input)))
+            .collect();
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |count| count.get()),
+            threads => threads,
+        }.min(jobs.len()).max(1);
+        let next = AtomicUsize::new(0);
+        let finished = Mutex::new(Vec::with_capacity(jobs.len()));
+        let work = || {
+            let mut done = vec![];
+            while let Some(&(program, input)) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                let (output, steps, halt) = run_one(&programs[program], &inputs[input], max_steps);
+                done.push(BatchRun { program, input, output, steps, halt });
+            }
+            finished.lock().expect("a batch thread panicked").extend(done);
+        };
+        if threads == 1 {
+            work();
+        } else {
+            thread::scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(work);
+                }
+            });
+        }
+        let mut runs = finished.into_inner().expect("a batch thread panicked");
+        runs.sort_by_key(|run| (run.program, run.input));
+        BatchResults { names: programs.iter().map(|program| program.name.clone()).collect(), runs }
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::batch::{run_batch, BatchProgram, HaltReason};
+    use crate::outcome::outcome::MachineError;
+    use crate::test_programs::loads;
+
+    fn program(name: &str, words: &[(u8, u16)]) -> BatchProgram {
+        BatchProgram::new(name, loads(words))
+    }
+
+    fn programs() -> Vec<BatchProgram> {
+        vec![
+            // add two words
+            program("add.txt", &[(0x10, 0x8AFF), (0x11, 0x8BFF), (0x12, 0x1CAB), (0x13, 0x9CFF), (0x14, 0x0000)]),
+            // spin forever
+            program("spin, \"fast\".txt", &[(0x10, 0xC010)]),
+        ]
+    }
+
+    #[test]
+    fn runs_every_combination_in_order() {
+        let inputs = vec![vec![2, 3], vec![0x7FFF, 1], vec![5]];
+        let results = run_batch(&programs(), &inputs, 50, 4);
+        let summary: Vec<_> = results.get_runs().iter()
+            .map(|run| (run.program, run.input, run.output.clone(), run.steps, run.halt.clone()))
+            .collect();
+        assert_eq!(summary, vec![
+            (0, 0, vec![5], 5, HaltReason::Halted),
+            (0, 1, vec![0x8000], 5, HaltReason::Halted),
+            (0, 2, vec![], 1, HaltReason::Trap(MachineError::InputExhausted { pc: 0x11, instruction: 0x8BFF })),
+            (1, 0, vec![], 50, HaltReason::StepLimit),
+            (1, 1, vec![], 50, HaltReason::StepLimit),
+            (1, 2, vec![], 50, HaltReason::StepLimit),
+        ]);
+        assert_eq!(results, run_batch(&programs(), &inputs, 50, 1));
+    }
+
+    #[test]
+    fn writes_json_and_csv() {
+        let results = run_batch(&programs(), &[vec![5]], 10, 0);
+        assert_eq!(results.to_json(), concat!(
+            "[\n",
+            r#"  {"program":"add.txt","input":0,"output":[],"steps":1,"halt":"trap","error":"Input exhausted @ pc = 11x; instruction 8BFF"},"#, "\n",
+            r#"  {"program":"spin, \"fast\".txt","input":0,"output":[],"steps":10,"halt":"step_limit","error":null}"#, "\n",
+            "]\n"));
+        let results = run_batch(&programs()[..1], &[vec![2, 3], vec![4]], 10, 2);
+        assert_eq!(results.to_csv(), concat!(
+            "program,input,output,steps,halt,error\n",
+            "add.txt,0,0005,5,halted,\n",
+            "add.txt,1,,1,trap,Input exhausted @ pc = 11x; instruction 8BFF\n"));
+        let broken = run_batch(&[BatchProgram::unloadable("broken.asm", "broken.asm:1:1: unknown mnemonic `x`")], &[vec![]], 1, 1);
+        assert_eq!(broken.to_csv().lines().nth(1), Some("broken.asm,0,,0,load_error,broken.asm:1:1: unknown mnemonic `x`"));
+        let spin = run_batch(&programs()[1..], &[vec![]], 1, 1);
+        assert_eq!(spin.to_csv().lines().nth(1), Some("\"spin, \"\"fast\"\".txt\",0,,1,step_limit,"));
+    }
+}
@@ -0,0 +1,407 @@
+pub mod block_engine {
+    use crate::device::device::Device;
+    use crate::machine::{is_positive, operate, Instruction, Machine, OpCode, RunLimits, Semantics, DEADLINE_CHECK_INTERVAL};
+    use crate::outcome::outcome::{MachineError, StepOutcome};
+    use std::time::Instant;
+
+    // One instruction of a compiled block, its registers and address taken
+    // out of the word. Writes to R[0] are compiled away as Nop.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Op {
+        Nop,
+        Add(u8, u8, u8),
+        Subtract(u8, u8, u8),
+        And(u8, u8, u8),
+        Xor(u8, u8, u8),
+        ShiftLeft(u8, u8, u8),
+        ShiftRight(u8, u8, u8),
+        LoadAddress(u8, u16),
+        Load(u8, u16),
+        Store(u8, u16),
+        LoadIndirect(u8, u8),
+        StoreIndirect(u8, u8),
+        // the rest end a block
+        BranchZero(u8, u16),
+        BranchPositive(u8, u16),
+        // a branch on R[0]
+        Jump(u16),
+        JumpRegister(u8),
+        JumpAndLink(u8, u16),
+        Halt,
+    }
+
+    // The op for one instruction, or None when the interpreter has to run
+    // it: a load or store of a device, or a register the machine lacks.
+    fn compile_one(instruction: &Instruction, machine: &Machine, env: &dyn Device) -> Option<Op> {
+        let register_count = machine.get_config().register_count as u8;
+        if (register_count..16).any(|r|
+            instruction.reads_register(r) || instruction.register_written() == Some(r)) {
+            return None;
+        }
+        let (op, d, s, t, address) = instruction.get_values();
+        let target = address as u16 & machine.get_config().address_mask();
+        let device = machine.device_address(target, env).is_some();
+        let op = match op {
+            OpCode::Add | OpCode::Subtract | OpCode::And | OpCode::Xor |
+            OpCode::ShiftLeft | OpCode::ShiftRight | OpCode::LoadAddress if d == 0 => Op::Nop,
+            OpCode::Load | OpCode::Store if device => return None,
+            OpCode::Load if d == 0 => Op::Nop,
+            OpCode::Halt => Op::Halt,
+            OpCode::Add => Op::Add(d, s, t),
+            OpCode::Subtract => Op::Subtract(d, s, t),
+            OpCode::And => Op::And(d, s, t),
+            OpCode::Xor => Op::Xor(d, s, t),
+            OpCode::ShiftLeft => Op::ShiftLeft(d, s, t),
+            OpCode::ShiftRight => Op::ShiftRight(d, s, t),
+            OpCode::LoadAddress => Op::LoadAddress(d, address as u16),
+            OpCode::Load => Op::Load(d, target),
+            OpCode::Store => Op::Store(d, target),
+            OpCode::LoadIndirect => Op::LoadIndirect(d, t),
+            OpCode::StoreIndirect => Op::StoreIndirect(d, t),
+            OpCode::BranchZero if d == 0 => Op::Jump(target),
+            OpCode::BranchZero => Op::BranchZero(d, target),
+            OpCode::BranchPositive => Op::BranchPositive(d, target),
+            OpCode::JumpRegister => Op::JumpRegister(d),
+            OpCode::JumpAndLink => Op::JumpAndLink(d, target),
+        };
+        Some(op)
+    }
+
+    fn ends_block(op: &Op) -> bool {
+        matches!(op, Op::BranchZero(..) | Op::BranchPositive(..) | Op::Jump(_) |
+                     Op::JumpRegister(_) | Op::JumpAndLink(..) | Op::Halt)
+    }
+
+    // The ops of the words from start up to and including the first jump,
+    // branch or halt, stopping short of any word the interpreter has to run
+    // and of the i/o word, which traps when fetched. Empty when the word at
+    // start is one of those.
+    fn compile(machine: &Machine, env: &dyn Device, start: u16) -> Vec<Op> {
+        let config = machine.get_config();
+        let mut ops = vec![];
+        let mut pc = start;
+        while ops.len() < machine.memory.len() && pc != config.io_address {
+            let instruction = Instruction::decode(machine.memory[pc as usize]);
+            let op = match compile_one(&instruction, machine, env) {
+                Some(op) => op,
+                None => break,
+            };
+            ops.push(op);
+            if ends_block(&op) {
+                break;
+            }
+            pc = pc.wrapping_add(1) & config.address_mask();
+        }
+        ops
+    }
+
+    fn operate_on(machine: &mut Machine, op: OpCode, d: u8, s: u8, t: u8) {
+        let value = operate(op, machine.read_reg(s as usize), machine.read_reg(t as usize));
+        machine.write_reg(d as usize, value);
+    }
+
+    // How a run of a block's ops stopped.
+    struct BlockExit {
+        steps: u64,
+        // a store went over a compiled word
+        wrote_code: bool,
+    }
+
+    // Run at most budget of the ops, which start at the pc. An indirect load
+    // or store of a device stops the block before it, leaving the pc on it
+    // for the interpreter; a store over compiled code stops it just after.
+    fn run_block(ops: &[Op], code: &[bool], machine: &mut Machine, env: &dyn Device, budget: u64) -> BlockExit {
+        let mask = machine.get_config().address_mask();
+        let mut pc = machine.get_program_counter();
+        let mut steps = 0;
+        for op in ops.iter().take(budget.min(ops.len() as u64) as usize) {
+            let mut next = pc.wrapping_add(1) & mask;
+            let mut written = None;
+            match *op {
+                Op::Nop => {},
+                Op::Add(d, s, t) => operate_on(machine, OpCode::Add, d, s, t),
+                Op::Subtract(d, s, t) => operate_on(machine, OpCode::Subtract, d, s, t),
+                Op::And(d, s, t) => operate_on(machine, OpCode::And, d, s, t),
+                Op::Xor(d, s, t) => operate_on(machine, OpCode::Xor, d, s, t),
+                Op::ShiftLeft(d, s, t) => operate_on(machine, OpCode::ShiftLeft, d, s, t),
+                Op::ShiftRight(d, s, t) => operate_on(machine, OpCode::ShiftRight, d, s, t),
+                Op::LoadAddress(d, value) => machine.write_reg(d as usize, value),
+                Op::Load(d, address) => {
+                    let value = machine.read_memory(address);
+                    machine.write_reg(d as usize, value);
+                },
+                Op::Store(d, address) => {
+                    let value = machine.read_reg(d as usize);
+                    machine.write_memory(address, value);
+                    written = Some(address);
+                },
+                Op::LoadIndirect(d, t) | Op::StoreIndirect(d, t) => {
+                    let address = machine.read_reg(t as usize) & mask;
+                    if machine.device_address(address, env).is_some() {
+                        break;
+                    }
+                    if matches!(op, Op::StoreIndirect(..)) {
+                        let value = machine.read_reg(d as usize);
+                        machine.write_memory(address, value);
+                        written = Some(address);
+                    } else if d != 0 {
+                        let value = machine.read_memory(address);
+                        machine.write_reg(d as usize, value);
+                    }
+                },
+                Op::BranchZero(d, target) => if machine.read_reg(d as usize) == 0 {
+                    next = target;
+                },
+                Op::BranchPositive(d, target) => if is_positive(machine.read_reg(d as usize)) {
+                    next = target;
+                },
+                Op::Jump(target) => next = target,
+                Op::JumpRegister(d) => next = machine.read_reg(d as usize) & mask,
+                Op::JumpAndLink(d, target) => {
+                    if d != 0 {
+                        machine.write_reg(d as usize, next);
+                    }
+                    next = target;
+                },
+                Op::Halt => machine.reset_running(),
+            }
+            steps += 1;
+            pc = next;
+            if written.is_some_and(|address| code[address as usize]) {
+                machine.set_program_counter(pc);
+                return BlockExit { steps, wrote_code: true };
+            }
+        }
+        machine.set_program_counter(pc);
+        BlockExit { steps, wrote_code: false }
+    }
+
+    // The word an instruction run by the interpreter may write: where it
+    // stores, or where a word read from a device lands.
+    fn written_by(machine: &Machine, pc: u16) -> Option<u16> {
+        let mask = machine.get_config().address_mask();
+        let instruction = Instruction::decode(machine.memory[pc as usize]);
+        let (op, _, _, t, address) = instruction.get_values();
+        match op {
+            OpCode::Load | OpCode::Store => Some(address as u16 & mask),
+            OpCode::LoadIndirect | OpCode::StoreIndirect => Some(machine.read_reg(t as usize) & mask),
+            _ => None,
+        }
+    }
+
+    /// Runs a machine a basic block at a time. Each block is compiled the
+    /// first time the pc reaches it, and any store over a compiled word
+    /// throws all of them away. Words that load or store a device run on the
+    /// interpreter, as does everything when the machine keeps a journal, has
+    /// interrupts, a profile, coverage or a sanitizer, or loops are to be
+    /// detected. Blocks last for one call, since the machine may change
+    /// between calls.
+    #[derive(Debug, Default)]
+    pub struct BlockEngine {
+        // the ops of the block starting at each address, once compiled
+        blocks: Vec<Option<Vec<Op>>>,
+        // words that are part of some compiled block
+        code: Vec<bool>,
+        compiled_steps: u64,
+        interpreted_steps: u64,
+    }
+
+    impl BlockEngine {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        // steps run as compiled blocks and by the interpreter
+        pub fn get_compiled_steps(&self) -> u64 {
+            self.compiled_steps
+        }
+        pub fn get_interpreted_steps(&self) -> u64 {
+            self.interpreted_steps
+        }
+
+        fn flush(&mut self, memory_size: usize) {
+            self.blocks.clear();
+            self.blocks.resize(memory_size, None);
+            self.code.clear();
+            self.code.resize(memory_size, false);
+        }
+
+        // As Machine::run_with_limits.
+        pub fn run_with_limits(&mut self, machine: &mut Machine, env: &mut dyn Device, limits: &RunLimits)
+            -> Result<StepOutcome, MachineError> {
+            machine.set_program_counter(machine.get_config().entry_pc);
+            machine.set_running();
+            self.resume_with_limits(machine, env, limits)
+        }
+
+        // As Machine::resume_with_limits, with the limits counting only the
+        // steps taken by this call.
+        pub fn resume_with_limits(&mut self, machine: &mut Machine, env: &mut dyn Device, limits: &RunLimits)
+            -> Result<StepOutcome, MachineError> {
+            if limits.detect_loops || !machine.is_plain() {
+                let start = machine.get_step_count();
+                let outcome = machine.resume_with_limits(env, limits);
+                self.interpreted_steps += machine.get_step_count() - start;
+                return outcome;
+            }
+            let memory_size = machine.memory.len();
+            self.flush(memory_size);
+            let start = machine.get_step_count();
+            let mut next_check = 0;
+            while machine.get_running() {
+                let taken = machine.get_step_count() - start;
+                if limits.max_steps.is_some_and(|max_steps| taken >= max_steps) {
+                    return Ok(StepOutcome::StepLimit { steps: taken });
+                }
+                if let Some(deadline) = limits.deadline {
+                    if taken >= next_check {
+                        next_check = taken + DEADLINE_CHECK_INTERVAL;
+                        if Instant::now() >= deadline {
+                            return Ok(StepOutcome::DeadlineExceeded { steps: taken });
+                        }
+                    }
+                }
+                let budget = limits.max_steps.map_or(u64::MAX, |max_steps| max_steps - taken);
+                let pc = machine.get_program_counter();
+                if self.blocks[pc as usize].is_none() {
+                    let ops = compile(machine, &*env, pc);
+                    for i in 0..ops.len() {
+                        self.code[(pc as usize + i) % memory_size] = true;
+                    }
+                    self.blocks[pc as usize] = Some(ops);
+                }
+                let ops = self.blocks[pc as usize].as_deref().unwrap_or_default();
+                let exit = run_block(ops, &self.code, machine, &*env, budget);
+                machine.count_steps(exit.steps);
+                for _ in 0..exit.steps {
+                    env.tick();
+                }
+                self.compiled_steps += exit.steps;
+                if exit.wrote_code {
+                    self.flush(memory_size);
+                }
+                if exit.steps == 0 && machine.get_running() {
+                    // a word the interpreter has to run
+                    let written = written_by(machine, machine.get_program_counter());
+                    machine.fast_step(env)?;
+                    self.interpreted_steps += 1;
+                    if written.is_some_and(|address| self.code[address as usize]) {
+                        self.flush(memory_size);
+                    }
+                }
+            }
+            Ok(StepOutcome::Halted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod block_engine_tests {
+    use super::block_engine::BlockEngine;
+    use crate::bus::bus::{CycleCounter, DeviceBus};
+    use crate::device::device::IO_ADDRESS;
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::machine::{Machine, RunLimits};
+    use crate::outcome::outcome::{MachineError, StepOutcome};
+    use crate::test_programs::{loads, MULTIPLY};
+
+    fn machine(words: &[(u8, u16)]) -> Machine {
+        let mut machine = Machine::new();
+        machine.load(loads(words));
+        machine
+    }
+
+    fn limit(max_steps: u64) -> RunLimits {
+        RunLimits { max_steps: Some(max_steps), ..RunLimits::default() }
+    }
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        let mut interpreted = machine(&MULTIPLY);
+        let mut interpreted_env = ExternalEnv::new(vec![7, 6]);
+        interpreted.run_with_limit(&mut interpreted_env, 1000).unwrap();
+
+        let mut compiled = machine(&MULTIPLY);
+        let mut env = ExternalEnv::new(vec![7, 6]);
+        let mut engine = BlockEngine::new();
+        assert_eq!(engine.run_with_limits(&mut compiled, &mut env, &limit(1000)), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[42]);
+        assert_eq!(compiled.get_step_count(), interpreted.get_step_count());
+        assert_eq!(compiled.get_program_counter(), interpreted.get_program_counter());
+        assert_eq!(compiled.get_regs(), interpreted.get_regs());
+        assert_eq!(compiled.get_memory(), interpreted.get_memory());
+        // the two reads and the write of mem[FF] are left to the interpreter
+        assert_eq!(engine.get_interpreted_steps(), 3);
+        assert_eq!(engine.get_compiled_steps(), compiled.get_step_count() - 3);
+    }
+
+    #[test]
+    fn stops_partway_through_a_block() {
+        let mut machine = machine(&MULTIPLY);
+        let mut env = ExternalEnv::new(vec![2, 3]);
+        let mut engine = BlockEngine::new();
+        // the reads, then two of the words of the block at 12
+        assert_eq!(engine.run_with_limits(&mut machine, &mut env, &limit(4)), Ok(StepOutcome::StepLimit { steps: 4 }));
+        assert_eq!(machine.get_program_counter(), 0x14);
+        assert_eq!(machine.get_regs()[1], 1);
+        assert_eq!(engine.resume_with_limits(&mut machine, &mut env, &limit(100)), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[6]);
+    }
+
+    #[test]
+    fn stores_over_compiled_code() {
+        // the store at 12 writes over 13, the next word of its own block
+        let mut machine = machine(&[
+            (0x10, 0x7A05),   // R[A] <- 5
+            (0x11, 0x8B20),   // R[B] <- mem[20], 7A09
+            (0x12, 0x9B13),   // mem[13] <- R[B]
+            (0x13, 0x0000),   // halt, until stored over: R[A] <- 9
+            (0x14, 0x9AFF),   // write R[A]
+            (0x15, 0x0000),
+            (0x20, 0x7A09)]);
+        let mut env = ExternalEnv::new(vec![]);
+        let mut engine = BlockEngine::new();
+        assert_eq!(engine.run_with_limits(&mut machine, &mut env, &limit(100)), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[9]);
+        assert_eq!(machine.get_step_count(), 6);
+    }
+
+    #[test]
+    fn indirect_io_falls_back() {
+        let mut machine = machine(&[
+            (0x10, 0x71FF),   // R[1] <- FF
+            (0x11, 0xA201),   // R[2] <- mem[R[1]], a read
+            (0x12, 0x1222),   // R[2] <- R[2] + R[2]
+            (0x13, 0xB201),   // mem[R[1]] <- R[2], a write
+            (0x14, 0x0000)]);
+        let mut env = ExternalEnv::new(vec![21]);
+        let mut engine = BlockEngine::new();
+        assert_eq!(engine.run_with_limits(&mut machine, &mut env, &limit(100)), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[42]);
+        assert_eq!(engine.get_interpreted_steps(), 2);
+        assert_eq!(machine.get_step_count(), 5);
+    }
+
+    #[test]
+    fn devices_see_every_step() {
+        // a counter mapped at 80 reads the steps taken before the read
+        let mut machine = machine(&[
+            (0x10, 0x1000), (0x11, 0x1000), (0x12, 0x8A80), (0x13, 0x9AFF), (0x14, 0x0000)]);
+        let mut env = ExternalEnv::new(vec![]);
+        let mut bus = DeviceBus::new();
+        bus.bind(IO_ADDRESS, "stdio", &mut env);
+        bus.bind(0x80, "counter", CycleCounter::new());
+        let mut engine = BlockEngine::new();
+        assert_eq!(engine.run_with_limits(&mut machine, &mut bus, &limit(100)), Ok(StepOutcome::Halted));
+        drop(bus);
+        assert_eq!(env.get_output(), &[2]);
+    }
+
+    #[test]
+    fn traps_as_the_interpreter_does() {
+        let mut machine = machine(&[(0x10, 0x8AFF)]);
+        let mut env = ExternalEnv::new(vec![]);
+        assert_eq!(BlockEngine::new().run_with_limits(&mut machine, &mut env, &limit(10)),
+                   Err(MachineError::InputExhausted { pc: 0x10, instruction: 0x8AFF }));
+        assert!(!machine.get_running());
+    }
+}
@@ -0,0 +1,440 @@
+pub mod bus {
+    use crate::device::device::Device;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::io::Write;
+
+    struct Port<'a> {
+        name: String,
+        device: Box<dyn Device + 'a>,
+    }
+
+    /// Memory-mapped i/o over several addresses. Each bound address passes
+    /// its loads and stores, direct or indirect, to its own device; every
+    /// other address is plain memory. Bind the standard `ExternalEnv` at
+    /// `IO_ADDRESS` to keep mem[FF] working as usual.
+    #[derive(Default)]
+    pub struct DeviceBus<'a> {
+        ports: BTreeMap<u8, Port<'a>>,
+    }
+
+    impl<'a> DeviceBus<'a> {
+        pub fn new() -> Self {
+            Self { ports: BTreeMap::new() }
+        }
+        // Bind a device to address, returning the device it replaces. The
+        // bus decides which addresses are mapped; the device's own
+        // is_mapped is not consulted.
+        pub fn bind(&mut self, address: u8, name: &str, device: impl Device + 'a)
+            -> Option<Box<dyn Device + 'a>> {
+            let port = Port { name: name.into(), device: Box::new(device) };
+            self.ports.insert(address, port).map(|port| port.device)
+        }
+        pub fn unbind(&mut self, address: u8) -> Option<Box<dyn Device + 'a>> {
+            self.ports.remove(&address).map(|port| port.device)
+        }
+        // bound addresses and their names, in address order
+        pub fn ports(&self) -> Vec<(u8, &str)> {
+            self.ports.iter().map(|(address, port)| (*address, port.name.as_str())).collect()
+        }
+    }
+
+    impl Device for DeviceBus<'_> {
+        fn is_mapped(&self, address: u8) -> bool {
+            self.ports.contains_key(&address)
+        }
+        fn port_name(&self, address: u8) -> Option<&str> {
+            self.ports.get(&address).map(|port| port.name.as_str())
+        }
+        fn read(&mut self, address: u8) -> Option<u16> {
+            self.ports.get_mut(&address)?.device.read(address)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            if let Some(port) = self.ports.get_mut(&address) {
+                port.device.write(address, word)
+            }
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            self.ports.get_mut(&address).is_some_and(|port| port.device.unread(address, word))
+        }
+        fn unwrite(&mut self, address: u8) -> bool {
+            self.ports.get_mut(&address).is_some_and(|port| port.device.unwrite(address))
+        }
+        // The counts only grow during a run, so their sums still change on
+        // every read and write. One device without a position spoils it.
+        fn io_position(&self) -> Option<(usize, usize)> {
+            self.ports.values().try_fold((0, 0), |(read, written), port| {
+                let (more_read, more_written) = port.device.io_position()?;
+                Some((read + more_read, written + more_written))
+            })
+        }
+        fn tick(&mut self) {
+            for port in self.ports.values_mut() {
+                port.device.tick();
+            }
+        }
+        fn untick(&mut self) {
+            for port in self.ports.values_mut() {
+                port.device.untick();
+            }
+        }
+        // every device is asked, so that each request is acknowledged
+        fn interrupt_requested(&mut self) -> bool {
+            let mut requested = false;
+            for port in self.ports.values_mut() {
+                requested |= port.device.interrupt_requested();
+            }
+            requested
+        }
+    }
+
+    /// A read-only stream of words, such as a second input file. Stores to
+    /// it are dropped.
+    #[derive(Debug, Default)]
+    pub struct InputStream {
+        words: VecDeque<u16>,
+        taken: usize,
+    }
+
+    impl InputStream {
+        pub fn new(words: Vec<u16>) -> Self {
+            Self { words: words.into(), taken: 0 }
+        }
+        pub fn remaining(&self) -> usize {
+            self.words.len()
+        }
+    }
+
+    impl Device for InputStream {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            let word = self.words.pop_front()?;
+            self.taken += 1;
+            Some(word)
+        }
+        fn write(&mut self, address: u8, word: u16) {}
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            self.words.push_front(word);
+            self.taken = self.taken.saturating_sub(1);
+            true
+        }
+        fn unwrite(&mut self, address: u8) -> bool {
+            true
+        }
+        fn io_position(&self) -> Option<(usize, usize)> {
+            Some((self.taken, 0))
+        }
+    }
+
+    /// A character terminal: a store prints the low byte of the word as an
+    /// ASCII character and a load takes the next character of the typed
+    /// input.
+    pub struct CharConsole<W: Write> {
+        input: VecDeque<u16>,
+        writer: W,
+        taken: usize,
+        written: usize,
+    }
+
+    impl<W: Write> CharConsole<W> {
+        pub fn new(writer: W) -> Self {
+            Self::with_input("", writer)
+        }
+        pub fn with_input(input: &str, writer: W) -> Self {
+            Self {
+                input: input.bytes().map(u16::from).collect(),
+                writer,
+                taken: 0,
+                written: 0,
+            }
+        }
+        pub fn into_inner(self) -> W {
+            self.writer
+        }
+    }
+
+    impl<W: Write> Device for CharConsole<W> {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            let character = self.input.pop_front()?;
+            self.taken += 1;
+            Some(character)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            // a terminal that has gone away just loses the character
+            let _ = self.writer.write_all(&[word as u8]);
+            let _ = self.writer.flush();
+            self.written += 1;
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            self.input.push_front(word);
+            self.taken = self.taken.saturating_sub(1);
+            true
+        }
+        fn io_position(&self) -> Option<(usize, usize)> {
+            Some((self.taken, self.written))
+        }
+    }
+
+    /// Counts the machine's steps. A load gives the count so far, modulo
+    /// 2^16, and a store sets it.
+    #[derive(Debug, Default)]
+    pub struct CycleCounter {
+        cycles: u64,
+    }
+
+    impl CycleCounter {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn get_cycles(&self) -> u64 {
+            self.cycles
+        }
+    }
+
+    impl Device for CycleCounter {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            Some(self.cycles as u16)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            self.cycles = word.into();
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            true
+        }
+        fn tick(&mut self) {
+            self.cycles += 1;
+        }
+        fn untick(&mut self) {
+            self.cycles = self.cycles.saturating_sub(1);
+        }
+    }
+
+    /// Raises an interrupt every `period` steps; a period of zero stops it.
+    /// A load gives the steps left until the next interrupt and a store
+    /// sets a new period, counting from then.
+    #[derive(Debug, Default)]
+    pub struct IntervalTimer {
+        period: u16,
+        // steps since the period was set
+        elapsed: u64,
+        raised: bool,
+    }
+
+    impl IntervalTimer {
+        pub fn new(period: u16) -> Self {
+            Self { period, elapsed: 0, raised: false }
+        }
+    }
+
+    impl Device for IntervalTimer {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            if self.period == 0 {
+                return Some(0);
+            }
+            let period = u64::from(self.period);
+            Some((period - self.elapsed % period) as u16)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            *self = Self::new(word);
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            true
+        }
+        fn tick(&mut self) {
+            self.elapsed += 1;
+            if self.period != 0 && self.elapsed.is_multiple_of(u64::from(self.period)) {
+                self.raised = true;
+            }
+        }
+        // an interrupt raised by the tick being undone is lowered again
+        fn untick(&mut self) {
+            if self.period != 0 && self.elapsed != 0 && self.elapsed.is_multiple_of(u64::from(self.period)) {
+                self.raised = false;
+            }
+            self.elapsed = self.elapsed.saturating_sub(1);
+        }
+        fn interrupt_requested(&mut self) -> bool {
+            std::mem::take(&mut self.raised)
+        }
+    }
+
+    /// Pseudo-random words from a 16-bit xorshift generator, so that a run
+    /// can be repeated from the same seed. A store reseeds it.
+    #[derive(Debug)]
+    pub struct RandomSource {
+        state: u16,
+    }
+
+    impl RandomSource {
+        pub fn new(seed: u16) -> Self {
+            // xorshift never leaves zero
+            Self { state: if seed == 0 { 1 } else { seed } }
+        }
+    }
+
+    // Undo x ^= x << shift, or x >> shift when right. Each pass recovers
+    // another shift's worth of the original bits.
+    fn unxorshift(y: u16, shift: u32, right: bool) -> u16 {
+        let mut x = y;
+        for _ in 0..16 / shift {
+            x = y ^ if right { x >> shift } else { x << shift };
+        }
+        x
+    }
+
+    impl Device for RandomSource {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            let mut x = self.state;
+            x ^= x << 7;
+            x ^= x >> 9;
+            x ^= x << 8;
+            self.state = x;
+            Some(x)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            *self = Self::new(word);
+        }
+        // the word read is the state it left, so running the generator
+        // backwards from it gives the state before
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            let mut x = unxorshift(word, 8, false);
+            x = unxorshift(x, 9, true);
+            self.state = unxorshift(x, 7, false);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod bus_tests {
+    use super::bus::{CharConsole, CycleCounter, DeviceBus, InputStream, IntervalTimer, RandomSource};
+    use crate::device::device::{Device, IO_ADDRESS};
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::machine::Machine;
+    use crate::outcome::outcome::StepOutcome;
+
+    #[test]
+    fn bus_dispatches_by_address() {
+        let mut external = ExternalEnv::new(vec![1, 2]);
+        let mut bus = DeviceBus::new();
+        bus.bind(IO_ADDRESS, "stdio", &mut external);
+        bus.bind(0xFE, "input", InputStream::new(vec![7]));
+        assert!(bus.is_mapped(0xFE));
+        assert!(!bus.is_mapped(0xFD));
+        assert_eq!(bus.port_name(0xFE), Some("input"));
+        assert_eq!(bus.ports(), vec![(0xFE, "input"), (0xFF, "stdio")]);
+        assert_eq!(bus.read(0xFE), Some(7));
+        assert_eq!(bus.read(0xFE), None);
+        assert_eq!(bus.read(0xFF), Some(1));
+        assert_eq!(bus.read(0xFD), None);
+        bus.write(0xFF, 9);
+        assert_eq!(bus.io_position(), Some((2, 1)));
+        assert!(bus.unread(0xFE, 7));
+        assert_eq!(bus.io_position(), Some((1, 1)));
+        bus.bind(0xFD, "counter", CycleCounter::new());
+        assert_eq!(bus.io_position(), None);
+        assert!(bus.unbind(0xFD).is_some());
+        drop(bus);
+        assert_eq!(external.get_output(), &[9]);
+    }
+
+    #[test]
+    fn console_prints_characters() {
+        let mut console = CharConsole::with_input("hi", vec![]);
+        assert_eq!(console.read(0xFE), Some(u16::from(b'h')));
+        assert_eq!(console.read(0xFE), Some(u16::from(b'i')));
+        assert_eq!(console.read(0xFE), None);
+        console.write(0xFE, 0x4F);
+        console.write(0xFE, 0x014B);
+        assert_eq!(console.into_inner(), b"OK");
+    }
+
+    #[test]
+    fn counter_follows_ticks() {
+        let mut counter = CycleCounter::new();
+        counter.tick();
+        counter.tick();
+        assert_eq!(counter.read(0xFD), Some(2));
+        counter.untick();
+        assert_eq!(counter.get_cycles(), 1);
+        counter.write(0xFD, 0xFFFF);
+        counter.tick();
+        assert_eq!(counter.read(0xFD), Some(0));
+    }
+
+    #[test]
+    fn timer_raises_every_period() {
+        let mut timer = IntervalTimer::new(3);
+        let mut raised = vec![];
+        for _ in 0..7 {
+            timer.tick();
+            raised.push(timer.interrupt_requested());
+        }
+        assert_eq!(raised, vec![false, false, true, false, false, true, false]);
+        assert_eq!(timer.read(0xFB), Some(2));
+        timer.write(0xFB, 0);
+        timer.tick();
+        assert!(!timer.interrupt_requested());
+    }
+
+    #[test]
+    fn untick_lowers_the_interrupt() {
+        let mut timer = IntervalTimer::new(2);
+        timer.tick();
+        timer.tick();
+        timer.untick();
+        assert!(!timer.interrupt_requested());
+        assert_eq!(timer.read(0xFB), Some(1));
+        timer.tick();
+        assert!(timer.interrupt_requested());
+    }
+
+    #[test]
+    fn bus_acknowledges_every_request() {
+        let mut bus = DeviceBus::new();
+        bus.bind(0xFB, "timer", IntervalTimer::new(1));
+        bus.bind(0xFA, "timer", IntervalTimer::new(1));
+        bus.tick();
+        assert!(bus.interrupt_requested());
+        assert!(!bus.interrupt_requested());
+    }
+
+    #[test]
+    fn random_source_repeats_from_seed() {
+        let mut first = RandomSource::new(0x1234);
+        let mut second = RandomSource::new(0x1234);
+        let words: Vec<_> = (0..100).map(|_| first.read(0xFC).unwrap()).collect();
+        assert!(words.iter().all(|&word| word != 0));
+        assert!(words.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(second.read(0xFC), Some(words[0]));
+        second.write(0xFC, 0x1234);
+        assert_eq!(second.read(0xFC), Some(words[0]));
+    }
+
+    #[test]
+    fn random_source_takes_back_reads() {
+        let mut random = RandomSource::new(0xBEEF);
+        let words: Vec<_> = (0..50).map(|_| random.read(0xFC).unwrap()).collect();
+        for &word in words[10..].iter().rev() {
+            assert!(random.unread(0xFC, word));
+        }
+        assert_eq!(random.read(0xFC), Some(words[10]));
+    }
+
+    #[test]
+    fn bus_without_stdio() {
+        // 10: 8AFF R[A] <- mem[FF]; 11: 1AAA R[A] <- R[A] + R[A]; 12: 9AFF mem[FF] <- R[A]
+        // 13: 8BFE R[B] <- console; 14: 0000 halt
+        let mut machine = Machine::new();
+        for (address, word) in [0x8AFF, 0x1AAA, 0x9AFF, 0x8BFE, 0x0000].iter().enumerate() {
+            machine.set_memory_word(0x10 + address, *word);
+        }
+        machine.set_memory_word(0xFF, 0x0021);
+        let mut bus = DeviceBus::new();
+        bus.bind(0xFE, "console", CharConsole::with_input("!", vec![]));
+        assert_eq!(machine.current_instruction_pp_on(0x8AFF, &bus),
+                   "Op: Load - d:R[A] set from mem[FF]=33(0021)");
+        assert_eq!(machine.run_with_limit(&mut bus, 100), Ok(StepOutcome::Halted));
+        // mem[FF] is plain memory on this bus
+        assert_eq!(machine.get_memory_word(0xFF), 0x0042);
+        assert_eq!(machine.get_regs()[0xB], u16::from(b'!'));
+    }
+}
@@ -0,0 +1,245 @@
+pub mod cfg {
+    use crate::device::device::IO_ADDRESS;
+    use crate::disassembler::disassembler::{is_block_end, pseudocode, reachable, successors, ENTRY};
+    use crate::machine::{Instruction, OpCode, ProgramLoadWord};
+    use std::fmt::Write;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum EdgeKind {
+        // on to the next word
+        Next,
+        Taken,
+        NotTaken,
+        // a branch on R[0], which always goes
+        Jump,
+        // a jump and link, and the return to the word after it that the
+        // program is taken to make
+        Call,
+        Return,
+    }
+
+    impl EdgeKind {
+        fn label(&self) -> &'static str {
+            match self {
+                EdgeKind::Next => "",
+                EdgeKind::Taken => "taken",
+                EdgeKind::NotTaken => "not taken",
+                EdgeKind::Jump => "goto",
+                EdgeKind::Call => "call",
+                EdgeKind::Return => "return",
+            }
+        }
+    }
+
+    /// Words that run one after the other, entered only at the first and
+    /// left only from the last.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BasicBlock {
+        pub start: u8,
+        // the last word, inclusive
+        pub end: u8,
+        // the addresses control goes to after the last word
+        pub edges: Vec<(u8, EdgeKind)>,
+        // ends in a jump through a register, which goes somewhere unknown
+        pub indirect: bool,
+    }
+
+    /// The basic blocks of the code reachable from the entry at 10. Any
+    /// loaded word that cannot run is unreachable; it may be data. Control
+    /// that can leave the loaded words, into memory the program did not
+    /// load or onto the i/o word, falls off.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ControlFlowGraph {
+        memory: Vec<u16>,
+        blocks: Vec<BasicBlock>,
+        unreachable: Vec<u8>,
+        // (from, to) for each way out of the loaded words
+        falls_off: Vec<(u8, u8)>,
+    }
+
+    fn edge_kind(instruction: &Instruction, to: u8) -> EdgeKind {
+        let (op, d, _, _, target) = instruction.get_values();
+        let taken = to == target;
+        match op {
+            OpCode::BranchZero if d == 0 => EdgeKind::Jump,
+            OpCode::BranchZero | OpCode::BranchPositive if taken => EdgeKind::Taken,
+            OpCode::BranchZero | OpCode::BranchPositive => EdgeKind::NotTaken,
+            OpCode::JumpAndLink if taken => EdgeKind::Call,
+            OpCode::JumpAndLink => EdgeKind::Return,
+            _ => EdgeKind::Next,
+        }
+    }
+
+    impl ControlFlowGraph {
+        pub fn build(loads: &[ProgramLoadWord]) -> Self {
+            let mut memory = [0u16; 256];
+            let mut loaded = [false; 256];
+            for load in loads {
+                memory[load.get_address() as usize] = load.get_content();
+                loaded[load.get_address() as usize] = true;
+            }
+            let code = reachable(&memory, ENTRY);
+            let instruction = |address: u8| Instruction::decode(memory[address as usize]);
+
+            // a block starts at the entry, at every jump target, after every
+            // jump, and wherever the code starts or leaves the loaded words
+            let mut leader = [false; 256];
+            leader[ENTRY as usize] = true;
+            for address in (0..=255u8).filter(|&address| code[address as usize]) {
+                let next = address.wrapping_add(1);
+                let ends = is_block_end(&instruction(address));
+                for to in successors(address, &instruction(address)) {
+                    if to != next || ends {
+                        leader[to as usize] = true;
+                    }
+                }
+                if ends || loaded[next as usize] != loaded[address as usize] {
+                    leader[next as usize] = true;
+                }
+            }
+
+            let mut blocks = vec![];
+            let mut falls_off = vec![];
+            let mut address = 0usize;
+            while address < 256 {
+                if !code[address] {
+                    address += 1;
+                    continue;
+                }
+                let start = address as u8;
+                let mut end = start;
+                while !is_block_end(&instruction(end)) {
+                    let next = end.wrapping_add(1);
+                    if leader[next as usize] || !code[next as usize] {
+                        break;
+                    }
+                    end = next;
+                }
+                let last = instruction(end);
+                let edges: Vec<_> = successors(end, &last).into_iter()
+                    .map(|to| (to, edge_kind(&last, to)))
+                    .collect();
+                for (to, _) in &edges {
+                    if !loaded[*to as usize] || *to == IO_ADDRESS {
+                        falls_off.push((end, *to));
+                    }
+                }
+                let (op, ..) = last.get_values();
+                blocks.push(BasicBlock { start, end, edges, indirect: *op == OpCode::JumpRegister });
+                address = end as usize + 1;
+            }
+            let unreachable = (0..=255u8)
+                .filter(|&address| loaded[address as usize] && !code[address as usize])
+                .collect();
+            Self { memory: memory.to_vec(), blocks, unreachable, falls_off }
+        }
+
+        pub fn blocks(&self) -> &[BasicBlock] {
+            &self.blocks
+        }
+        pub fn block_at(&self, address: u8) -> Option<&BasicBlock> {
+            self.blocks.iter().find(|block| (block.start..=block.end).contains(&address))
+        }
+        pub fn unreachable(&self) -> &[u8] {
+            &self.unreachable
+        }
+        pub fn falls_off(&self) -> &[(u8, u8)] {
+            &self.falls_off
+        }
+
+        fn lines(&self, start: u8, end: u8) -> String {
+            (start..=end)
+                .map(|address| {
+                    let word = self.memory[address as usize];
+                    format!("{:02X}: {:04X}   {}\\l", address, word, pseudocode(&Instruction::decode(word)))
+                })
+                .collect()
+        }
+
+        // The graph in Graphviz DOT, one box per block listing its words.
+        // Unreachable words are dashed boxes, and the ways off the loaded
+        // words are red.
+        pub fn to_dot(&self) -> String {
+            let mut dot = String::new();
+            let _ = writeln!(dot, "digraph program {{");
+            let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+            for block in &self.blocks {
+                let off = self.falls_off.iter().any(|(_, to)| *to == block.start);
+                let _ = writeln!(dot, "    b{:02X} [label=\"{}\"{}];", block.start, self.lines(block.start, block.end),
+                                 if off { ", color=red" } else { "" });
+                for (to, kind) in &block.edges {
+                    let mut attributes = vec![];
+                    if !kind.label().is_empty() {
+                        attributes.push(format!("label=\"{}\"", kind.label()));
+                    }
+                    if self.falls_off.contains(&(block.end, *to)) {
+                        attributes.push("color=red".into());
+                    }
+                    if attributes.is_empty() {
+                        let _ = writeln!(dot, "    b{:02X} -> b{:02X};", block.start, to);
+                    } else {
+                        let _ = writeln!(dot, "    b{:02X} -> b{:02X} [{}];", block.start, to, attributes.join(", "));
+                    }
+                }
+                if block.indirect {
+                    let _ = writeln!(dot, "    unknown{:02X} [label=\"?\", shape=circle];", block.start);
+                    let _ = writeln!(dot, "    b{:02X} -> unknown{:02X} [style=dashed];", block.start, block.start);
+                }
+            }
+            if self.falls_off.iter().any(|(_, to)| *to == IO_ADDRESS) {
+                let _ = writeln!(dot, "    b{:02X} [label=\"{:02X}: i/o\", color=red];", IO_ADDRESS, IO_ADDRESS);
+            }
+            for address in &self.unreachable {
+                let _ = writeln!(dot, "    u{:02X} [label=\"{}\", style=dashed];", address, self.lines(*address, *address));
+            }
+            let _ = writeln!(dot, "}}");
+            dot
+        }
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use super::cfg::{ControlFlowGraph, EdgeKind};
+    use crate::test_programs::{loads, MULTIPLY};
+
+    #[test]
+    fn multiply_blocks() {
+        let graph = ControlFlowGraph::build(&loads(&MULTIPLY));
+        let spans: Vec<_> = graph.blocks().iter().map(|block| (block.start, block.end)).collect();
+        // the loop test at 14 is a block of its own, since the loop jumps back to it
+        assert_eq!(spans, vec![(0x10, 0x13), (0x14, 0x14), (0x15, 0x17), (0x18, 0x19)]);
+        assert_eq!(graph.blocks()[0].edges, vec![(0x14, EdgeKind::Next)]);
+        assert_eq!(graph.blocks()[1].edges, vec![(0x15, EdgeKind::NotTaken), (0x18, EdgeKind::Taken)]);
+        assert_eq!(graph.blocks()[2].edges, vec![(0x14, EdgeKind::Jump)]);
+        assert!(graph.blocks()[3].edges.is_empty());
+        assert!(graph.unreachable().is_empty());
+        assert!(graph.falls_off().is_empty());
+        assert_eq!(graph.block_at(0x16).unwrap().start, 0x15);
+        let dot = graph.to_dot();
+        assert!(dot.contains("    b10 -> b14;\n"));
+        assert!(dot.contains("    b14 -> b18 [label=\"taken\"];\n"));
+        assert!(dot.contains("    b15 -> b14 [label=\"goto\"];\n"));
+        assert!(dot.contains("    b14 [label=\"14: CA18   if (R[A] == 0) goto 18\\l\"];"));
+    }
+
+    #[test]
+    fn unreachable_and_falling_off() {
+        // 10: 7A05 R[A] <- 5; 11: FB20 call 20; 12: EA00 goto R[A]
+        // 20: 1AAA; no return, so it runs on into 21, which was not loaded
+        // 30: data never reached
+        let graph = ControlFlowGraph::build(&loads(&[
+            (0x10, 0x7A05), (0x11, 0xFB20), (0x12, 0xEA00), (0x20, 0x1AAA), (0x30, 0x1234)]));
+        assert_eq!(graph.unreachable(), &[0x30]);
+        assert_eq!(graph.falls_off(), &[(0x20, 0x21)]);
+        let call = graph.block_at(0x11).unwrap();
+        assert_eq!(call.edges, vec![(0x12, EdgeKind::Return), (0x20, EdgeKind::Call)]);
+        assert!(graph.block_at(0x12).unwrap().indirect);
+        // the word never loaded is zero, a halt, in a block of its own
+        assert_eq!(graph.block_at(0x21).map(|block| (block.start, block.end)), Some((0x21, 0x21)));
+        let dot = graph.to_dot();
+        assert!(dot.contains("    b20 -> b21 [color=red];\n"));
+        assert!(dot.contains("    b21 [label=\"21: 0000   halt\\l\", color=red];\n"));
+        assert!(dot.contains("u30 [label=\"30: 1234"));
+    }
+}
@@ -0,0 +1,156 @@
+pub mod config {
+    use crate::interrupt::interrupt::InterruptConfig;
+    use std::fmt;
+
+    /// The shape of a TOY-like machine. `MachineConfig::toy()`, also the
+    /// default, is the standard TOY: 256 words of 16 bits, 16 registers,
+    /// programs starting at 10 and i/o through mem[FF].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MachineConfig {
+        // memory holds 2^address_bits words
+        pub address_bits: u8,
+        pub word_bits: u8,
+        pub register_count: usize,
+        // where run starts
+        pub entry_pc: u16,
+        // the word that reads from and writes to the standard i/o device
+        pub io_address: u16,
+    }
+
+    impl MachineConfig {
+        pub const fn toy() -> Self {
+            Self {
+                address_bits: 8,
+                word_bits: 16,
+                register_count: 16,
+                entry_pc: 0x10,
+                io_address: 0xFF,
+            }
+        }
+        // TOY-8: 32 words of 8 bits, a single accumulator, programs
+        // starting at 00 and i/o through mem[1F]
+        pub const fn toy8() -> Self {
+            Self {
+                address_bits: 5,
+                word_bits: 8,
+                register_count: 1,
+                entry_pc: 0x00,
+                io_address: 0x1F,
+            }
+        }
+        pub fn memory_size(&self) -> usize {
+            1 << self.address_bits
+        }
+        // addresses wrap around the top of memory
+        pub fn address_mask(&self) -> u16 {
+            (self.memory_size() - 1) as u16
+        }
+        pub fn word_mask(&self) -> u16 {
+            (((1u32) << self.word_bits) - 1) as u16
+        }
+
+        // Check the config for a machine whose instructions are word_bits
+        // wide and name registers with register_bits bits.
+        pub fn validate(&self, word_bits: u8, register_bits: u8) -> Result<(), ConfigError> {
+            if self.word_bits != word_bits {
+                return Err(ConfigError::WordBits(self.word_bits));
+            }
+            if self.address_bits == 0 || self.address_bits > 16 {
+                return Err(ConfigError::AddressBits(self.address_bits));
+            }
+            if self.register_count == 0 || self.register_count > 1 << register_bits {
+                return Err(ConfigError::RegisterCount(self.register_count));
+            }
+            if self.entry_pc as usize >= self.memory_size() {
+                return Err(ConfigError::OutsideMemory(self.entry_pc));
+            }
+            if self.io_address as usize >= self.memory_size() {
+                return Err(ConfigError::OutsideMemory(self.io_address));
+            }
+            Ok(())
+        }
+
+        // Check interrupts can be installed in a machine of this shape.
+        pub fn validate_interrupts(&self, interrupts: &InterruptConfig) -> Result<(), ConfigError> {
+            let register = interrupts.saved_pc_register;
+            if register == 0 || register as usize >= self.register_count {
+                return Err(ConfigError::SavedPcRegister(register));
+            }
+            for address in [interrupts.vector, interrupts.control_address] {
+                if address as usize >= self.memory_size() {
+                    return Err(ConfigError::OutsideMemory(address));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Default for MachineConfig {
+        fn default() -> Self {
+            Self::toy()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ConfigError {
+        // the instruction set needs words of another width
+        WordBits(u8),
+        AddressBits(u8),
+        RegisterCount(usize),
+        // the entry pc or i/o address is past the end of memory
+        OutsideMemory(u16),
+        // a register that cannot hold the pc of an interrupted program
+        SavedPcRegister(u8),
+    }
+
+    impl fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ConfigError::WordBits(bits) => write!(
+                    f, "{} bit words are not supported by this instruction set", bits),
+                ConfigError::AddressBits(bits) => write!(f, "{} address bits is out of range", bits),
+                ConfigError::RegisterCount(count) => write!(
+                    f, "{} registers cannot be named by the instruction set", count),
+                ConfigError::OutsideMemory(address) => write!(
+                    f, "address {:02X} is outside memory", address),
+                ConfigError::SavedPcRegister(register) => write!(
+                    f, "R[{:X}] cannot hold the interrupted pc", register),
+            }
+        }
+    }
+
+    impl std::error::Error for ConfigError {}
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::config::{ConfigError, MachineConfig};
+
+    #[test]
+    fn standard_toy() {
+        let config = MachineConfig::default();
+        assert_eq!(config, MachineConfig::toy());
+        assert_eq!(config.memory_size(), 256);
+        assert_eq!(config.address_mask(), 0xFF);
+        assert_eq!(config.word_mask(), 0xFFFF);
+        assert_eq!(config.validate(16, 4), Ok(()));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let toy = MachineConfig::toy();
+        assert_eq!(MachineConfig { word_bits: 8, ..toy }.validate(16, 4), Err(ConfigError::WordBits(8)));
+        assert_eq!(MachineConfig { address_bits: 17, ..toy }.validate(16, 4),
+                   Err(ConfigError::AddressBits(17)));
+        assert_eq!(MachineConfig { register_count: 17, ..toy }.validate(16, 4),
+                   Err(ConfigError::RegisterCount(17)));
+        assert_eq!(MachineConfig { address_bits: 4, ..toy }.validate(16, 4),
+                   Err(ConfigError::OutsideMemory(0x10)));
+        assert_eq!(MachineConfig::toy8().validate(8, 0), Ok(()));
+        assert_eq!(MachineConfig::toy8().word_mask(), 0xFF);
+        let large = MachineConfig { address_bits: 12, io_address: 0xFFF, ..toy };
+        assert_eq!(large.validate(16, 4), Ok(()));
+        assert_eq!(large.memory_size(), 4096);
+    }
+}
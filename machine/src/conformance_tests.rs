@@ -0,0 +1,248 @@
+// Conformance of each opcode against the behaviour of the Princeton reference
+// simulator (TOY.java), at the edge values of 16-bit two's complement words.
+// Every case loads a single instruction at 10, runs one step and checks the
+// registers, memory and pc that result, once on the interpreter and once
+// on the block engine.
+
+use crate::block_engine::block_engine::BlockEngine;
+use crate::external_env::external_env::ExternalEnv;
+use crate::machine::{Machine, RunLimits};
+use crate::outcome::outcome::{MachineError, StepOutcome};
+
+struct Case {
+    name: &'static str,
+    word: u16,
+    regs: Vec<(usize, u16)>,
+    memory: Vec<(usize, u16)>,
+    expect_regs: Vec<(usize, u16)>,
+    expect_memory: Vec<(usize, u16)>,
+    expect_pc: u16,
+    expect_outcome: StepOutcome,
+}
+
+impl Case {
+    fn new(name: &'static str, word: u16) -> Self {
+        Self {
+            name,
+            word,
+            regs: vec![],
+            memory: vec![],
+            expect_regs: vec![],
+            expect_memory: vec![],
+            expect_pc: 0x11,
+            expect_outcome: StepOutcome::Running,
+        }
+    }
+    fn reg(mut self, index: usize, value: u16) -> Self {
+        self.regs.push((index, value));
+        self
+    }
+    fn mem(mut self, index: usize, value: u16) -> Self {
+        self.memory.push((index, value));
+        self
+    }
+    fn expect_reg(mut self, index: usize, value: u16) -> Self {
+        self.expect_regs.push((index, value));
+        self
+    }
+    fn expect_mem(mut self, index: usize, value: u16) -> Self {
+        self.expect_memory.push((index, value));
+        self
+    }
+    fn expect_pc(mut self, pc: u16) -> Self {
+        self.expect_pc = pc;
+        self
+    }
+    fn expect_halt(mut self) -> Self {
+        self.expect_outcome = StepOutcome::Halted;
+        self
+    }
+
+    fn check(&self) {
+        self.check_with("interpreter", |machine, env| machine.run_one_step(env, false));
+        self.check_with("block engine", |machine, env| {
+            let limits = RunLimits { max_steps: Some(1), ..RunLimits::default() };
+            match BlockEngine::new().resume_with_limits(machine, env, &limits) {
+                Ok(StepOutcome::StepLimit { .. }) => Ok(StepOutcome::Running),
+                outcome => outcome,
+            }
+        });
+    }
+
+    fn check_with(&self, engine: &str,
+                  step: fn(&mut Machine, &mut ExternalEnv) -> Result<StepOutcome, MachineError>) {
+        let name = format!("{} on the {}", self.name, engine);
+        let mut machine = Machine::new();
+        let mut env = ExternalEnv::new(vec![]);
+        for (index, value) in &self.regs {
+            machine.set_reg(*index, *value);
+        }
+        for (index, value) in &self.memory {
+            machine.set_memory_word(*index, *value);
+        }
+        machine.set_memory_word(0x10, self.word);
+        machine.set_program_counter(0x10);
+        machine.set_running();
+        let outcome = step(&mut machine, &mut env);
+        assert_eq!(outcome, Ok(self.expect_outcome), "{}: outcome", name);
+        let regs = machine.get_regs();
+        for (index, value) in &self.expect_regs {
+            assert_eq!(regs[*index], *value,
+                       "{}: R[{:01X}] is {:04X}, expected {:04X}", name, index, regs[*index], value);
+        }
+        for (index, value) in &self.expect_memory {
+            let word = machine.get_memory_word(*index);
+            assert_eq!(word, *value,
+                       "{}: mem[{:02X}] is {:04X}, expected {:04X}", name, index, word, value);
+        }
+        assert_eq!(machine.get_program_counter(), self.expect_pc, "{}: pc", name);
+        assert_eq!(regs[0], 0, "{}: R[0] must stay 0", name);
+    }
+}
+
+fn check_all(cases: Vec<Case>) {
+    for case in cases {
+        case.check();
+    }
+}
+
+#[test]
+fn halt() {
+    check_all(vec![
+        Case::new("halt", 0x0000).expect_halt(),
+        Case::new("halt ignores operand bits", 0x0ABC).expect_halt(),
+    ]);
+}
+
+#[test]
+fn add() {
+    check_all(vec![
+        Case::new("1 + 1", 0x1312).reg(1, 1).reg(2, 1).expect_reg(3, 2),
+        Case::new("7FFF + 1 overflows to 8000", 0x1312).reg(1, 0x7FFF).reg(2, 1).expect_reg(3, 0x8000),
+        Case::new("FFFF + 1 wraps to 0", 0x1312).reg(1, 0xFFFF).reg(2, 1).expect_reg(3, 0x0000),
+        Case::new("8000 + 8000 wraps to 0", 0x1312).reg(1, 0x8000).reg(2, 0x8000).expect_reg(3, 0x0000),
+        Case::new("-1 + -1", 0x1312).reg(1, 0xFFFF).reg(2, 0xFFFF).expect_reg(3, 0xFFFE),
+        Case::new("R[0] stays 0", 0x1012).reg(1, 5).reg(2, 6).expect_reg(0, 0),
+    ]);
+}
+
+#[test]
+fn subtract() {
+    check_all(vec![
+        Case::new("5 - 3", 0x2312).reg(1, 5).reg(2, 3).expect_reg(3, 2),
+        Case::new("0 - 1", 0x2312).reg(2, 1).expect_reg(3, 0xFFFF),
+        Case::new("8000 - 1 overflows to 7FFF", 0x2312).reg(1, 0x8000).reg(2, 1).expect_reg(3, 0x7FFF),
+        Case::new("7FFF - FFFF overflows to 8000", 0x2312).reg(1, 0x7FFF).reg(2, 0xFFFF).expect_reg(3, 0x8000),
+        Case::new("0 - 8000", 0x2312).reg(2, 0x8000).expect_reg(3, 0x8000),
+    ]);
+}
+
+#[test]
+fn and_xor() {
+    check_all(vec![
+        Case::new("and", 0x3312).reg(1, 0xF0F0).reg(2, 0xFF00).expect_reg(3, 0xF000),
+        Case::new("and with 0", 0x3310).reg(1, 0xFFFF).expect_reg(3, 0x0000),
+        Case::new("xor", 0x4312).reg(1, 0xF0F0).reg(2, 0xFF00).expect_reg(3, 0x0FF0),
+        Case::new("xor with self", 0x4311).reg(1, 0x1234).expect_reg(3, 0x0000),
+        Case::new("xor with FFFF is not", 0x4312).reg(1, 0x1234).reg(2, 0xFFFF).expect_reg(3, 0xEDCB),
+    ]);
+}
+
+#[test]
+fn shift_left() {
+    check_all(vec![
+        Case::new("1 << 0", 0x5312).reg(1, 1).expect_reg(3, 0x0001),
+        Case::new("1 << 15", 0x5312).reg(1, 1).reg(2, 15).expect_reg(3, 0x8000),
+        Case::new("1 << 16", 0x5312).reg(1, 1).reg(2, 16).expect_reg(3, 0x0000),
+        Case::new("FFFF << 1", 0x5312).reg(1, 0xFFFF).reg(2, 1).expect_reg(3, 0xFFFE),
+        Case::new("FFFF << 31", 0x5312).reg(1, 0xFFFF).reg(2, 31).expect_reg(3, 0x0000),
+        Case::new("count is taken mod 32", 0x5312).reg(1, 0x1234).reg(2, 32).expect_reg(3, 0x1234),
+        Case::new("count 33 shifts by 1", 0x5312).reg(1, 0x1234).reg(2, 33).expect_reg(3, 0x2468),
+        Case::new("negative count", 0x5312).reg(1, 0x1234).reg(2, 0xFFFF).expect_reg(3, 0x0000),
+    ]);
+}
+
+#[test]
+fn shift_right() {
+    check_all(vec![
+        Case::new("positive >> 1", 0x6312).reg(1, 0x7FFE).reg(2, 1).expect_reg(3, 0x3FFF),
+        Case::new("8000 >> 1 copies the sign", 0x6312).reg(1, 0x8000).reg(2, 1).expect_reg(3, 0xC000),
+        Case::new("8000 >> 15", 0x6312).reg(1, 0x8000).reg(2, 15).expect_reg(3, 0xFFFF),
+        Case::new("8000 >> 16", 0x6312).reg(1, 0x8000).reg(2, 16).expect_reg(3, 0xFFFF),
+        Case::new("7FFF >> 16", 0x6312).reg(1, 0x7FFF).reg(2, 16).expect_reg(3, 0x0000),
+        Case::new("-1 >> 31", 0x6312).reg(1, 0xFFFF).reg(2, 31).expect_reg(3, 0xFFFF),
+        Case::new("count is taken mod 32", 0x6312).reg(1, 0x8000).reg(2, 33).expect_reg(3, 0xC000),
+    ]);
+}
+
+#[test]
+fn load_address() {
+    check_all(vec![
+        Case::new("load 00", 0x7300).reg(3, 0x1234).expect_reg(3, 0x0000),
+        Case::new("load FF is not sign extended", 0x73FF).expect_reg(3, 0x00FF),
+        Case::new("load into R[0]", 0x70FF).expect_reg(0, 0x0000),
+    ]);
+}
+
+#[test]
+fn load_and_store() {
+    check_all(vec![
+        Case::new("load", 0x8330).mem(0x30, 0x8001).expect_reg(3, 0x8001),
+        Case::new("load own instruction", 0x8310).expect_reg(3, 0x8310),
+        Case::new("store", 0x9330).reg(3, 0xFFFF).expect_mem(0x30, 0xFFFF),
+        Case::new("store R[0]", 0x9030).mem(0x30, 0x1234).expect_mem(0x30, 0x0000),
+        Case::new("store over next instruction", 0x9311).reg(3, 0xABCD).expect_mem(0x11, 0xABCD),
+    ]);
+}
+
+#[test]
+fn load_and_store_indirect() {
+    check_all(vec![
+        Case::new("load indirect", 0xA302).reg(2, 0x30).mem(0x30, 0x4321).expect_reg(3, 0x4321),
+        Case::new("load indirect uses low 8 bits", 0xA302).reg(2, 0xFF30).mem(0x30, 0x4321)
+            .expect_reg(3, 0x4321),
+        Case::new("store indirect", 0xB302).reg(2, 0x30).reg(3, 0x8000).expect_mem(0x30, 0x8000),
+        Case::new("store indirect uses low 8 bits", 0xB302).reg(2, 0x0130).reg(3, 0x8000)
+            .expect_mem(0x30, 0x8000),
+    ]);
+}
+
+#[test]
+fn branch_zero() {
+    check_all(vec![
+        Case::new("taken on 0", 0xC330).expect_pc(0x30),
+        Case::new("not taken on 1", 0xC330).reg(3, 1),
+        Case::new("not taken on 8000", 0xC330).reg(3, 0x8000),
+        Case::new("R[0] is an unconditional goto", 0xC0FE).expect_pc(0xFE),
+    ]);
+}
+
+#[test]
+fn branch_positive() {
+    check_all(vec![
+        Case::new("taken on 1", 0xD330).reg(3, 1).expect_pc(0x30),
+        Case::new("taken on 7FFF", 0xD330).reg(3, 0x7FFF).expect_pc(0x30),
+        Case::new("not taken on 0", 0xD330),
+        Case::new("not taken on 8000", 0xD330).reg(3, 0x8000),
+        Case::new("not taken on FFFF", 0xD330).reg(3, 0xFFFF),
+    ]);
+}
+
+#[test]
+fn jump_register() {
+    check_all(vec![
+        Case::new("jump", 0xE300).reg(3, 0x30).expect_pc(0x30),
+        Case::new("jump uses low 8 bits", 0xE300).reg(3, 0x1234).expect_pc(0x34),
+        Case::new("jump ignores s and t", 0xE3FF).reg(3, 0x30).expect_pc(0x30),
+        Case::new("jump through R[0]", 0xE000).expect_pc(0x00),
+    ]);
+}
+
+#[test]
+fn jump_and_link() {
+    check_all(vec![
+        Case::new("link holds the return address", 0xF330).expect_reg(3, 0x0011).expect_pc(0x30),
+        Case::new("link into R[0] is discarded", 0xF030).expect_reg(0, 0).expect_pc(0x30),
+        Case::new("jump to self", 0xF310).expect_reg(3, 0x0011).expect_pc(0x10),
+    ]);
+}
@@ -0,0 +1,180 @@
+pub mod coverage {
+    use crate::disassembler::disassembler::pseudocode;
+    use crate::machine::{Instruction, OpCode, ProgramLoadWord};
+    use std::fmt::Write;
+
+    /// Which instructions ran and which way each branch went. Coverage
+    /// from several runs of one program, say over a suite of test inputs,
+    /// adds up with `merge`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Coverage {
+        executions: Vec<u64>,
+        // per address, how often a branch there was taken and not taken
+        branches: Vec<[u64; 2]>,
+    }
+
+    impl Coverage {
+        pub fn new(memory_size: usize) -> Self {
+            Self {
+                executions: vec![0; memory_size],
+                branches: vec![[0; 2]; memory_size],
+            }
+        }
+        // taken is None for anything but a branch
+        pub(crate) fn record(&mut self, pc: u16, taken: Option<bool>) {
+            self.executions[pc as usize] += 1;
+            if let Some(taken) = taken {
+                self.branches[pc as usize][if taken { 0 } else { 1 }] += 1;
+            }
+        }
+        pub fn get_executions(&self, address: u16) -> u64 {
+            self.executions[address as usize]
+        }
+        pub fn is_executed(&self, address: u16) -> bool {
+            self.get_executions(address) > 0
+        }
+        // how often the branch at address was (taken, not taken)
+        pub fn get_branch(&self, address: u16) -> (u64, u64) {
+            let [taken, not_taken] = self.branches[address as usize];
+            (taken, not_taken)
+        }
+        pub fn merge(&mut self, other: &Coverage) {
+            for (count, more) in self.executions.iter_mut().zip(&other.executions) {
+                *count += more;
+            }
+            for (counts, more) in self.branches.iter_mut().zip(&other.branches) {
+                counts[0] += more[0];
+                counts[1] += more[1];
+            }
+        }
+
+        // Of the program's words, how many ran, and of the directions its
+        // branches can go, how many were seen: (executed, words, directions
+        // seen, directions). Every loaded word counts, data included, since
+        // a program file does not say which words are data.
+        pub fn summary(&self, program: &[(usize, ProgramLoadWord)]) -> (usize, usize, usize, usize) {
+            let mut totals = (0, 0, 0, 0);
+            for (_, load) in program {
+                let address = load.get_address() as u16;
+                totals.1 += 1;
+                if self.is_executed(address) {
+                    totals.0 += 1;
+                }
+                if is_branch(load) {
+                    let (taken, not_taken) = self.get_branch(address);
+                    totals.2 += (taken > 0) as usize + (not_taken > 0) as usize;
+                    totals.3 += 2;
+                }
+            }
+            totals
+        }
+
+        // One line per program line as read by ProgramReader::parse_with_lines,
+        // unexecuted words marked #####, then the totals.
+        pub fn report(&self, program: &[(usize, ProgramLoadWord)]) -> String {
+            let mut text = String::new();
+            let _ = writeln!(text, " line     count  branches        word");
+            for (line, load) in program {
+                let address = load.get_address() as u16;
+                let executions = self.get_executions(address);
+                let count = if executions > 0 { executions.to_string() } else { "#####".into() };
+                let branches = if is_branch(load) {
+                    let (taken, not_taken) = self.get_branch(address);
+                    format!("{} taken {} not", taken, not_taken)
+                } else {
+                    String::new()
+                };
+                let _ = writeln!(text, "{:>5} {:>9}  {:<14}  {:02X}: {:04X}   {}", line, count, branches,
+                                 address, load.get_content(),
+                                 pseudocode(&Instruction::decode(load.get_content())));
+            }
+            let (executed, words, seen, directions) = self.summary(program);
+            let _ = writeln!(text, "instructions: {}/{} executed ({:.1}%)", executed, words, percent(executed, words));
+            let _ = writeln!(text, "branch directions: {}/{} covered ({:.1}%)", seen, directions,
+                             percent(seen, directions));
+            text
+        }
+
+        // The coverage as an lcov tracefile for the program file at path,
+        // with each branch as a block of two: taken, then not taken.
+        pub fn lcov(&self, path: &str, program: &[(usize, ProgramLoadWord)]) -> String {
+            let mut text = String::new();
+            let _ = writeln!(text, "TN:");
+            let _ = writeln!(text, "SF:{}", path);
+            for (line, load) in program.iter().filter(|(_, load)| is_branch(load)) {
+                let address = load.get_address() as u16;
+                let (taken, not_taken) = self.get_branch(address);
+                for (branch, count) in [taken, not_taken].iter().enumerate() {
+                    if self.is_executed(address) {
+                        let _ = writeln!(text, "BRDA:{},{},{},{}", line, address, branch, count);
+                    } else {
+                        let _ = writeln!(text, "BRDA:{},{},{},-", line, address, branch);
+                    }
+                }
+            }
+            let (executed, words, seen, directions) = self.summary(program);
+            let _ = writeln!(text, "BRF:{}", directions);
+            let _ = writeln!(text, "BRH:{}", seen);
+            for (line, load) in program {
+                let _ = writeln!(text, "DA:{},{}", line, self.get_executions(load.get_address() as u16));
+            }
+            let _ = writeln!(text, "LF:{}", words);
+            let _ = writeln!(text, "LH:{}", executed);
+            let _ = writeln!(text, "end_of_record");
+            text
+        }
+    }
+
+    // a branch on R[0] always goes the same way, so it is a jump here
+    fn is_branch(load: &ProgramLoadWord) -> bool {
+        let instruction = Instruction::decode(load.get_content());
+        let (op, d, ..) = instruction.get_values();
+        matches!(op, OpCode::BranchZero | OpCode::BranchPositive) && d != 0
+    }
+
+    fn percent(part: usize, whole: usize) -> f64 {
+        if whole == 0 { 100.0 } else { 100.0 * part as f64 / whole as f64 }
+    }
+}
+
+#[cfg(test)]
+mod coverage_tests {
+    use super::coverage::Coverage;
+    use crate::machine::ProgramLoadWord;
+
+    fn program() -> Vec<(usize, ProgramLoadWord)> {
+        vec![(3, ProgramLoadWord::new(0x10, 0xCA12)),
+             (4, ProgramLoadWord::new(0x11, 0x7101)),
+             (5, ProgramLoadWord::new(0x12, 0x0000))]
+    }
+
+    #[test]
+    fn merges_runs() {
+        let mut first = Coverage::new(256);
+        first.record(0x10, Some(true));
+        first.record(0x12, None);
+        let mut second = Coverage::new(256);
+        second.record(0x10, Some(false));
+        second.record(0x11, None);
+        second.record(0x12, None);
+        assert_eq!(first.summary(&program()), (2, 3, 1, 2));
+        first.merge(&second);
+        assert_eq!(first.get_branch(0x10), (1, 1));
+        assert_eq!(first.get_executions(0x12), 2);
+        assert_eq!(first.summary(&program()), (3, 3, 2, 2));
+    }
+
+    #[test]
+    fn lcov_records() {
+        let mut coverage = Coverage::new(256);
+        coverage.record(0x10, Some(true));
+        coverage.record(0x12, None);
+        let lcov = coverage.lcov("program.txt", &program());
+        assert_eq!(lcov, "TN:\nSF:program.txt\nBRDA:3,16,0,1\nBRDA:3,16,1,0\nBRF:2\nBRH:1\n\
+                          DA:3,1\nDA:4,0\nDA:5,1\nLF:3\nLH:2\nend_of_record\n");
+        let report = coverage.report(&program());
+        assert!(report.contains("    4     #####"));
+        assert!(report.contains("1 taken 0 not"));
+        assert!(report.ends_with("instructions: 2/3 executed (66.7%)\nbranch directions: 1/2 covered (50.0%)\n"));
+    }
+}
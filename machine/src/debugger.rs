@@ -0,0 +1,315 @@
+pub mod debugger {
+    use std::fmt;
+    use crate::machine::Instruction;
+
+    /// Comparisons treat words as 16-bit two's complement values.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Comparison {
+        Equal, NotEqual, Less, LessOrEqual, Greater, GreaterOrEqual,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Operand {
+        Register(u8),
+        Memory(u16),
+        Value(u16),
+    }
+
+    /// A test such as `R[A] == 0` or `mem[30] > R[1]`, evaluated against the
+    /// machine state before the instruction at a breakpoint executes.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Condition {
+        left: Operand,
+        comparison: Comparison,
+        right: Operand,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WatchTarget {
+        Register(u8),
+        // addresses wrap at the top of the machine's memory
+        Memory(u16),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Access {
+        Read,
+        Write,
+        ReadWrite,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Breakpoint {
+        pub id: usize,
+        pub address: u16,
+        pub condition: Option<Condition>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Watchpoint {
+        pub id: usize,
+        pub target: WatchTarget,
+        pub access: Access,
+    }
+
+    /// Why `Machine::run_until_event` or `Machine::debug_step` returned.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DebugEvent {
+        // one instruction executed and nothing was hit (debug_step only)
+        Stepped,
+        Halted,
+        // the machine stopped before executing the instruction at pc
+        Breakpoint { id: usize, pc: u16 },
+        // the instruction at pc touched the watched register or word;
+        // access is Read or Write, never ReadWrite
+        Watchpoint { id: usize, pc: u16, target: WatchTarget, access: Access, old: u16, new: u16 },
+    }
+
+    /// Breakpoints and watchpoints share one sequence of ids.
+    #[derive(Debug, Default)]
+    pub struct Debugger {
+        next_id: usize,
+        breakpoints: Vec<Breakpoint>,
+        watchpoints: Vec<Watchpoint>,
+    }
+
+    impl Operand {
+        fn value(&self, regs: &[u16; 16], memory: &[u16]) -> u16 {
+            match self {
+                Operand::Register(r) => regs[*r as usize],
+                Operand::Memory(address) => memory[wrap(*address, memory)],
+                Operand::Value(value) => *value,
+            }
+        }
+        // R[A], mem[3F] or a hex number such as 0 or FFFF
+        fn parse(text: &str) -> Result<Operand, String> {
+            let text = text.trim();
+            if let Some(target) = WatchTarget::parse_opt(text)? {
+                return Ok(match target {
+                    WatchTarget::Register(r) => Operand::Register(r),
+                    WatchTarget::Memory(address) => Operand::Memory(address),
+                });
+            }
+            u16::from_str_radix(text, 16)
+                .map(Operand::Value)
+                .map_err(|_| format!("expected R[x], mem[xx] or a hex word, found `{}`", text))
+        }
+    }
+
+    impl Condition {
+        pub fn new(left: Operand, comparison: Comparison, right: Operand) -> Self {
+            Self { left, comparison, right }
+        }
+        pub fn parse(text: &str) -> Result<Condition, String> {
+            // two character operators first so that `<=` is not read as `<`
+            let operators = [
+                ("==", Comparison::Equal), ("!=", Comparison::NotEqual),
+                ("<=", Comparison::LessOrEqual), (">=", Comparison::GreaterOrEqual),
+                ("<", Comparison::Less), (">", Comparison::Greater),
+            ];
+            for (symbol, comparison) in operators {
+                if let Some((left, right)) = text.split_once(symbol) {
+                    return Ok(Condition::new(Operand::parse(left)?, comparison, Operand::parse(right)?));
+                }
+            }
+            Err(format!("no comparison operator in `{}`", text))
+        }
+        pub fn holds(&self, regs: &[u16; 16], memory: &[u16]) -> bool {
+            let left = self.left.value(regs, memory) as i16;
+            let right = self.right.value(regs, memory) as i16;
+            match self.comparison {
+                Comparison::Equal => left == right,
+                Comparison::NotEqual => left != right,
+                Comparison::Less => left < right,
+                Comparison::LessOrEqual => left <= right,
+                Comparison::Greater => left > right,
+                Comparison::GreaterOrEqual => left >= right,
+            }
+        }
+    }
+
+    impl WatchTarget {
+        fn parse_opt(text: &str) -> Result<Option<WatchTarget>, String> {
+            let text = text.trim();
+            let inner = |prefix: &str| text.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(']'));
+            if let Some(digit) = inner("R[") {
+                return u8::from_str_radix(digit, 16).ok().filter(|r| *r < 16)
+                    .map(|r| Some(WatchTarget::Register(r)))
+                    .ok_or_else(|| format!("bad register `{}`", text));
+            }
+            if let Some(address) = inner("mem[") {
+                return u16::from_str_radix(address, 16)
+                    .map(|address| Some(WatchTarget::Memory(address)))
+                    .map_err(|_| format!("bad memory address `{}`", text));
+            }
+            Ok(None)
+        }
+        pub fn parse(text: &str) -> Result<WatchTarget, String> {
+            WatchTarget::parse_opt(text)?
+                .ok_or_else(|| format!("expected R[x] or mem[xx], found `{}`", text.trim()))
+        }
+        pub fn value(&self, regs: &[u16; 16], memory: &[u16]) -> u16 {
+            match self {
+                WatchTarget::Register(r) => regs[*r as usize],
+                WatchTarget::Memory(address) => memory[wrap(*address, memory)],
+            }
+        }
+    }
+
+    // the index of address in memory, which is a power of two words long
+    fn wrap(address: u16, memory: &[u16]) -> usize {
+        address as usize & (memory.len() - 1)
+    }
+
+    impl Access {
+        fn covers(&self, access: Access) -> bool {
+            *self == Access::ReadWrite || *self == access
+        }
+    }
+
+    impl Debugger {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        fn take_id(&mut self) -> usize {
+            self.next_id += 1;
+            self.next_id
+        }
+        pub fn add_breakpoint(&mut self, address: u16) -> usize {
+            let id = self.take_id();
+            self.breakpoints.push(Breakpoint { id, address, condition: None });
+            id
+        }
+        pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Condition) -> usize {
+            let id = self.take_id();
+            self.breakpoints.push(Breakpoint { id, address, condition: Some(condition) });
+            id
+        }
+        pub fn add_watchpoint(&mut self, target: WatchTarget, access: Access) -> usize {
+            let id = self.take_id();
+            self.watchpoints.push(Watchpoint { id, target, access });
+            id
+        }
+        // remove the breakpoint or watchpoint with this id
+        pub fn remove(&mut self, id: usize) -> bool {
+            let count = self.breakpoints.len() + self.watchpoints.len();
+            self.breakpoints.retain(|point| point.id != id);
+            self.watchpoints.retain(|point| point.id != id);
+            count != self.breakpoints.len() + self.watchpoints.len()
+        }
+        pub fn clear(&mut self) {
+            self.breakpoints.clear();
+            self.watchpoints.clear();
+        }
+        pub fn get_breakpoints(&self) -> &[Breakpoint] {
+            &self.breakpoints
+        }
+        pub fn get_watchpoints(&self) -> &[Watchpoint] {
+            &self.watchpoints
+        }
+        pub fn has_watchpoints(&self) -> bool {
+            !self.watchpoints.is_empty()
+        }
+
+        pub(crate) fn breakpoint_at(&self, pc: u16, regs: &[u16; 16], memory: &[u16]) -> Option<usize> {
+            self.breakpoints.iter()
+                .find(|point| point.address == pc && point.condition
+                    .is_none_or(|condition| condition.holds(regs, memory)))
+                .map(|point| point.id)
+        }
+
+        // The first watchpoint the instruction will touch, with the kind of
+        // access and the value before the instruction runs. A write is
+        // reported in preference to a read of the same target.
+        pub(crate) fn watch_hit(&self, instruction: &Instruction, regs: &[u16; 16], memory: &[u16])
+            -> Option<(usize, WatchTarget, Access, u16)> {
+            let mask = (memory.len() - 1) as u16;
+            for point in &self.watchpoints {
+                let (read, written) = match point.target {
+                    WatchTarget::Register(r) => (
+                        instruction.reads_register(r),
+                        r != 0 && instruction.register_written() == Some(r)),
+                    WatchTarget::Memory(address) => (
+                        instruction.memory_read_address(regs, mask) == Some(address & mask),
+                        instruction.memory_write_address(regs, mask) == Some(address & mask)),
+                };
+                let old = point.target.value(regs, memory);
+                if written && point.access.covers(Access::Write) {
+                    return Some((point.id, point.target, Access::Write, old));
+                }
+                if read && point.access.covers(Access::Read) {
+                    return Some((point.id, point.target, Access::Read, old));
+                }
+            }
+            None
+        }
+    }
+
+    impl fmt::Display for WatchTarget {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                WatchTarget::Register(r) => write!(f, "R[{:01X}]", r),
+                WatchTarget::Memory(address) => write!(f, "mem[{:02X}]", address),
+            }
+        }
+    }
+
+    impl fmt::Display for DebugEvent {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                DebugEvent::Stepped => write!(f, "Stepped"),
+                DebugEvent::Halted => write!(f, "Halted"),
+                DebugEvent::Breakpoint { id, pc } => write!(f, "Breakpoint {} @ pc = {:02X}x", id, pc),
+                DebugEvent::Watchpoint { id, pc, target, access, old, new } => write!(
+                    f, "Watchpoint {} @ pc = {:02X}x: {:?} of {} {:04X} -> {:04X}",
+                    id, pc, access, target, old, new),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::debugger::*;
+
+    #[test]
+    fn parse_conditions() {
+        let condition = Condition::parse("R[A] == 0").unwrap();
+        assert_eq!(condition, Condition::new(Operand::Register(0xA), Comparison::Equal, Operand::Value(0)));
+        let condition = Condition::parse("mem[3F]<=R[1]").unwrap();
+        assert_eq!(condition, Condition::new(Operand::Memory(0x3F), Comparison::LessOrEqual, Operand::Register(1)));
+        assert!(Condition::parse("R[G] == 0").is_err());
+        assert!(Condition::parse("R[A] = 0").is_err());
+        assert!(Condition::parse("R[A] == zz").is_err());
+    }
+
+    #[test]
+    fn conditions_compare_signed_words() {
+        let mut regs = [0u16; 16];
+        let memory = [0u16; 256];
+        regs[1] = 0xFFFF;
+        assert!(Condition::parse("R[1] < 0").unwrap().holds(&regs, &memory));
+        assert!(!Condition::parse("R[1] > 0").unwrap().holds(&regs, &memory));
+        assert!(Condition::parse("R[1] == FFFF").unwrap().holds(&regs, &memory));
+    }
+
+    #[test]
+    fn ids_are_shared_and_removable() {
+        let mut debugger = Debugger::new();
+        let first = debugger.add_breakpoint(0x14);
+        let second = debugger.add_watchpoint(WatchTarget::Register(0xC), Access::Write);
+        assert_ne!(first, second);
+        assert!(debugger.remove(first));
+        assert!(!debugger.remove(first));
+        assert!(debugger.get_breakpoints().is_empty());
+        assert_eq!(debugger.get_watchpoints().len(), 1);
+    }
+
+    #[test]
+    fn parse_watch_targets() {
+        assert_eq!(WatchTarget::parse("R[c]"), Ok(WatchTarget::Register(0xC)));
+        assert_eq!(WatchTarget::parse(" mem[FF] "), Ok(WatchTarget::Memory(0xFF)));
+        assert!(WatchTarget::parse("C").is_err());
+    }
+}
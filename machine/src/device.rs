@@ -0,0 +1,233 @@
+pub mod device {
+    use std::io::{BufRead, Write};
+
+    /// The address of standard input and output in TOY.
+    pub const IO_ADDRESS: u8 = 0xFF;
+
+    /// The machine's view of the outside world. A load from a mapped
+    /// address first copies `read` into that memory word, and a store to a
+    /// mapped address passes the stored word to `write`, just as TOY does
+    /// for mem[FF]. `ExternalEnv` is the standard implementation, and
+    /// `DeviceBus` maps several devices at once.
+    pub trait Device {
+        fn is_mapped(&self, address: u8) -> bool {
+            address == IO_ADDRESS
+        }
+        // a label for the pretty printer to show beside a mapped address
+        fn port_name(&self, address: u8) -> Option<&str> {
+            None
+        }
+        // the next word for a load from address, or None when there is none
+        fn read(&mut self, address: u8) -> Option<u16>;
+        fn write(&mut self, address: u8, word: u16);
+        // Undo the latest read or write at address so that the machine can
+        // step backwards; a device that cannot do this returns false.
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            false
+        }
+        fn unwrite(&mut self, address: u8) -> bool {
+            false
+        }
+        // The number of words read and written so far, for devices whose
+        // future reads depend on nothing else. Loop detection relies on it
+        // and is skipped when a device returns None.
+        fn io_position(&self) -> Option<(usize, usize)> {
+            None
+        }
+        // called after every step, and with untick when a step is undone,
+        // for devices that keep time
+        fn tick(&mut self) {}
+        fn untick(&mut self) {}
+        // Whether the device has raised an interrupt since it was last
+        // asked; asking acknowledges it. Only a machine with interrupts
+        // installed asks.
+        fn interrupt_requested(&mut self) -> bool {
+            false
+        }
+        // The input not yet read and the output written so far, for
+        // devices that keep them, so that a snapshot can carry them.
+        fn saved_io(&self) -> Option<(Vec<u16>, Vec<u16>)> {
+            None
+        }
+        // Put back i/o taken by saved_io; a device that cannot returns false.
+        fn restore_io(&mut self, input: &[u16], output: &[u16]) -> bool {
+            false
+        }
+    }
+
+    // lets a borrowed device be bound into a DeviceBus
+    impl<T: Device + ?Sized> Device for &mut T {
+        fn is_mapped(&self, address: u8) -> bool {
+            (**self).is_mapped(address)
+        }
+        fn port_name(&self, address: u8) -> Option<&str> {
+            (**self).port_name(address)
+        }
+        fn read(&mut self, address: u8) -> Option<u16> {
+            (**self).read(address)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            (**self).write(address, word)
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            (**self).unread(address, word)
+        }
+        fn unwrite(&mut self, address: u8) -> bool {
+            (**self).unwrite(address)
+        }
+        fn io_position(&self) -> Option<(usize, usize)> {
+            (**self).io_position()
+        }
+        fn tick(&mut self) {
+            (**self).tick()
+        }
+        fn untick(&mut self) {
+            (**self).untick()
+        }
+        fn interrupt_requested(&mut self) -> bool {
+            (**self).interrupt_requested()
+        }
+        fn saved_io(&self) -> Option<(Vec<u16>, Vec<u16>)> {
+            (**self).saved_io()
+        }
+        fn restore_io(&mut self, input: &[u16], output: &[u16]) -> bool {
+            (**self).restore_io(input, output)
+        }
+    }
+
+    /// Words in and out as text, one four digit hex word per line as
+    /// TOY.java reads and prints them; input words may also be separated by
+    /// spaces. Tokens that are not hex words are skipped.
+    pub struct StreamDevice<R: BufRead, W: Write> {
+        reader: R,
+        writer: W,
+        pending: Vec<u16>,
+    }
+
+    impl<R: BufRead, W: Write> StreamDevice<R, W> {
+        pub fn new(reader: R, writer: W) -> Self {
+            Self {
+                reader,
+                writer,
+                pending: vec![],
+            }
+        }
+        pub fn into_inner(self) -> (R, W) {
+            (self.reader, self.writer)
+        }
+    }
+
+    impl<R: BufRead, W: Write> Device for StreamDevice<R, W> {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            while self.pending.is_empty() {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).ok()? == 0 {
+                    return None;
+                }
+                // a token that is not a hex word is skipped with a warning
+                // rather than being taken for the end of input
+                let mut words = vec![];
+                for token in line.split_whitespace() {
+                    match u16::from_str_radix(token, 16) {
+                        Ok(word) => words.push(word),
+                        Err(_) => eprintln!("Skipping input {:?}: not a hex word", token),
+                    }
+                }
+                words.reverse();
+                self.pending = words;
+            }
+            self.pending.pop()
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            // a closed stdout is not the program's fault; the word is dropped
+            let _ = writeln!(self.writer, "{:04X}", word);
+            let _ = self.writer.flush();
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            self.pending.push(word);
+            true
+        }
+    }
+
+    /// A device built from two closures, for hosts such as the browser that
+    /// supply i/o through callbacks.
+    pub struct ClosureDevice {
+        read: Box<dyn FnMut(u8) -> Option<u16>>,
+        write: Box<dyn FnMut(u8, u16)>,
+    }
+
+    impl ClosureDevice {
+        pub fn new(read: impl FnMut(u8) -> Option<u16> + 'static,
+                   write: impl FnMut(u8, u16) + 'static) -> Self {
+            Self {
+                read: Box::new(read),
+                write: Box::new(write),
+            }
+        }
+    }
+
+    impl Device for ClosureDevice {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            (self.read)(address)
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            (self.write)(address, word)
+        }
+    }
+}
+
+#[cfg(test)]
+mod device_tests {
+    use super::device::{ClosureDevice, Device, StreamDevice};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[test]
+    fn stream_device_reads_hex_words() {
+        let input = Cursor::new("0002\n0003 000A\n\nFFFF\n");
+        let mut device = StreamDevice::new(input, vec![]);
+        assert!(device.is_mapped(0xFF));
+        assert!(!device.is_mapped(0xFE));
+        assert_eq!(device.read(0xFF), Some(0x0002));
+        assert_eq!(device.read(0xFF), Some(0x0003));
+        assert!(device.unread(0xFF, 0x0003));
+        assert_eq!(device.read(0xFF), Some(0x0003));
+        assert_eq!(device.read(0xFF), Some(0x000A));
+        assert_eq!(device.read(0xFF), Some(0xFFFF));
+        assert_eq!(device.read(0xFF), None);
+    }
+
+    #[test]
+    fn stream_device_skips_bad_tokens() {
+        let mut device = StreamDevice::new(Cursor::new("0001 zz 0002\n12345\n0003\n"), vec![]);
+        assert_eq!(device.read(0xFF), Some(0x0001));
+        assert_eq!(device.read(0xFF), Some(0x0002));
+        assert_eq!(device.read(0xFF), Some(0x0003));
+        assert_eq!(device.read(0xFF), None);
+    }
+
+    #[test]
+    fn stream_device_writes_hex_lines() {
+        let mut device = StreamDevice::new(Cursor::new(""), vec![]);
+        device.write(0xFF, 0x000C);
+        device.write(0xFF, 0xFFFF);
+        let (_, written) = device.into_inner();
+        assert_eq!(String::from_utf8(written).unwrap(), "000C\nFFFF\n");
+    }
+
+    #[test]
+    fn closure_device() {
+        let written = Rc::new(RefCell::new(vec![]));
+        let sink = written.clone();
+        let mut next = 0;
+        let mut device = ClosureDevice::new(
+            move |_| { next += 1; Some(next) },
+            move |address, word| sink.borrow_mut().push((address, word)));
+        assert_eq!(device.read(0xFF), Some(1));
+        assert_eq!(device.read(0xFF), Some(2));
+        device.write(0xFF, 7);
+        assert_eq!(*written.borrow(), vec![(0xFF, 7)]);
+        assert!(!device.unwrite(0xFF));
+    }
+}
@@ -0,0 +1,243 @@
+pub mod disassembler {
+    use crate::device::device::IO_ADDRESS;
+    use crate::machine::{Instruction, OpCode, ProgramLoadWord};
+    use std::fmt;
+
+    // where TOY starts running a program
+    pub const ENTRY: u8 = 0x10;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WordKind {
+        Code,
+        Data,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ListingLine {
+        pub address: u8,
+        pub word: u16,
+        pub kind: WordKind,
+        pub text: String,
+    }
+
+    /// A program listing in the `XX: YYYY   pseudocode` layout of
+    /// multiply.toy, which `ProgramReader` reads back as the same words.
+    /// A blank line follows each jump, branch and halt.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Listing {
+        lines: Vec<ListingLine>,
+    }
+
+    impl Listing {
+        pub fn lines(&self) -> &[ListingLine] {
+            &self.lines
+        }
+    }
+
+    impl fmt::Display for Listing {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let mut previous: Option<&ListingLine> = None;
+            for line in &self.lines {
+                if let Some(previous) = previous {
+                    let gap = line.address != previous.address.wrapping_add(1);
+                    if gap || ends_block(previous) || line.kind != previous.kind {
+                        writeln!(f)?;
+                    }
+                }
+                writeln!(f, "{:02X}: {:04X}   {}", line.address, line.word, line.text)?;
+                previous = Some(line);
+            }
+            Ok(())
+        }
+    }
+
+    fn ends_block(line: &ListingLine) -> bool {
+        line.kind == WordKind::Code && is_block_end(&Instruction::decode(line.word))
+    }
+
+    // whether control can leave the instruction other than to the next word
+    pub(crate) fn is_block_end(instruction: &Instruction) -> bool {
+        let (op, ..) = instruction.get_values();
+        matches!(op, OpCode::Halt | OpCode::BranchZero | OpCode::BranchPositive |
+            OpCode::JumpRegister | OpCode::JumpAndLink)
+    }
+
+    // The instruction as the pseudocode column of a TOY listing writes it.
+    pub fn pseudocode(instruction: &Instruction) -> String {
+        let (op, d, s, t, addr) = instruction.get_values();
+        let binary = |symbol: &str| format!("R[{:X}] <- R[{:X}] {} R[{:X}]", d, s, symbol, t);
+        match op {
+            OpCode::Halt => "halt".into(),
+            OpCode::Add => binary("+"),
+            OpCode::Subtract => binary("-"),
+            OpCode::And => binary("&"),
+            OpCode::Xor => binary("^"),
+            OpCode::ShiftLeft => binary("<<"),
+            OpCode::ShiftRight => binary(">>"),
+            OpCode::LoadAddress => format!("R[{:X}] <- {:04X}", d, addr),
+            OpCode::Load if addr == IO_ADDRESS => format!("read R[{:X}]", d),
+            OpCode::Load => format!("R[{:X}] <- mem[{:02X}]", d, addr),
+            OpCode::Store if addr == IO_ADDRESS => format!("write R[{:X}]", d),
+            OpCode::Store => format!("mem[{:02X}] <- R[{:X}]", addr, d),
+            OpCode::LoadIndirect => format!("R[{:X}] <- mem[R[{:X}]]", d, t),
+            OpCode::StoreIndirect => format!("mem[R[{:X}]] <- R[{:X}]", t, d),
+            // R[0] is always zero, so this is the unconditional jump
+            OpCode::BranchZero if d == 0 => format!("goto {:02X}", addr),
+            OpCode::BranchZero => format!("if (R[{:X}] == 0) goto {:02X}", d, addr),
+            OpCode::BranchPositive => format!("if (R[{:X}] > 0) goto {:02X}", d, addr),
+            OpCode::JumpRegister => format!("goto R[{:X}]", d),
+            OpCode::JumpAndLink => format!("R[{:X}] <- pc; goto {:02X}", d, addr),
+        }
+    }
+
+    // a word that is not code, shown as its signed value
+    fn data_text(word: u16) -> String {
+        format!("data {}", word as i16)
+    }
+
+    // Where control can go after the instruction at address. A jump through
+    // a register goes somewhere unknown, and the program is taken to return
+    // to the word after a jump and link.
+    pub(crate) fn successors(address: u8, instruction: &Instruction) -> Vec<u8> {
+        let next = address.wrapping_add(1);
+        let (op, d, _, _, addr) = instruction.get_values();
+        match op {
+            OpCode::Halt | OpCode::JumpRegister => vec![],
+            OpCode::BranchZero if d == 0 => vec![addr],
+            OpCode::BranchZero | OpCode::BranchPositive | OpCode::JumpAndLink => vec![next, addr],
+            _ => vec![next],
+        }
+    }
+
+    // Mark the words that can run when the program starts at entry. They
+    // are code; every other word is data. Execution never reaches mem[FF].
+    pub fn reachable(memory: &[u16; 256], entry: u8) -> [bool; 256] {
+        let mut seen = [false; 256];
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            if address == IO_ADDRESS || seen[address as usize] {
+                continue;
+            }
+            seen[address as usize] = true;
+            let instruction = Instruction::decode(memory[address as usize]);
+            pending.extend(successors(address, &instruction));
+        }
+        seen
+    }
+
+    // List the words loaded, plus any word the program can reach, such as
+    // the halt at an address never loaded.
+    pub fn disassemble(loads: &[ProgramLoadWord]) -> Listing {
+        let mut memory = [0u16; 256];
+        let mut loaded = [false; 256];
+        for load in loads {
+            memory[load.get_address() as usize] = load.get_content();
+            loaded[load.get_address() as usize] = true;
+        }
+        listing(&memory, &loaded)
+    }
+
+    // List a memory image; words that are zero and unreachable are left out.
+    pub fn disassemble_memory(memory: &[u16; 256]) -> Listing {
+        let mut loaded = [false; 256];
+        for (address, word) in memory.iter().enumerate() {
+            loaded[address] = *word != 0;
+        }
+        listing(memory, &loaded)
+    }
+
+    fn listing(memory: &[u16; 256], loaded: &[bool; 256]) -> Listing {
+        let code = reachable(memory, ENTRY);
+        let lines = (0..=255u8)
+            .filter(|&address| loaded[address as usize] || code[address as usize])
+            .map(|address| {
+                let word = memory[address as usize];
+                if code[address as usize] {
+                    let text = pseudocode(&Instruction::decode(word));
+                    ListingLine { address, word, kind: WordKind::Code, text }
+                } else {
+                    ListingLine { address, word, kind: WordKind::Data, text: data_text(word) }
+                }
+            })
+            .collect();
+        Listing { lines }
+    }
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use super::disassembler::{disassemble, disassemble_memory, pseudocode, WordKind};
+    use crate::machine::{Instruction, ProgramLoadWord};
+    use crate::program_reader::program_reader::ProgramReader;
+    use crate::test_programs::{loads, MULTIPLY};
+
+    #[test]
+    fn multiply_listing() {
+        let listing = disassemble(&loads(&MULTIPLY)).to_string();
+        assert_eq!(listing, "\
+10: 8AFF   read R[A]
+11: 8BFF   read R[B]
+12: 7C00   R[C] <- 0000
+13: 7101   R[1] <- 0001
+14: CA18   if (R[A] == 0) goto 18
+
+15: 1CCB   R[C] <- R[C] + R[B]
+16: 2AA1   R[A] <- R[A] - R[1]
+17: C014   goto 14
+
+18: 9CFF   write R[C]
+19: 0000   halt
+");
+    }
+
+    #[test]
+    fn listing_reads_back_unchanged() {
+        let mut program = loads(&MULTIPLY);
+        program.push(ProgramLoadWord::new(0x30, 0xFFFF));
+        let listing = disassemble(&program);
+        let mut reader = ProgramReader::new();
+        reader.load_from_vec(listing.to_string().lines().map(String::from).collect());
+        let read_back: Vec<_> = reader.parse().iter()
+            .map(|load| (load.get_address(), load.get_content()))
+            .collect();
+        let expected: Vec<_> = program.iter()
+            .map(|load| (load.get_address(), load.get_content()))
+            .collect();
+        assert_eq!(read_back, expected);
+    }
+
+    #[test]
+    fn unreachable_words_are_data() {
+        // 10: 8A20 R[A] <- mem[20]; 11: 9AFF write; 12: 0000 halt; 20: data
+        let mut memory = [0u16; 256];
+        memory[0x10] = 0x8A20;
+        memory[0x11] = 0x9AFF;
+        memory[0x13] = 0x1234;
+        memory[0x20] = 0xFFFB;
+        let listing = disassemble_memory(&memory);
+        let kinds: Vec<_> = listing.lines().iter().map(|line| (line.address, line.kind)).collect();
+        assert_eq!(kinds, vec![(0x10, WordKind::Code), (0x11, WordKind::Code),
+                               (0x12, WordKind::Code), (0x13, WordKind::Data),
+                               (0x20, WordKind::Data)]);
+        assert_eq!(listing.lines()[4].text, "data -5");
+    }
+
+    #[test]
+    fn subroutine_calls_are_followed() {
+        // 10: FF20 call 20; 11: 0000 halt; 20: 1110 R[1] <- R[1] + R[0]; 21: EF00 return
+        let listing = disassemble(&loads(&[(0x10, 0xFF20), (0x11, 0x0000), (0x20, 0x1110), (0x21, 0xEF00)]));
+        assert!(listing.lines().iter().all(|line| line.kind == WordKind::Code));
+        assert_eq!(listing.lines()[0].text, "R[F] <- pc; goto 20");
+        assert_eq!(listing.lines()[3].text, "goto R[F]");
+    }
+
+    #[test]
+    fn pseudocode_for_each_format() {
+        assert_eq!(pseudocode(&Instruction::decode(0x5123)), "R[1] <- R[2] << R[3]");
+        assert_eq!(pseudocode(&Instruction::decode(0x8A30)), "R[A] <- mem[30]");
+        assert_eq!(pseudocode(&Instruction::decode(0x9A30)), "mem[30] <- R[A]");
+        assert_eq!(pseudocode(&Instruction::decode(0xA12F)), "R[1] <- mem[R[F]]");
+        assert_eq!(pseudocode(&Instruction::decode(0xB12F)), "mem[R[F]] <- R[1]");
+        assert_eq!(pseudocode(&Instruction::decode(0xD320)), "if (R[3] > 0) goto 20");
+    }
+}
@@ -1,13 +1,19 @@
 pub mod external_env {
+    use crate::device::device::Device;
+
     #[derive(Debug)]
     pub struct ExternalEnv {
         input: Vec<u16>,
         output: Vec<u16>,
+        words_read: usize,
+        // input pushed since the machine last asked, which raises an interrupt
+        input_arrived: bool,
         pub input_for_dump: String,
         pub output_for_dump: String
     }
 
     impl ExternalEnv {
+        #[allow(clippy::len_zero, clippy::useless_conversion)]
         pub fn new(input: Vec<u16>) -> Self {
             let output = vec![];
             let mut str = String::from("");
@@ -24,24 +30,45 @@ pub mod external_env {
             Self {
                 input,
                 output,
+                words_read: 0,
+                input_arrived: false,
                 input_for_dump: String::from(str),
                 output_for_dump: String::from(""),
             }
         }
+        // rebuild an environment part way through a run
+        pub fn with_output(input: Vec<u16>, output: Vec<u16>) -> Self {
+            let mut env = Self::new(input);
+            for word in output {
+                env.put_word(word);
+            }
+            env
+        }
+        pub fn get_input(&self) -> &[u16] {
+            &self.input
+        }
+        pub fn get_output(&self) -> &[u16] {
+            &self.output
+        }
         pub fn push_to_input(&mut self, value: u16) {
             self.input.push(value);
+            self.input_arrived = true;
         }
 
+        #[allow(clippy::len_zero)]
         pub(crate) fn is_end_of_input(&self) -> bool{
             self.input.len() == 0
         }
+        #[allow(clippy::len_zero)]
         pub(crate) fn get_next_word(&mut self) -> Option<u16> {
             if self.input.len() == 0 {
                 return None
             }
             let value = self.input.remove(0);
+            self.words_read += 1;
             Option::from(value)
         }
+        #[allow(clippy::len_zero)]
         pub(crate) fn put_word(&mut self, word: u16) {
             if self.output_for_dump.len() == 0 {
                 self.output_for_dump = format!("Output: {}({:04X}x)", word, word)
@@ -50,10 +77,29 @@ pub mod external_env {
             }
             self.output.push(word)
         }
+        // push a word taken by get_next_word back onto the front of the input
+        pub(crate) fn unget_word(&mut self, word: u16) {
+            self.input.insert(0, word);
+            self.words_read = self.words_read.saturating_sub(1);
+        }
+        // remove the last word written by put_word
+        pub(crate) fn unput_word(&mut self) -> Option<u16> {
+            let word = self.output.pop()?;
+            let string_vec: Vec<String> = self.output.iter()
+                .map(|value| format!("{}({:04X}x)", value, value))
+                .collect();
+            self.output_for_dump = if string_vec.is_empty() {
+                String::from("")
+            } else {
+                format!("Output: {}", string_vec.join(", "))
+            };
+            Some(word)
+        }
         pub(crate) fn peek_at_last_output(&self) -> u16 {
             let last = self.output.len() - 1;
             self.output[last]
         }
+        #[allow(clippy::len_zero)]
         pub(crate) fn dump(&self){
             println!("External environment for i/o");
             println!("  {}", self.input_for_dump);
@@ -63,6 +109,7 @@ pub mod external_env {
                 println!("  {}", self.output_for_dump);
             }
         }
+        #[allow(clippy::len_zero, clippy::useless_conversion)]
         pub fn input_for_display(&self) -> String {
             if self.input.len() == 0{
                 "(No input)".into()
@@ -73,6 +120,7 @@ pub mod external_env {
                 string_vec.join(", ").into()
             }
         }
+        #[allow(clippy::len_zero, clippy::useless_conversion)]
         pub fn output_for_display(&self) -> String {
             if self.output.len() == 0{
                 "(No output)".into()
@@ -84,12 +132,42 @@ pub mod external_env {
             }
         }
     }
+
+    impl Device for ExternalEnv {
+        fn read(&mut self, address: u8) -> Option<u16> {
+            self.get_next_word()
+        }
+        fn write(&mut self, address: u8, word: u16) {
+            self.put_word(word)
+        }
+        fn unread(&mut self, address: u8, word: u16) -> bool {
+            self.unget_word(word);
+            true
+        }
+        fn unwrite(&mut self, address: u8) -> bool {
+            self.unput_word().is_some()
+        }
+        fn io_position(&self) -> Option<(usize, usize)> {
+            Some((self.words_read, self.output.len()))
+        }
+        fn interrupt_requested(&mut self) -> bool {
+            std::mem::take(&mut self.input_arrived)
+        }
+        fn saved_io(&self) -> Option<(Vec<u16>, Vec<u16>)> {
+            Some((self.input.clone(), self.output.clone()))
+        }
+        fn restore_io(&mut self, input: &[u16], output: &[u16]) -> bool {
+            *self = ExternalEnv::with_output(input.to_vec(), output.to_vec());
+            true
+        }
+    }
 }
 
 #[cfg(test)]
 mod external_env_tests {
     use super::external_env::ExternalEnv;
 
+    #[allow(clippy::assertions_on_constants)]
     fn test_read_next_word(env: &mut ExternalEnv, expected: u16) {
         let opt_value = env.get_next_word();
         let mut word: u16 = 0;
@@ -126,6 +204,7 @@ mod external_env_tests {
         assert_eq!(env.input_for_dump, "No input");
     }
     #[test]
+    #[allow(clippy::useless_vec)]
     fn write_to_env() {
         let mut env = ExternalEnv::new(vec![]);
         for word in vec![0x1234, 0x2345, 0x3456] {
@@ -135,6 +214,21 @@ mod external_env_tests {
         assert_eq!(env.output_for_dump, "Output: 4660(1234x), 9029(2345x), 13398(3456x)");
     }
 
+    #[test]
+    fn undo_read_and_write() {
+        let mut env = ExternalEnv::new(vec![0x1234, 0x2345]);
+        test_read_next_word(&mut env, 0x1234);
+        env.unget_word(0x1234);
+        test_read_next_word(&mut env, 0x1234);
+        env.put_word(0x0001);
+        env.put_word(0x0002);
+        assert_eq!(env.unput_word(), Some(0x0002));
+        assert_eq!(env.output_for_dump, "Output: 1(0001x)");
+        assert_eq!(env.unput_word(), Some(0x0001));
+        assert_eq!(env.output_for_dump, "");
+        assert_eq!(env.unput_word(), None);
+    }
+
     #[test]
     fn push_value_to_input() {
         let mut env = ExternalEnv::new(vec![]);
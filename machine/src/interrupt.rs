@@ -0,0 +1,104 @@
+pub mod interrupt {
+    /// Where an interrupt goes. Taking one copies the pc to
+    /// R[saved_pc_register], disables interrupts and continues at the
+    /// vector. A store to the control word enables interrupts when the word
+    /// is nonzero and disables them when it is zero; it is plain memory
+    /// otherwise. A handler returns with a store to the control word
+    /// followed by `jr` through the saved register. Interrupts wait for one
+    /// instruction after a program enables them, so that `jr` runs first.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct InterruptConfig {
+        pub vector: u16,
+        pub saved_pc_register: u8,
+        pub control_address: u16,
+    }
+
+    /// The interrupt state of a machine with interrupts installed. They
+    /// start disabled.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct InterruptController {
+        config: InterruptConfig,
+        enabled: bool,
+        // raised and not yet taken
+        pending: bool,
+        // enabled by the program on the step just taken
+        holding: bool,
+    }
+
+    impl InterruptController {
+        pub fn new(config: InterruptConfig) -> Self {
+            Self { config, enabled: false, pending: false, holding: false }
+        }
+        pub fn get_config(&self) -> &InterruptConfig {
+            &self.config
+        }
+        pub fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+        pub fn is_pending(&self) -> bool {
+            self.pending
+        }
+        pub fn set_enabled(&mut self, enabled: bool) {
+            self.enabled = enabled;
+            self.holding = false;
+        }
+        // a request stays pending while interrupts are disabled
+        pub fn raise(&mut self) {
+            self.pending = true;
+        }
+        // whether an interrupt is taken before the next instruction
+        pub(crate) fn due(&self) -> bool {
+            self.enabled && self.pending && !self.holding
+        }
+        // enter the handler, giving the pc to resume at
+        pub(crate) fn take(&mut self) -> u16 {
+            self.pending = false;
+            self.enabled = false;
+            self.config.vector
+        }
+        // a store of word to the control address by the program
+        pub(crate) fn control(&mut self, word: u16) {
+            self.enabled = word != 0;
+            self.holding = self.enabled;
+        }
+        // one step went by without taking an interrupt
+        pub(crate) fn stepped(&mut self) {
+            self.holding = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod interrupt_tests {
+    use super::interrupt::{InterruptConfig, InterruptController};
+
+    fn controller() -> InterruptController {
+        InterruptController::new(InterruptConfig { vector: 0x30, saved_pc_register: 0xB, control_address: 0xFC })
+    }
+
+    #[test]
+    fn pending_waits_for_enable() {
+        let mut controller = controller();
+        controller.raise();
+        assert!(!controller.due());
+        controller.set_enabled(true);
+        assert!(controller.due());
+        assert_eq!(controller.take(), 0x30);
+        assert!(!controller.is_enabled());
+        assert!(!controller.is_pending());
+    }
+
+    #[test]
+    fn program_enable_holds_for_one_step() {
+        let mut controller = controller();
+        controller.raise();
+        controller.control(1);
+        assert!(!controller.due());
+        controller.stepped();
+        assert!(controller.due());
+        controller.control(0);
+        assert!(!controller.due());
+    }
+}
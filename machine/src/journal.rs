@@ -0,0 +1,158 @@
+pub mod journal {
+    use std::collections::VecDeque;
+    use crate::interrupt::interrupt::InterruptController;
+
+    /// The state changed by one call to `Machine::run_one_step`, holding the
+    /// values as they were before the step so that it can be undone exactly.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct StepRecord {
+        // pc and running flag before the step
+        pub(crate) pc: u16,
+        pub(crate) running: bool,
+        // register d and its previous value
+        pub(crate) reg: Option<(u8, u16)>,
+        // memory address written and its previous value
+        pub(crate) memory: Option<(u16, u16)>,
+        // device address and word read from it, to be pushed back on undo
+        pub(crate) input: Option<(u8, u16)>,
+        // device address written to
+        pub(crate) output: Option<u8>,
+        // interrupt state before the step, when interrupts are installed
+        pub(crate) interrupts: Option<InterruptController>,
+        // the register an interrupt taken by the step saved the pc in, and
+        // its previous value
+        pub(crate) saved_pc: Option<(u8, u16)>,
+    }
+
+    impl StepRecord {
+        pub(crate) fn new(pc: u16, running: bool) -> Self {
+            Self {
+                pc,
+                running,
+                reg: None,
+                memory: None,
+                input: None,
+                output: None,
+                interrupts: None,
+                saved_pc: None,
+            }
+        }
+        pub fn get_pc(&self) -> u16 {
+            self.pc
+        }
+    }
+
+    /// What `Machine::step_back` managed to undo.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StepBack {
+        // there was no recorded step to undo
+        NoHistory,
+        Undone,
+        // the machine stepped back, but the device at this address could
+        // not take back the word the step read or wrote
+        DeviceKept(u8),
+    }
+
+    impl StepBack {
+        // whether a step was undone, fully or not
+        pub fn stepped(&self) -> bool {
+            *self != StepBack::NoHistory
+        }
+    }
+
+    /// History of executed steps, oldest first. Recording is off until
+    /// enabled, since a long run would otherwise grow without bound; a
+    /// capacity keeps only the most recent steps.
+    #[derive(Debug, Default)]
+    pub struct Journal {
+        enabled: bool,
+        // most records kept; the oldest is dropped to make room
+        capacity: Option<usize>,
+        records: VecDeque<StepRecord>,
+    }
+
+    impl Journal {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+        pub fn set_enabled(&mut self, enabled: bool) {
+            self.enabled = enabled;
+            if !enabled {
+                self.records.clear();
+            }
+        }
+        pub fn get_capacity(&self) -> Option<usize> {
+            self.capacity
+        }
+        // None keeps every record; lowering the capacity drops the oldest
+        pub fn set_capacity(&mut self, capacity: Option<usize>) {
+            self.capacity = capacity;
+            if let Some(capacity) = capacity {
+                while self.records.len() > capacity {
+                    self.records.pop_front();
+                }
+            }
+        }
+        pub fn len(&self) -> usize {
+            self.records.len()
+        }
+        pub fn is_empty(&self) -> bool {
+            self.records.is_empty()
+        }
+        pub fn clear(&mut self) {
+            self.records.clear();
+        }
+        pub fn records(&self) -> impl Iterator<Item = &StepRecord> {
+            self.records.iter()
+        }
+        pub(crate) fn push(&mut self, record: StepRecord) {
+            if !self.enabled || self.capacity == Some(0) {
+                return;
+            }
+            if Some(self.records.len()) == self.capacity {
+                self.records.pop_front();
+            }
+            self.records.push_back(record);
+        }
+        pub(crate) fn pop(&mut self) -> Option<StepRecord> {
+            self.records.pop_back()
+        }
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use super::journal::{Journal, StepRecord};
+
+    #[test]
+    fn disabled_journal_records_nothing() {
+        let mut journal = Journal::new();
+        journal.push(StepRecord::new(0x10, true));
+        assert!(journal.is_empty());
+        journal.set_enabled(true);
+        journal.push(StepRecord::new(0x10, true));
+        journal.push(StepRecord::new(0x11, true));
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.pop().map(|record| record.get_pc()), Some(0x11));
+        journal.set_enabled(false);
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn full_journal_drops_the_oldest_record() {
+        let mut journal = Journal::new();
+        journal.set_enabled(true);
+        journal.set_capacity(Some(2));
+        for pc in 0x10..0x14 {
+            journal.push(StepRecord::new(pc, true));
+        }
+        let pcs: Vec<u16> = journal.records().map(|record| record.get_pc()).collect();
+        assert_eq!(pcs, vec![0x12, 0x13]);
+        journal.set_capacity(Some(1));
+        assert_eq!(journal.pop().map(|record| record.get_pc()), Some(0x13));
+        assert!(journal.is_empty());
+    }
+}
@@ -1,26 +1,81 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+#![allow(clippy::module_inception)]
+// num-derive 0.3 writes its impls inside a const
+#![allow(non_local_definitions)]
 
 #[macro_use]
 extern crate num_derive;
 
+pub mod analyzer;
+pub mod assembler;
+pub mod batch;
+pub mod block_engine;
+pub mod bus;
+pub mod cfg;
+pub mod config;
+pub mod coverage;
+pub mod debugger;
+pub mod device;
+pub mod disassembler;
 pub mod external_env;
+pub mod interrupt;
+pub mod journal;
+pub mod loop_detector;
+pub mod outcome;
+#[cfg(test)]
+mod conformance_tests;
+pub mod profiler;
 pub mod program_reader;
+pub mod sanitizer;
+pub mod snapshot;
+#[cfg(test)]
+mod test_programs;
+pub mod toy8;
+#[cfg(test)]
+mod toy8_conformance_tests;
+pub mod toy_java;
+pub mod trace;
 
 pub mod machine {
-    use super::external_env::external_env::ExternalEnv;
+    use super::config::config::{ConfigError, MachineConfig};
+    use super::coverage::coverage::Coverage;
+    use super::debugger::debugger::{DebugEvent, Debugger};
+    use super::device::device::{Device, IO_ADDRESS};
+    use super::interrupt::interrupt::{InterruptConfig, InterruptController};
+    use super::journal::journal::{Journal, StepBack, StepRecord};
+    use super::loop_detector::loop_detector::{LoopDetector, LoopState};
+    use super::outcome::outcome::{MachineError, StepOutcome};
+    use super::profiler::profiler::Profile;
+    use super::sanitizer::sanitizer::Sanitizer;
+    use super::snapshot::snapshot::{MachineSnapshot, SnapshotError, SNAPSHOT_VERSION};
+    use super::trace::trace::{NoTrace, TextTrace, TraceEvent, TraceSink};
+    use std::time::Instant;
 
     #[derive(Debug)]
     pub struct Machine {
-        pc: u8,
+        config: MachineConfig,
+        pc: u16,
+        // always 16 wide; a register past config.register_count traps when used
         regs: [u16; 16],
-        pub(crate) memory: [u16; 256],
+        pub(crate) memory: Vec<u16>,
+        // each word decoded when first fetched, and forgotten when written
+        decoded: Vec<Option<Instruction>>,
         running: bool,
+        // instructions executed since the machine was created or restored
+        steps: u64,
+        journal: Journal,
+        debugger: Debugger,
+        // None until interrupts are installed, so classic programs never see them
+        interrupts: Option<InterruptController>,
+        profile: Option<Profile>,
+        coverage: Option<Coverage>,
+        sanitizer: Option<Sanitizer>,
     }
 
     #[derive(FromPrimitive, ToPrimitive)]
-    #[derive(Debug)]
-    #[derive(PartialEq)]
+    #[derive(Debug, Clone, Copy)]
+    #[derive(PartialEq, Eq, Hash)]
     #[repr(u8)]
     pub enum OpCode{
         Halt, Add, Subtract, And, Xor, ShiftLeft, ShiftRight,
@@ -29,13 +84,13 @@ pub mod machine {
     }
 
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct ProgramLoadWord {
         address: u8,
         content: u16,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct Instruction {
         op: OpCode,
         // really u4 - one hex digit
@@ -53,6 +108,56 @@ pub mod machine {
             let op: OpCode = num::FromPrimitive::from_u8(op_code_num).unwrap();
             Self { op, d, s, t, address }
         }
+        // R[d] <- R[s] op R[t]; panics when op takes an address or a
+        // register number is not one hex digit
+        pub fn rr(op: OpCode, d: u8, s: u8, t: u8) -> Self {
+            assert!(op.is_rr(), "{:?} is not a register-register operation", op);
+            assert!(d < 16 && s < 16 && t < 16, "register out of range");
+            Self { op, d, s, t, address: 0 }
+        }
+        // an operation on R[d] and addr; panics when op does not take an address
+        pub fn addr(op: OpCode, d: u8, address: u8) -> Self {
+            assert!(op.is_addr(), "{:?} does not take an address", op);
+            assert!(d < 16, "register out of range");
+            Self { op, d, s: 0, t: 0, address }
+        }
+        pub fn halt() -> Self {
+            Self { op: OpCode::Halt, d: 0, s: 0, t: 0, address: 0 }
+        }
+        pub fn jump_register(d: u8) -> Self {
+            assert!(d < 16, "register out of range");
+            Self { op: OpCode::JumpRegister, d, s: 0, t: 0, address: 0 }
+        }
+        // Decode any word as the machine does. Digits the operation ignores
+        // are dropped, so encode gives back the word only when it is
+        // canonical; use TryFrom to reject the others.
+        pub fn decode(word: u16) -> Self {
+            let op = (word >> 12) as u8;
+            let d = (word >> 8 & 0xF) as u8;
+            let s = (word >> 4 & 0xF) as u8;
+            let t = (word & 0xF) as u8;
+            let address = (word & 0xFF) as u8;
+            let op: OpCode = num::FromPrimitive::from_u8(op).unwrap();
+            if op == OpCode::Halt {
+                Self::halt()
+            } else if op == OpCode::JumpRegister {
+                Self::jump_register(d)
+            } else if op.is_addr() {
+                Self::addr(op, d, address)
+            } else {
+                Self::rr(op, d, s, t)
+            }
+        }
+        pub fn encode(&self) -> u16 {
+            let op = (self.op as u16) << 12;
+            let d = (self.d as u16) << 8;
+            match self.op {
+                OpCode::Halt => 0,
+                OpCode::JumpRegister => op | d,
+                op_code if op_code.is_addr() => op | d | self.address as u16,
+                _ => op | d | (self.s as u16) << 4 | self.t as u16,
+            }
+        }
         pub fn get_values(&self) -> (&OpCode, u8, u8, u8, u8) {
             (&self.op, self.d, self.s, self.t, self.address)
         }
@@ -61,18 +166,78 @@ pub mod machine {
             // or op code is LoadDirect and R[t] == 255
             match self.op {
                 OpCode::Load => {self.address == 0xFF}
-                OpCode::LoadIndirect => {regs[self.t as usize] == 0xFF}
+                OpCode::LoadIndirect => {regs[self.t as usize] & 0xFF == 0xFF}
                 _ => false
             }
         }
         pub fn is_write_from_memory(&self, regs: &[u16; 16]) -> bool{
             match self.op {
                 OpCode::Store => {self.address == 0xFF}
-                OpCode::StoreIndirect => {regs[self.t as usize] == 0xFF}
+                OpCode::StoreIndirect => {regs[self.t as usize] & 0xFF == 0xFF}
                 _ => false
             }
         }
-        pub fn format_for_pp(&self, regs: &[u16; 16], memory: &[u16; 256]) -> String {
+        pub fn reads_register(&self, r: u8) -> bool {
+            match self.op {
+                OpCode::Add | OpCode::Subtract | OpCode::And | OpCode::Xor |
+                OpCode::ShiftLeft | OpCode::ShiftRight => self.s == r || self.t == r,
+                OpCode::LoadIndirect => self.t == r,
+                OpCode::StoreIndirect => self.d == r || self.t == r,
+                OpCode::Store | OpCode::BranchZero | OpCode::BranchPositive |
+                OpCode::JumpRegister => self.d == r,
+                OpCode::Halt | OpCode::LoadAddress | OpCode::Load | OpCode::JumpAndLink => false,
+            }
+        }
+        pub fn register_written(&self) -> Option<u8> {
+            match self.op {
+                OpCode::Add | OpCode::Subtract | OpCode::And | OpCode::Xor |
+                OpCode::ShiftLeft | OpCode::ShiftRight | OpCode::LoadAddress |
+                OpCode::Load | OpCode::LoadIndirect | OpCode::JumpAndLink => Some(self.d),
+                _ => None
+            }
+        }
+        // the memory address this instruction will read, if any; indirect
+        // addresses wrap at address_mask
+        pub fn memory_read_address(&self, regs: &[u16; 16], address_mask: u16) -> Option<u16> {
+            match self.op {
+                OpCode::Load => Some(self.address as u16 & address_mask),
+                OpCode::LoadIndirect => Some(regs[self.t as usize] & address_mask),
+                _ => None
+            }
+        }
+        // the memory address this instruction will write, if any
+        pub fn memory_write_address(&self, regs: &[u16; 16], address_mask: u16) -> Option<u16> {
+            match self.op {
+                OpCode::Store => Some(self.address as u16 & address_mask),
+                OpCode::StoreIndirect => Some(regs[self.t as usize] & address_mask),
+                _ => None
+            }
+        }
+        // whether this branch will be taken, or None if it is not a branch
+        pub fn branch_taken(&self, regs: &[u16; 16]) -> Option<bool> {
+            match self.op {
+                OpCode::BranchZero | OpCode::BranchPositive =>
+                    Some(branch_condition(self.op, regs[self.d as usize])),
+                _ => None
+            }
+        }
+        pub fn format_for_pp(&self, regs: &[u16; 16], memory: &[u16]) -> String {
+            self.format_with_ports(regs, memory, &|address| (address == IO_ADDRESS as u16).then(String::new))
+        }
+        // like format_for_pp, but shows the i/o of every address the device
+        // maps, with its port name
+        pub fn format_for_pp_on(&self, regs: &[u16; 16], memory: &[u16], ports: &dyn Device)
+            -> String {
+            self.format_with_ports(regs, memory, &|address| {
+                let address = u8::try_from(address).ok()?;
+                ports.is_mapped(address).then(|| port_label(ports, address))
+            })
+        }
+        // port gives the label to add after a mapped address, or None for
+        // memory; memory is a power of two words long
+        pub(crate) fn format_with_ports(&self, regs: &[u16; 16], memory: &[u16],
+                                        port: &dyn Fn(u16) -> Option<String>) -> String {
+            let address_mask = (memory.len() - 1) as u16;
             let (op, d, s, t, addr) = self.get_values();
             let dc = regs[d as usize];
             let sc = regs[s as usize];
@@ -83,7 +248,7 @@ pub mod machine {
                 OpCode::ShiftLeft | OpCode::ShiftRight
                 => {
                     format!(
-                        "Op: {:?} - d:R[{:01X}] set from: s:R[{:01X}]={}({:04X}) *op* t:R[{:01X}]={}({:04X})",
+                        "Op: {:?} - d:R[{:01X}] set from: s:R[{:01X}]={}({:04X}) <op> t:R[{:01X}]={}({:04X})",
                         op, d, s, sc, sc, t, tc, tc)
                 },
                 // R[d] <- addr
@@ -94,10 +259,11 @@ pub mod machine {
                 },
                 // R[d] <- mem[addr]
                 OpCode::Load => {
-                    if addr == 0xFF {
+                    let addr = addr as u16 & address_mask;
+                    if let Some(label) = port(addr) {
                         format!(
-                            "Op: {:?} - input to d:R[{:01X}] via mem[{:02X}]",
-                            op, d, addr
+                            "Op: {:?} - input to d:R[{:01X}] via mem[{:02X}]{}",
+                            op, d, addr, label
                         )
                     } else {
                         format!(
@@ -107,10 +273,11 @@ pub mod machine {
                 },
                 // mem[addr] <- R[d]
                 OpCode::Store => {
-                    if addr == 0xFF {
+                    let addr = addr as u16 & address_mask;
+                    if let Some(label) = port(addr) {
                         format!(
-                            "Op: {:?} - output from d:R[{:01X}] via mem[{:02X}]",
-                            op, d, addr
+                            "Op: {:?} - output from d:R[{:01X}] via mem[{:02X}]{}",
+                            op, d, addr, label
                         )
                     } else {
                         format!(
@@ -120,22 +287,23 @@ pub mod machine {
                 },
                 // R[d] <- mem[R[t]]
                 OpCode::LoadIndirect => {
-                    if tc == 255 {
+                    let ta = tc & address_mask;
+                    if let Some(label) = port(ta) {
                         format!(
-                            "Op: {:?} - input to d:R[{:01X}] via mem[{:02X}] as indicated by t:R[{:01X}]",
-                            op, d, tc, t )
+                            "Op: {:?} - input to d:R[{:01X}] via mem[{:02X}]{} as indicated by t:R[{:01X}]",
+                            op, d, ta, label, t )
                     } else {
                         format!(
                             "Op: {:?} - d:R[{:01X}] set from mem[t:R{:01X}]={}({:04X}) where t:R[{:01X}]={:04X}",
-                            op, d, t, memory[tc as usize], memory[tc as usize], t, tc)
+                            op, d, t, memory[ta as usize], memory[ta as usize], t, tc)
                     }
                 },
                 // mem[R[t]] <- R[d]
                 OpCode::StoreIndirect => {
-                    if tc == 255 {
+                    if let Some(label) = port(tc & address_mask) {
                         format!(
-                            "Op: {:?} - output from d:R[{:01X}] via mem[{:02X}] as indicated by t:R[{:01X}]",
-                            op, d, tc, t )
+                            "Op: {:?} - output from d:R[{:01X}] via mem[{:02X}]{} as indicated by t:R[{:01X}]",
+                            op, d, tc & address_mask, label, t )
                     } else {
                         format!(
                             "Op: {:?} - mem[t:R[{:01X}]={:04X}] set from d:R[{:01X}]{}({:04X})",
@@ -144,20 +312,20 @@ pub mod machine {
                 },
                 OpCode::BranchZero => {
                     format!("Op: {:?} - pc becomes {:02X} when d:R[{:01X}]={:?}({:04X}) == 0",
-                             op, addr & 0xFF, d, dc, dc )
+                             op, addr, d, dc, dc )
                 },
                 OpCode::BranchPositive => {
                     format!("Op: {:?} - pc becomes {:02X} when d:R[{:01X}]={:?}({:04X}) > 0",
-                             op, addr & 0xFF, d, dc, dc )
+                             op, addr, d, dc, dc )
                 },
                 OpCode::JumpRegister => {
                     format!("Op: {:?} - pc becomes {:02X} from d:R[{:01X}]={:04X}",
-                             op, dc & 0xFF, d, dc )
+                             op, dc & address_mask, d, dc )
                 },
                 //R[d] <- pc; pc <- addr
                 OpCode::JumpAndLink => {
                     format!("Op: {:?} - d:R[{:01X}] becomes pc and pc becomes {:02X}",
-                             op, d, addr & 0xFF )
+                             op, d, addr )
                 },
                 OpCode::Halt => { format!("Op: {:?}", op )},
             }
@@ -165,6 +333,194 @@ pub mod machine {
         }
     }
 
+    // " (name)" for a named port, nothing for an unnamed one
+    fn port_label(ports: &dyn Device, address: u8) -> String {
+        match ports.port_name(address) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        }
+    }
+
+    impl OpCode {
+        // the format 1 operations: R[d] <- R[s] op R[t]
+        pub fn is_rr(&self) -> bool {
+            matches!(self, OpCode::Add | OpCode::Subtract | OpCode::And | OpCode::Xor |
+                OpCode::ShiftLeft | OpCode::ShiftRight | OpCode::LoadIndirect | OpCode::StoreIndirect)
+        }
+        // the format 2 operations, which take R[d] and an address
+        pub fn is_addr(&self) -> bool {
+            matches!(self, OpCode::LoadAddress | OpCode::Load | OpCode::Store |
+                OpCode::BranchZero | OpCode::BranchPositive | OpCode::JumpAndLink)
+        }
+    }
+
+    /// A word that decodes to an instruction that ignores some of its hex
+    /// digits, so that it does not survive a round trip through `encode`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DecodeError {
+        pub word: u16,
+    }
+
+    impl std::fmt::Display for DecodeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:04X} is not a canonical instruction; it executes as {:04X}",
+                   self.word, Instruction::decode(self.word).encode())
+        }
+    }
+
+    impl std::error::Error for DecodeError {}
+
+    impl TryFrom<u16> for Instruction {
+        type Error = DecodeError;
+
+        fn try_from(word: u16) -> Result<Self, Self::Error> {
+            let instruction = Instruction::decode(word);
+            if instruction.encode() == word {
+                Ok(instruction)
+            } else {
+                Err(DecodeError { word })
+            }
+        }
+    }
+
+    // Words are 16-bit two's complement. The reference simulator (TOY.java)
+    // evaluates shifts on 32-bit ints and truncates the result, so the shift
+    // count is taken mod 32, counts from 16 to 31 shift every bit out, and a
+    // right shift copies the sign bit.
+    pub(crate) fn shift_left(value: u16, count: u16) -> u16 {
+        ((value as u32) << (count & 0x1F)) as u16
+    }
+    pub(crate) fn shift_right(value: u16, count: u16) -> u16 {
+        ((value as i16 as i32) >> (count & 0x1F)) as u16
+    }
+    pub(crate) fn is_positive(value: u16) -> bool {
+        (value as i16) > 0
+    }
+    // R[s] op R[t] for the arithmetic and logic op codes, 1 to 6
+    pub(crate) fn operate(op: OpCode, a: u16, b: u16) -> u16 {
+        match op {
+            OpCode::Add => a.wrapping_add(b),
+            OpCode::Subtract => a.wrapping_sub(b),
+            OpCode::And => a & b,
+            OpCode::Xor => a ^ b,
+            OpCode::ShiftLeft => shift_left(a, b),
+            OpCode::ShiftRight => shift_right(a, b),
+            _ => unreachable!("{:?} is not an arithmetic op code", op),
+        }
+    }
+    // whether BranchZero or BranchPositive on value is taken
+    pub(crate) fn branch_condition(op: OpCode, value: u16) -> bool {
+        match op {
+            OpCode::BranchZero => value == 0,
+            OpCode::BranchPositive => is_positive(value),
+            _ => unreachable!("{:?} is not a branch", op),
+        }
+    }
+
+    /// What instructions do to some kind of value: words for the machine,
+    /// ranges of words for the analyzer. `execute` is the one description of
+    /// every op code, so the two cannot disagree about which registers and
+    /// addresses an instruction uses.
+    pub(crate) trait Semantics {
+        type Value: Copy;
+        fn constant(&self, word: u16) -> Self::Value;
+        fn read_reg(&self, r: usize) -> Self::Value;
+        fn write_reg(&mut self, r: usize, value: Self::Value);
+        fn read_memory(&mut self, address: Self::Value) -> Self::Value;
+        fn write_memory(&mut self, address: Self::Value, value: Self::Value);
+        // one of the op codes 1 to 6
+        fn operate(&self, op: OpCode, a: Self::Value, b: Self::Value) -> Self::Value;
+        // BranchZero or BranchPositive on value
+        fn branch(&mut self, op: OpCode, value: Self::Value, target: u16);
+        fn jump(&mut self, target: Self::Value);
+        // the pc after the instruction, which jump and link saves
+        fn return_address(&self) -> Self::Value;
+    }
+
+    // Run one instruction; false for a halt. Addresses wrap at mask.
+    pub(crate) fn execute<S: Semantics>(state: &mut S, instruction: &Instruction, mask: u16) -> bool {
+        let (op, d, s, t, address) = instruction.get_values();
+        // addr as a memory address; lda takes it as a plain number
+        let target = address as u16 & mask;
+        let d = d as usize;
+        let s = s as usize;
+        let t = t as usize;
+        let mask = state.constant(mask);
+        match op {
+            // 0	halt	-	exit
+            OpCode::Halt => { return false; }
+            // 1	add	1	R[d] <- R[s] + R[t]
+            // 2	subtract	1	R[d] <- R[s] - R[t]
+            // 3	and	1	R[d] <- R[s] & R[t]
+            // 4	xor	1	R[d] <- R[s] ^ R[t]
+            // 5	left shift	1	R[d] <- R[s] << R[t]
+            // 6	right shift	1	R[d] <- R[s] >> R[t]
+            OpCode::Add | OpCode::Subtract | OpCode::And | OpCode::Xor |
+            OpCode::ShiftLeft | OpCode::ShiftRight => {
+                let value = state.operate(*op, state.read_reg(s), state.read_reg(t));
+                state.write_reg(d, value);
+            },
+            // 7	load address	2	R[d] <- addr
+            OpCode::LoadAddress => {
+                let value = state.constant(address as u16);
+                state.write_reg(d, value);
+            },
+            // 8	load	2	R[d] <- mem[addr]
+            // Note: addr == 255 is special case to load sysin into mem[255]
+            //   before execution this instruction; handled in execution loop
+            OpCode::Load => {
+                let value = state.read_memory(state.constant(target));
+                state.write_reg(d, value);
+            },
+            // 9	store	2	mem[addr] <- R[d]
+            // Note: addr == 255 is special case to write sysout from mem[255]
+            //   after execution this instruction; handled in execution loop
+            OpCode::Store => {
+                let (address, value) = (state.constant(target), state.read_reg(d));
+                state.write_memory(address, value);
+            },
+            // A	load indirect	1	R[d] <- mem[R[t]]
+            // Note: R[t] == 255 is special case to load sysin into mem[255]
+            //   before execution this instruction; handled in execution loop
+            // Note: R[t] wraps at the top of memory, as with pc <- R[d]
+            OpCode::LoadIndirect => {
+                let address = state.operate(OpCode::And, state.read_reg(t), mask);
+                let value = state.read_memory(address);
+                state.write_reg(d, value);
+            },
+            // B	store indirect	1	mem[R[t]] <- R[d]
+            // Note: R[T] == 255 is special case to write sysout from mem[255]
+            //   after execution this instruction; handled in execution loop
+            OpCode::StoreIndirect => {
+                let address = state.operate(OpCode::And, state.read_reg(t), mask);
+                let value = state.read_reg(d);
+                state.write_memory(address, value);
+            },
+            // C	branch zero	2	if (R[d] == 0) pc <- addr
+            // D	branch positive	2	if (R[d] > 0) pc <- addr
+            OpCode::BranchZero | OpCode::BranchPositive => {
+                let value = state.read_reg(d);
+                state.branch(*op, value, target);
+            },
+            // E	jump register	-	pc <- R[d]
+            OpCode::JumpRegister => {
+                let target = state.operate(OpCode::And, state.read_reg(d), mask);
+                state.jump(target);
+            },
+            // F	jump and link	2	R[d] <- pc; pc <- addr
+            OpCode::JumpAndLink => {
+                let link = state.return_address();
+                state.write_reg(d, link);
+                let target = state.constant(target);
+                state.jump(target);
+            },
+        };
+        // regs are u16 and pc is masked to an address, so neither can overflow
+        let zero = state.constant(0);
+        state.write_reg(0, zero);                // ensure reg[0] is always 0
+        true
+    }
+
     impl ProgramLoadWord {
         pub fn new(address: u8, content: u16) -> Self {
             Self {
@@ -172,139 +528,155 @@ pub mod machine {
                 content,
             }
         }
+        pub fn get_address(&self) -> u8 {
+            self.address
+        }
+        pub fn get_content(&self) -> u16 {
+            self.content
+        }
+    }
+
+    /// Bounds on a run. With none set a run only stops at halt or on a
+    /// trap. The deadline is read from the system clock, which is not
+    /// available on wasm32-unknown-unknown, so leave it unset in the browser.
+    #[derive(Debug, Clone, Default)]
+    pub struct RunLimits {
+        pub max_steps: Option<u64>,
+        pub deadline: Option<Instant>,
+        // compare each state against an earlier one to prove an endless loop
+        pub detect_loops: bool,
+    }
+
+    // how often, in steps, a limited run reads the clock
+    pub(crate) const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+    impl Default for Machine {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl Machine {
         pub fn new() -> Self {
-            let pc: u8 = 0;
-            let memory: [u16; 256] = [0; 256];
-            let regs: [u16; 16] = [0; 16];
-            let running = false;
+            Self::build(MachineConfig::toy())
+        }
+        // a machine of another shape running the TOY instruction set, which
+        // needs 16 bit words and at most 16 registers
+        pub fn with_config(config: MachineConfig) -> Result<Self, ConfigError> {
+            config.validate(16, 4)?;
+            Ok(Self::build(config))
+        }
+        fn build(config: MachineConfig) -> Self {
             Self {
-                pc,
-                regs,
-                memory,
-                running,
+                config,
+                pc: 0,
+                regs: [0; 16],
+                memory: vec![0; config.memory_size()],
+                decoded: vec![None; config.memory_size()],
+                running: false,
+                steps: 0,
+                journal: Journal::new(),
+                debugger: Debugger::new(),
+                interrupts: None,
+                profile: None,
+                coverage: None,
+                sanitizer: None,
             }
         }
+        pub fn get_config(&self) -> &MachineConfig {
+            &self.config
+        }
         pub fn load(&mut self, loads: Vec<ProgramLoadWord>) {
+            let mask = self.config.address_mask();
             for word in loads {
-                self.memory[word.address as usize] = word.content
+                let address = word.address as u16 & mask;
+                self.set_memory_word(address as usize, word.content);
+                if let Some(sanitizer) = self.sanitizer.as_mut() {
+                    sanitizer.loaded(address);
+                }
             }
         }
 
         pub fn set_running(&mut self) { self.running = true; }
         pub fn reset_running(&mut self) { self.running = false; }
         pub fn get_running(&self) -> bool { self.running }
-        pub fn set_program_counter(&mut self, pc: u8) {
-            self.pc = pc;
+        pub fn set_program_counter(&mut self, pc: u16) {
+            self.pc = pc & self.config.address_mask();
         }
-        pub fn get_program_counter(&self) -> u8 {
+        pub fn get_program_counter(&self) -> u16 {
             self.pc
         }
+        pub fn get_step_count(&self) -> u64 {
+            self.steps
+        }
+        pub fn reset_step_count(&mut self) {
+            self.steps = 0;
+        }
+        // steps run somewhere other than run_one_step
+        pub(crate) fn count_steps(&mut self, steps: u64) {
+            self.steps += steps;
+        }
         pub fn get_regs(&self) -> Vec<u16> {
-            self.regs.to_vec()
+            self.regs[..self.config.register_count].to_vec()
         }
         pub fn set_reg(&mut self, i: usize, value: u16) {
+            assert!(i < self.config.register_count);
             self.regs[i] = value;
         }
         pub fn get_memory(&self) -> Vec<u16> {
-            self.memory.to_vec()
+            self.memory.clone()
         }
         pub fn set_memory_word(&mut self, index: usize, value: u16) {
-            assert!(index < 256);
+            assert!(index < self.memory.len());
             self.memory[index] = value;
+            self.decoded[index] = None;
         }
         pub fn get_memory_word(&self, index: usize) -> u16 {
-            assert!(index < 256);
+            assert!(index < self.memory.len());
             self.memory[index]
         }
 
+        // The device address behind a memory address, if a device takes its
+        // loads and stores. The configured i/o address reaches the device as
+        // IO_ADDRESS, and other addresses below 100 as themselves, when the
+        // device maps them; a bus without a stdio port leaves it plain memory.
+        pub(crate) fn device_address(&self, address: u16, env: &dyn Device) -> Option<u8> {
+            if address == self.config.io_address {
+                return env.is_mapped(IO_ADDRESS).then_some(IO_ADDRESS);
+            }
+            u8::try_from(address).ok()
+                .filter(|&port| port != IO_ADDRESS && env.is_mapped(port))
+        }
+
         pub(crate) fn get_next_instruction(&mut self) -> Instruction {
             let local_pc = self.pc;
-            let word = self.get_memory_word(local_pc as usize);
-            self.set_program_counter(local_pc + 1); // default
-            self.instruction_from_word(word)
+            self.set_program_counter(local_pc.wrapping_add(1)); // default, wrapping at the top of memory
+            match self.decoded[local_pc as usize] {
+                Some(instruction) => instruction,
+                None => {
+                    let instruction = self.instruction_from_word(self.memory[local_pc as usize]);
+                    self.decoded[local_pc as usize] = Some(instruction);
+                    instruction
+                },
+            }
         }
         fn instruction_from_word(&self, word: u16) -> Instruction {
-            let op= (word >> 12) as u8;
-            let d = (word >> 8 & 0xF) as u8;
-            let s = (word >> 4 & 0xF) as u8;
-            let t = (word & 0xF) as u8;
-            let address = (word & 0xFF) as u8;
-            let format2 = vec![7 as u8, 8, 9, 0xC, 0xD, 0xF];
-            if op == 0 {
-                Instruction::new(0, 0, 0, 0, 0)
-            } else if op == 0xE {
-                Instruction::new(op, d, 0, 0, 0)
-            } else {
-                if format2.contains(&op) {
-                    Instruction::new(op, d, 0, 0, address)
-                } else {
-                    Instruction::new(op, d, s, t, 0)
-                }
-            }
+            Instruction::decode(word)
         }
         fn execute_next_instruction(&mut self, instruction: &Instruction) -> bool {
-            let (op, d, s, t, address) =  instruction.get_values();
-            let d = d as usize;
-            let s = s as usize;
-            let t = t as usize;
-            match op {
-                // 0	halt	-	exit
-                OpCode::Halt => { return false; }
-                // 1	add	1	R[d] <- R[s] + R[t]
-                OpCode::Add => {self.regs[d] = self.regs[s] + self.regs[t]},
-                // 2	subtract	1	R[d] <- R[s] - R[t]
-                OpCode::Subtract => {
-                    self.regs[d] =
-                        {let a: i16 = self.regs[s] as i16 - self.regs[t] as i16; a} as u16
-                },
-                // 3	and	1	R[d] <- R[s] & R[t]
-                OpCode::And => {self.regs[d] = self.regs[s] & self.regs[t]},
-                // 4	xor	1	R[d] <- R[s] ^ R[t]
-                OpCode::Xor => {self.regs[d] = self.regs[s] ^ self.regs[t]},
-                // 5	left shift	1	R[d] <- R[s] << R[t]
-                OpCode::ShiftLeft => {self.regs[d] = self.regs[s] << self.regs[t]},
-                // 6	right shift	1	R[d] <- R[s] >> R[t]
-                OpCode::ShiftRight => {self.regs[d] = self.regs[s] >> self.regs[t]},
-                // 7	load address	2	R[d] <- addr
-                OpCode::LoadAddress => {self.regs[d] = address as u16},
-                // 8	load	2	R[d] <- mem[addr]
-                // Note: addr == 255 is special case to load sysin into mem[255]
-                //   before execution this instruction; handled in execution loop
-                OpCode::Load => {self.regs[d] = self.memory[address as usize]},
-                // 9	store	2	mem[addr] <- R[d]
-                // Note: addr == 255 is special case to write sysout from mem[255]
-                //   after execution this instruction; handled in execution loop
-                OpCode::Store => {self.memory[address as usize] = self.regs[d]},
-                // A	load indirect	1	R[d] <- mem[R[t]]
-                // Note: R[t] == 255 is special case to load sysin into mem[255]
-                //   before execution this instruction; handled in execution loop
-                OpCode::LoadIndirect => {self.regs[d] = self.memory[self.regs[t] as usize]},
-                // B	store indirect	1	mem[R[t]] <- R[d]
-                // Note: R[T] == 255 is special case to write sysout from mem[255]
-                //   after execution this instruction; handled in execution loop
-                OpCode::StoreIndirect => {self.memory[self.regs[t] as usize] = self.regs[d] },
-                // C	branch zero	2	if (R[d] == 0) pc <- addr
-                OpCode::BranchZero => {if self.regs[d] == 0 {self.pc = address}},
-                // D	branch positive	2	if (R[d] > 0) pc <- addr
-                OpCode::BranchPositive => {if self.regs[d] > 0 {self.pc = address}},
-                // E	jump register	-	pc <- R[d]
-                OpCode::JumpRegister => {self.pc = (self.regs[d] & 0xFF) as u8},
-                // F	jump and link	2	R[d] <- pc; pc <- addr
-                OpCode::JumpAndLink => {self.regs[d] = self.pc as u16; self.pc = address },
-            };
-            self.regs[0] = 0;                // ensure reg[0] is always 0
-            // don't let reg[d] overflow a 16-bit integer
-            self.regs[d as usize] = self.regs[d as usize] & 0xFFFF;
-            self.pc = self.pc & 0xFF;        // don't let pc overflow an 8-bit integer
-            true
+            let mask = self.config.address_mask();
+            execute(self, instruction, mask)
         }
         pub fn current_instruction_pp(&self, word: u16) -> String {
             let instruction = self.instruction_from_word(word);
             instruction.format_for_pp(&self.regs, &self.memory)
         }
+        pub fn current_instruction_pp_on(&self, word: u16, ports: &dyn Device) -> String {
+            let instruction = self.instruction_from_word(word);
+            instruction.format_with_ports(&self.regs, &self.memory, &|address| {
+                self.device_address(address, ports).map(|port| port_label(ports, port))
+            })
+        }
         pub fn dump_regs(&self) {
             print!("pc: {:2x} regs: 0={:2x}, 1={:2x}, 2={:2x}, 3={:2x}, 4={:2x},",
                    self.pc, self.regs[0], self.regs[1], self.regs[2],
@@ -321,7 +693,8 @@ pub mod machine {
         }
         pub fn dump_memory(&self) {
             println!(" memory...");
-            for i in 0..15 {
+            // the last row, which holds the i/o word, is left out
+            for i in 0..(self.memory.len() / 16).saturating_sub(1) {
                 let start = 16 * i;
                 print!("  {:02X}:", start);
                 for loc in start..(start + 16) {
@@ -330,50 +703,552 @@ pub mod machine {
                 println!()
             }
         }
-        pub fn run(&mut self, env: &mut ExternalEnv) {
-            self.set_program_counter(0x10);
+        // Run from the entry pc to a halt, printing the trace.
+        pub fn run(&mut self, env: &mut dyn Device) -> Result<StepOutcome, MachineError> {
+            self.run_traced(env, &mut TextTrace::stdout())
+        }
+        pub fn run_traced(&mut self, env: &mut dyn Device, trace: &mut dyn TraceSink)
+            -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(self.config.entry_pc);
             self.set_running();
+            let fast = !trace.is_enabled() && self.is_plain();
             while self.get_running(){
-                self.run_one_step(env, true);
+                if fast {
+                    self.fast_step(env)?;
+                } else {
+                    self.run_one_step_traced(env, trace)?;
+                }
+            }
+            Ok(StepOutcome::Halted)
+        }
+
+        pub fn get_journal(&self) -> &Journal {
+            &self.journal
+        }
+        // recording is needed for step_back and rewind_to; turning it off
+        // discards the history
+        pub fn set_journal_enabled(&mut self, enabled: bool) {
+            self.journal.set_enabled(enabled);
+        }
+        // keep at most this many steps of history; None is unbounded
+        pub fn set_journal_capacity(&mut self, capacity: Option<usize>) {
+            self.journal.set_capacity(capacity);
+        }
+        pub fn steps_recorded(&self) -> usize {
+            self.journal.len()
+        }
+
+        // Undo the most recent recorded step, including any word it read from
+        // or wrote to the device. A device that cannot take back its i/o is
+        // left as it is, and the result says so.
+        pub fn step_back(&mut self, env: &mut dyn Device) -> StepBack {
+            let record = match self.journal.pop() {
+                Some(record) => record,
+                None => return StepBack::NoHistory,
+            };
+            let mut result = StepBack::Undone;
+            if let Some(address) = record.output {
+                if !env.unwrite(address) {
+                    result = StepBack::DeviceKept(address);
+                }
+            }
+            if let Some((address, word)) = record.input {
+                if !env.unread(address, word) {
+                    result = StepBack::DeviceKept(address);
+                }
+            }
+            if let Some((address, value)) = record.memory {
+                self.set_memory_word(address as usize, value);
+            }
+            if let Some((d, value)) = record.reg {
+                self.regs[d as usize] = value;
+            }
+            if let Some((r, value)) = record.saved_pc {
+                self.regs[r as usize] = value;
+            }
+            if record.interrupts.is_some() {
+                self.interrupts = record.interrupts;
+            }
+            self.pc = record.pc;
+            self.running = record.running;
+            self.steps = self.steps.saturating_sub(1);
+            env.untick();
+            result
+        }
+
+        // Undo recorded steps until only `step` of them remain.
+        pub fn rewind_to(&mut self, step: usize, env: &mut dyn Device) -> bool {
+            if step > self.journal.len() {
+                return false;
+            }
+            while self.journal.len() > step {
+                self.step_back(env);
+            }
+            true
+        }
+
+        pub fn snapshot(&self, env: &dyn Device) -> MachineSnapshot {
+            let (input, output) = env.saved_io().unwrap_or_default();
+            MachineSnapshot {
+                version: SNAPSHOT_VERSION,
+                pc: self.pc,
+                regs: self.get_regs(),
+                memory: self.memory.clone(),
+                running: self.running,
+                steps: self.steps,
+                interrupts: self.interrupts,
+                input,
+                output,
+            }
+        }
+
+        // Replace the machine and device state with the snapshot. A device
+        // that keeps no i/o can only take a snapshot without any. The
+        // journal is cleared since its history belongs to the old state.
+        pub fn restore(&mut self, snapshot: &MachineSnapshot, env: &mut dyn Device)
+            -> Result<(), SnapshotError> {
+            snapshot.validate(&self.config)?;
+            let has_io = !snapshot.input.is_empty() || !snapshot.output.is_empty();
+            if !env.restore_io(&snapshot.input, &snapshot.output) && has_io {
+                return Err(SnapshotError::DeviceCannotRestore);
+            }
+            self.set_program_counter(snapshot.pc);
+            self.regs = [0; 16];
+            self.regs[..snapshot.regs.len()].copy_from_slice(&snapshot.regs);
+            self.memory.copy_from_slice(&snapshot.memory);
+            self.decoded.fill(None);
+            self.running = snapshot.running;
+            self.steps = snapshot.steps;
+            self.interrupts = snapshot.interrupts;
+            self.journal.clear();
+            Ok(())
+        }
+
+        pub fn get_debugger(&self) -> &Debugger {
+            &self.debugger
+        }
+        pub fn debugger_mut(&mut self) -> &mut Debugger {
+            &mut self.debugger
+        }
+
+        // Start counting steps into a fresh profile, or stop and drop it.
+        pub fn set_profiling(&mut self, enabled: bool) {
+            self.profile = enabled.then(|| Profile::new(self.memory.len()));
+        }
+        pub fn get_profile(&self) -> Option<&Profile> {
+            self.profile.as_ref()
+        }
+        // the profile so far, leaving a fresh one to count into
+        pub fn take_profile(&mut self) -> Option<Profile> {
+            let size = self.memory.len();
+            self.profile.as_mut().map(|profile| std::mem::replace(profile, Profile::new(size)))
+        }
+
+        // Start collecting coverage afresh, or stop and drop it.
+        pub fn set_coverage(&mut self, enabled: bool) {
+            self.coverage = enabled.then(|| Coverage::new(self.memory.len()));
+        }
+        pub fn get_coverage(&self) -> Option<&Coverage> {
+            self.coverage.as_ref()
+        }
+        // the coverage so far, leaving a fresh one to collect into
+        pub fn take_coverage(&mut self) -> Option<Coverage> {
+            let size = self.memory.len();
+            self.coverage.as_mut().map(|coverage| std::mem::replace(coverage, Coverage::new(size)))
+        }
+
+        // Start checking memory use afresh, or stop. Words loaded before it
+        // is turned on are taken for uninitialized data.
+        pub fn set_sanitizing(&mut self, enabled: bool) {
+            self.sanitizer = enabled.then(|| Sanitizer::new(self.memory.len()));
+        }
+        pub fn get_sanitizer(&self) -> Option<&Sanitizer> {
+            self.sanitizer.as_ref()
+        }
+
+        // Install interrupts, disabled; replaces any installed before.
+        pub fn set_interrupts(&mut self, config: InterruptConfig) -> Result<(), ConfigError> {
+            self.config.validate_interrupts(&config)?;
+            self.interrupts = Some(InterruptController::new(config));
+            Ok(())
+        }
+        pub fn clear_interrupts(&mut self) {
+            self.interrupts = None;
+        }
+        pub fn get_interrupts(&self) -> Option<&InterruptController> {
+            self.interrupts.as_ref()
+        }
+        // to raise an interrupt or enable interrupts from outside the program
+        pub fn interrupts_mut(&mut self) -> Option<&mut InterruptController> {
+            self.interrupts.as_mut()
+        }
+
+        // pass on an interrupt raised by the device since the last step
+        fn poll_interrupts(&mut self, env: &mut dyn Device) {
+            if let Some(interrupts) = self.interrupts.as_mut() {
+                if env.interrupt_requested() {
+                    interrupts.raise();
+                }
+            }
+        }
+
+        // the address of the next instruction, which is the vector when an
+        // interrupt is due
+        fn next_pc(&self) -> u16 {
+            match self.interrupts {
+                Some(interrupts) if interrupts.due() => interrupts.get_config().vector,
+                _ => self.pc,
+            }
+        }
+
+        // Take a due interrupt before the step's instruction is fetched.
+        fn enter_interrupt(&mut self, record: &mut StepRecord, trace: &mut dyn TraceSink) {
+            let interrupts = match self.interrupts.as_mut() {
+                Some(interrupts) => interrupts,
+                None => return,
+            };
+            record.interrupts = Some(*interrupts);
+            if !interrupts.due() {
+                interrupts.stepped();
+                return;
             }
+            let register = interrupts.get_config().saved_pc_register;
+            let vector = interrupts.take();
+            trace.event(TraceEvent::Interrupt { pc: self.pc, register, vector });
+            record.saved_pc = Some((register, self.regs[register as usize]));
+            self.regs[register as usize] = self.pc;
+            self.pc = vector;
         }
 
-        pub fn run_one_step(&mut self, env: &mut ExternalEnv, print_trace: bool){
+        // Execute one instruction, then report a watchpoint it touched, a halt,
+        // or a breakpoint on the instruction that is now next.
+        pub fn debug_step(&mut self, env: &mut dyn Device) -> Result<DebugEvent, MachineError> {
             if !self.get_running() {
-                return
+                return Ok(DebugEvent::Halted)
             }
-            let instruction = &self.get_next_instruction();
-            if instruction.is_read_to_memory(&self.regs) {
-                let option = env.get_next_word();
-                let word = option.unwrap();
-                self.set_memory_word(0xFF as usize, word);
-                if print_trace {
-                    println!("Read word to mem[255]: {}({:04X}x)", word, word);
+            self.poll_interrupts(env);
+            let pc = self.next_pc();
+            let hit = if self.debugger.has_watchpoints() {
+                let instruction = self.instruction_from_word(self.memory[pc as usize]);
+                self.debugger.watch_hit(&instruction, &self.regs, &self.memory)
+            } else {
+                None
+            };
+            let outcome = self.run_one_step(env, false)?;
+            if let Some((id, target, access, old)) = hit {
+                let new = target.value(&self.regs, &self.memory);
+                return Ok(DebugEvent::Watchpoint { id, pc, target, access, old, new })
+            }
+            if outcome == StepOutcome::Halted {
+                return Ok(DebugEvent::Halted)
+            }
+            match self.debugger.breakpoint_at(self.pc, &self.regs, &self.memory) {
+                Some(id) => Ok(DebugEvent::Breakpoint { id, pc: self.pc }),
+                None => Ok(DebugEvent::Stepped),
+            }
+        }
+
+        // Run from the current pc until a breakpoint, watchpoint or halt. A
+        // breakpoint on the current pc does not stop the first step, so this
+        // can be called again to continue from a breakpoint.
+        pub fn run_until_event(&mut self, env: &mut dyn Device) -> Result<DebugEvent, MachineError> {
+            loop {
+                match self.debug_step(env)? {
+                    DebugEvent::Stepped => continue,
+                    event => return Ok(event),
                 }
             }
-            if print_trace {
-                println!(
-                    "Execute @ pc = {:02X}x; {}",
-                    &self.pc - 1,
-                    instruction.format_for_pp(&self.regs, &self.memory)
-                );
+        }
+
+        // Like run, but stop at the first limit reached.
+        pub fn run_with_limits(&mut self, env: &mut dyn Device, limits: &RunLimits)
+            -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(self.config.entry_pc);
+            self.set_running();
+            self.resume_with_limits(env, limits)
+        }
+
+        pub fn run_with_limit(&mut self, env: &mut dyn Device, max_steps: u64)
+            -> Result<StepOutcome, MachineError> {
+            let limits = RunLimits { max_steps: Some(max_steps), ..RunLimits::default() };
+            self.run_with_limits(env, &limits)
+        }
+
+        // Continue from the current pc without tracing; the limits count
+        // only the steps taken by this call.
+        pub fn resume_with_limits(&mut self, env: &mut dyn Device, limits: &RunLimits)
+            -> Result<StepOutcome, MachineError> {
+            let start = self.steps;
+            // a device without an i/o position could feed each pass of a
+            // loop different words, so repeated states prove nothing; nor
+            // do they when an interrupt could come between them
+            let mut detector = match env.io_position() {
+                Some(io_position) if limits.detect_loops && self.interrupts.is_none() =>
+                    Some(LoopDetector::new(self.loop_state(io_position))),
+                _ => None,
+            };
+            let fast = self.is_plain();
+            while self.get_running() {
+                let taken = self.steps - start;
+                if limits.max_steps.is_some_and(|max_steps| taken >= max_steps) {
+                    return Ok(StepOutcome::StepLimit { steps: taken });
+                }
+                if let Some(deadline) = limits.deadline {
+                    if taken.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                        return Ok(StepOutcome::DeadlineExceeded { steps: taken });
+                    }
+                }
+                let changed = detector.as_ref().and(self.memory_touched());
+                if fast {
+                    self.fast_step(env)?;
+                } else {
+                    self.run_one_step(env, false)?;
+                }
+                if let Some(detector) = detector.as_mut() {
+                    let io_position = env.io_position().unwrap_or_default();
+                    if let Some(period) = detector.observe(self.loop_state(io_position), changed) {
+                        return Ok(StepOutcome::InfiniteLoop { pc: self.pc, period });
+                    }
+                }
             }
-            let running = self.execute_next_instruction(instruction);
+            Ok(StepOutcome::Halted)
+        }
+
+        // The memory word the next step may change, with its value now. A
+        // step without interrupts changes at most one: the word it stores
+        // to, or the word it loads input into.
+        fn memory_touched(&self) -> Option<(u16, u16)> {
+            let pc = self.pc as usize;
+            let instruction = self.decoded[pc]
+                .unwrap_or_else(|| self.instruction_from_word(self.memory[pc]));
+            let mask = self.config.address_mask();
+            let address = instruction.memory_write_address(&self.regs, mask)
+                .or_else(|| instruction.memory_read_address(&self.regs, mask))?;
+            Some((address, self.memory[address as usize]))
+        }
+
+        fn loop_state(&self, io_position: (usize, usize)) -> LoopState<'_> {
+            LoopState {
+                pc: self.pc,
+                regs: &self.regs,
+                memory: &self.memory,
+                io_position,
+            }
+        }
+
+        // Nothing is watching the steps: no journal, interrupts, profile,
+        // coverage or sanitizer, so fast_step does all a step has to.
+        pub(crate) fn is_plain(&self) -> bool {
+            !self.journal.is_enabled() && self.interrupts.is_none() && self.profile.is_none()
+                && self.coverage.is_none() && self.sanitizer.is_none()
+        }
+
+        // A step of a plain machine without trace events, records or any
+        // allocation. It must do just what run_one_step_traced does when
+        // there is nothing to trace or record.
+        pub(crate) fn fast_step(&mut self, env: &mut dyn Device) -> Result<StepOutcome, MachineError> {
+            let pc = self.pc;
+            if pc == self.config.io_address {
+                let word = self.memory[pc as usize];
+                return self.trap(pc, MachineError::InvalidAddress {
+                    pc, instruction: word, address: pc });
+            }
+            let instruction = self.get_next_instruction();
+            if self.config.register_count < 16 {
+                let register_count = self.config.register_count as u8;
+                let missing = (register_count..16).find(|&r|
+                    instruction.reads_register(r) || instruction.register_written() == Some(r));
+                if let Some(register) = missing {
+                    let word = self.memory[pc as usize];
+                    return self.trap(pc, MachineError::InvalidRegister {
+                        pc, instruction: word, register });
+                }
+            }
+            let mask = self.config.address_mask();
+            let memory_write = instruction.memory_write_address(&self.regs, mask);
+            let read_port = instruction.memory_read_address(&self.regs, mask)
+                .and_then(|address| Some((address, self.device_address(address, &*env)?)));
+            if let Some((address, port)) = read_port {
+                let word_read = match env.read(port) {
+                    Some(value) => value,
+                    None => {
+                        let word = self.memory[pc as usize];
+                        return self.trap(pc, MachineError::InputExhausted { pc, instruction: word });
+                    },
+                };
+                self.set_memory_word(address as usize, word_read);
+            }
+            let running = execute(self, &instruction, mask);
             if running {
-                if instruction.is_write_from_memory(&self.regs) {
-                    let word = self.get_memory_word(0xFF as usize);
-                    env.put_word(word);
-                    if print_trace {
-                        println!("Write word from mem[255]: {}({:04X}x)", word, word);
+                if let Some(address) = memory_write {
+                    if let Some(port) = self.device_address(address, &*env) {
+                        env.write(port, self.memory[address as usize]);
                     }
                 }
             }
+            self.steps += 1;
+            env.tick();
+            self.running = running;
+            Ok(if running { StepOutcome::Running } else { StepOutcome::Halted })
+        }
+
+        // stop the machine with the pc left on the instruction that trapped
+        fn trap(&mut self, pc: u16, error: MachineError) -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(pc);
+            self.reset_running();
+            Err(error)
+        }
+
+        // One step, printing its trace when print_trace is set.
+        pub fn run_one_step(&mut self, env: &mut dyn Device, print_trace: bool)
+            -> Result<StepOutcome, MachineError> {
+            if print_trace {
+                self.run_one_step_traced(env, &mut TextTrace::stdout())
+            } else {
+                self.run_one_step_traced(env, &mut NoTrace)
+            }
+        }
+        pub fn run_one_step_traced(&mut self, env: &mut dyn Device, trace: &mut dyn TraceSink)
+            -> Result<StepOutcome, MachineError> {
+            if !self.get_running() {
+                return Ok(StepOutcome::Halted)
+            }
+            let mut record = StepRecord::new(self.pc, true);
+            self.poll_interrupts(env);
+            self.enter_interrupt(&mut record, trace);
+            let pc = self.pc;
+            let word = self.get_memory_word(pc as usize);
+            let tracing = trace.is_enabled();
+            if tracing {
+                trace.event(TraceEvent::Fetch { pc, word });
+            }
+            if pc == self.config.io_address {
+                // the i/o word is not memory to fetch an instruction from
+                return self.trap(pc, MachineError::InvalidAddress {
+                    pc, instruction: word, address: pc });
+            }
+            let instruction = &self.get_next_instruction();
+            let register_count = self.config.register_count as u8;
+            let missing = (register_count..16).find(|&r|
+                instruction.reads_register(r) || instruction.register_written() == Some(r));
+            if let Some(register) = missing {
+                return self.trap(pc, MachineError::InvalidRegister {
+                    pc, instruction: word, register });
+            }
+            let mask = self.config.address_mask();
+            record.reg = Some((instruction.d, self.regs[instruction.d as usize]));
+            let memory_read = instruction.memory_read_address(&self.regs, mask);
+            let memory_write = instruction.memory_write_address(&self.regs, mask);
+            let taken = instruction.branch_taken(&self.regs);
+            record.memory = memory_write.map(|address| (address, self.memory[address as usize]));
+            let read_address = memory_read
+                .and_then(|address| Some((address, self.device_address(address, &*env)?)));
+            let write_address = memory_write
+                .and_then(|address| Some((address, self.device_address(address, &*env)?)));
+            if let Some(sanitizer) = self.sanitizer.as_mut() {
+                // device addresses are i/o, not memory
+                let read = memory_read.filter(|_| read_address.is_none());
+                let written = memory_write.filter(|_| write_address.is_none());
+                sanitizer.step(pc, read, written);
+            }
+            if let Some((address, port)) = read_address {
+                let word_read = match env.read(port) {
+                    Some(value) => value,
+                    None => return self.trap(pc, MachineError::InputExhausted {
+                        pc, instruction: word }),
+                };
+                record.memory = Some((address, self.memory[address as usize]));
+                record.input = Some((port, word_read));
+                self.set_memory_word(address as usize, word_read);
+                if tracing {
+                    trace.event(TraceEvent::Input { address, port, word: word_read });
+                }
+            }
+            if tracing {
+                trace.event(TraceEvent::Decode { pc, word, text: self.current_instruction_pp_on(word, &*env) });
+                if let Some(address) = memory_read {
+                    trace.event(TraceEvent::MemRead { address, value: self.memory[address as usize] });
+                }
+            }
+            let running = self.execute_next_instruction(instruction);
+            if let Some(interrupts) = self.interrupts.as_mut().filter(|_| running) {
+                let control = interrupts.get_config().control_address;
+                if memory_write == Some(control) {
+                    interrupts.control(self.memory[control as usize]);
+                }
+            }
+            if tracing && running {
+                if let Some(register) = instruction.register_written() {
+                    trace.event(TraceEvent::RegWrite { register, value: self.regs[register as usize] });
+                }
+                if let Some(address) = memory_write {
+                    trace.event(TraceEvent::MemWrite { address, value: self.memory[address as usize] });
+                }
+            }
+            if let Some((address, port)) = write_address.filter(|_| running) {
+                let word = self.get_memory_word(address as usize);
+                env.write(port, word);
+                record.output = Some(port);
+                if tracing {
+                    trace.event(TraceEvent::Output { address, port, word });
+                }
+            }
+            if tracing && !running {
+                trace.event(TraceEvent::Halt { pc });
+            }
+            if let Some(profile) = self.profile.as_mut() {
+                profile.record(pc, instruction, memory_read, memory_write);
+            }
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(pc, taken);
+            }
+            self.journal.push(record);
+            self.steps += 1;
+            env.tick();
             if running {
                 self.set_running();
+                Ok(StepOutcome::Running)
             } else {
                 self.reset_running();
+                Ok(StepOutcome::Halted)
+            }
+        }
+    }
+
+    // the machine's own registers, memory and pc; i/o is done around execute
+    impl Semantics for Machine {
+        type Value = u16;
+        fn constant(&self, word: u16) -> u16 {
+            word
+        }
+        fn read_reg(&self, r: usize) -> u16 {
+            self.regs[r]
+        }
+        fn write_reg(&mut self, r: usize, value: u16) {
+            self.regs[r] = value;
+        }
+        fn read_memory(&mut self, address: u16) -> u16 {
+            self.memory[address as usize]
+        }
+        fn write_memory(&mut self, address: u16, value: u16) {
+            self.memory[address as usize] = value;
+            self.decoded[address as usize] = None;
+        }
+        fn operate(&self, op: OpCode, a: u16, b: u16) -> u16 {
+            operate(op, a, b)
+        }
+        fn branch(&mut self, op: OpCode, value: u16, target: u16) {
+            if branch_condition(op, value) {
+                self.pc = target;
             }
         }
+        fn jump(&mut self, target: u16) {
+            self.pc = target;
+        }
+        // the fetch has already moved the pc on
+        fn return_address(&self) -> u16 {
+            self.pc
+        }
     }
 
     #[cfg(test)]
@@ -382,6 +1257,7 @@ pub mod machine {
         use crate::external_env::external_env::ExternalEnv;
         use crate::program_reader::program_reader::ProgramReader;
 
+        #[allow(clippy::assertions_on_constants, clippy::useless_vec)]
         mod read_write_memory {
             use super::*;
 
@@ -466,7 +1342,7 @@ pub mod machine {
             }
         }
 
-        fn loaded_machine() -> Machine {
+        fn multiply_loads() -> Vec<ProgramLoadWord> {
             let test_program_strings = vec![
                 "10: 8AFF",   // read to R[A]                  a = StdIn.readInt();
                 "11: 8BFF",   // read to R[B]                  b = StdIn.readInt();
@@ -485,12 +1361,16 @@ pub mod machine {
                 program_text.push(String::from(s));
             }
             reader.load_from_vec(program_text);
-            let loads = reader.parse();
+            reader.parse()
+        }
+
+        fn loaded_machine() -> Machine {
             let mut machine = Machine::new();
-            machine.load(loads);
+            machine.load(multiply_loads());
             machine
         }
 
+        #[allow(clippy::needless_range_loop)]
         mod loaded_program {
             use super::*;
 
@@ -579,6 +1459,7 @@ pub mod machine {
                            "Op: JumpAndLink - d:R[1] becomes pc and pc becomes 14");
             }
         }
+        #[allow(clippy::useless_vec, clippy::unnecessary_cast)]
         mod fetch_instruction {
             use super::*;
 
@@ -664,14 +1545,74 @@ pub mod machine {
                 assert_eq!(0x00, instruction.address);
             }
         }
-        mod instruction_execution {
+        mod encoding {
             use super::*;
-            use num;
 
             #[test]
-            fn test_from_primitive_to_opcode() {
-                let v: [OpCode; 3] = [
-                    num::FromPrimitive::from_u8(0).unwrap(),
+            fn decode_encode_round_trip_for_every_word() {
+                for word in 0..=u16::MAX {
+                    let instruction = Instruction::decode(word);
+                    assert_eq!(Instruction::decode(instruction.encode()), instruction, "{:04X}", word);
+                    match Instruction::try_from(word) {
+                        Ok(strict) => {
+                            assert_eq!(strict, instruction);
+                            assert_eq!(strict.encode(), word);
+                        },
+                        Err(error) => {
+                            assert_eq!(error.word, word);
+                            assert_ne!(instruction.encode(), word);
+                        },
+                    }
+                }
+            }
+
+            #[test]
+            fn canonical_words() {
+                // every format 1 and 2 word is canonical; halt and jump register
+                // ignore their low digits
+                let canonical = (0..=u16::MAX).filter(|&word| Instruction::try_from(word).is_ok()).count();
+                assert_eq!(canonical, 14 * 4096 + 1 + 16);
+            }
+
+            #[test]
+            fn constructors_encode() {
+                assert_eq!(Instruction::rr(OpCode::Add, 0xC, 0xC, 0xB).encode(), 0x1CCB);
+                assert_eq!(Instruction::rr(OpCode::StoreIndirect, 0xA, 0xB, 0xB).encode(), 0xBABB);
+                assert_eq!(Instruction::addr(OpCode::Load, 0xA, 0xFF).encode(), 0x8AFF);
+                assert_eq!(Instruction::addr(OpCode::BranchZero, 0xA, 0x18).encode(), 0xCA18);
+                assert_eq!(Instruction::jump_register(0xF).encode(), 0xEF00);
+                assert_eq!(Instruction::halt().encode(), 0x0000);
+                assert_eq!(Instruction::decode(0x1CCB), Instruction::rr(OpCode::Add, 0xC, 0xC, 0xB));
+            }
+
+            #[test]
+            fn non_canonical_words_are_rejected() {
+                let error = Instruction::try_from(0xE1FF).unwrap_err();
+                assert_eq!(error.to_string(), "E1FF is not a canonical instruction; it executes as E100");
+                assert!(Instruction::try_from(0x0005).is_err());
+            }
+
+            #[test]
+            #[should_panic(expected = "does not take an address")]
+            fn addr_rejects_register_operation() {
+                Instruction::addr(OpCode::Add, 1, 0x10);
+            }
+
+            #[test]
+            #[should_panic(expected = "register out of range")]
+            fn rr_rejects_wide_register() {
+                Instruction::rr(OpCode::Add, 0x10, 0, 0);
+            }
+        }
+        #[allow(clippy::unnecessary_cast)]
+        mod instruction_execution {
+            use super::*;
+            use num;
+
+            #[test]
+            fn test_from_primitive_to_opcode() {
+                let v: [OpCode; 3] = [
+                    num::FromPrimitive::from_u8(0).unwrap(),
                     num::FromPrimitive::from_u8(1).unwrap(),
                     num::FromPrimitive::from_u8(2).unwrap(),
                 ];
@@ -845,6 +1786,7 @@ pub mod machine {
                 assert!(!machine.execute_next_instruction(&instruction));
             }
         }
+        #[allow(clippy::nonminimal_bool, clippy::unnecessary_cast)]
         mod interface_with_external_read_write{
             use super::*;
             use super::ExternalEnv;
@@ -945,6 +1887,7 @@ pub mod machine {
                 assert_eq!(machine.memory[0xFF as usize], word_to_write);
             }
         }
+        #[allow(clippy::needless_range_loop)]
         mod run_one_step {
             use super::*;
 
@@ -960,14 +1903,896 @@ pub mod machine {
                 machine.set_running();
                 machine.set_program_counter(0x10);
                 assert_eq!(machine.get_program_counter(), 0x10);
-                machine.run_one_step(&mut env, false);
+                assert_eq!(machine.run_one_step(&mut env, false), Ok(StepOutcome::Running));
                 assert!(machine.get_running());
                 assert_eq!(machine.get_program_counter(), 0x11);
                 while machine.get_running() {
-                    machine.run_one_step(&mut env, false);
+                    machine.run_one_step(&mut env, false).unwrap();
                 }
                 assert_eq!(env.peek_at_last_output(), 1200);
+                assert_eq!(machine.run_one_step(&mut env, false), Ok(StepOutcome::Halted));
+            }
+
+            #[test]
+            fn input_exhausted_stops_machine() {
+                let mut env = ExternalEnv::new(vec![30]);
+                let mut machine = loaded_machine();
+                machine.set_running();
+                machine.set_program_counter(0x10);
+                assert_eq!(machine.run_one_step(&mut env, false), Ok(StepOutcome::Running));
+                assert_eq!(machine.run_one_step(&mut env, false),
+                           Err(MachineError::InputExhausted { pc: 0x11, instruction: 0x8BFF }));
+                assert!(!machine.get_running());
+                assert_eq!(machine.get_program_counter(), 0x11);
+            }
+
+            #[test]
+            fn fetch_from_io_address_is_invalid() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                machine.set_running();
+                machine.set_program_counter(0xFF);
+                assert_eq!(machine.run_one_step(&mut env, false),
+                           Err(MachineError::InvalidAddress { pc: 0xFF, instruction: 0, address: 0xFF }));
+                assert!(!machine.get_running());
+            }
+
+            #[test]
+            fn add_wraps_around() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                machine.set_reg(1, 0xFFFF);
+                machine.set_reg(2, 0x0002);
+                machine.set_memory_word(0x10, 0x1312);
+                machine.set_running();
+                machine.set_program_counter(0x10);
+                assert_eq!(machine.run_one_step(&mut env, false), Ok(StepOutcome::Running));
+                assert_eq!(machine.get_regs()[3], 0x0001);
+            }
+
+            #[test]
+            fn indirect_address_uses_low_byte() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                machine.set_reg(1, 0x1234);
+                machine.set_memory_word(0x34, 0xBEEF);
+                machine.set_memory_word(0x10, 0xA201);
+                machine.set_running();
+                machine.set_program_counter(0x10);
+                assert_eq!(machine.run_one_step(&mut env, false), Ok(StepOutcome::Running));
+                assert_eq!(machine.get_regs()[2], 0xBEEF);
+            }
+
+            #[test]
+            fn run_to_halt() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                assert_eq!(machine.run(&mut env), Ok(StepOutcome::Halted));
+                assert_eq!(env.peek_at_last_output(), 12);
+            }
+        }
+
+        mod limited_runs {
+            use super::*;
+            use std::time::Duration;
+
+            fn spin_machine() -> Machine {
+                // 10: C010   goto 10
+                let mut machine = Machine::new();
+                machine.set_memory_word(0x10, 0xC010);
+                machine
+            }
+
+            #[test]
+            fn step_count() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                machine.run(&mut env).unwrap();
+                // 4 setup steps, 3 passes of 4, the final test, write and halt
+                assert_eq!(machine.get_step_count(), 19);
+                machine.reset_step_count();
+                assert_eq!(machine.get_step_count(), 0);
+            }
+
+            #[test]
+            fn step_limit_stops_spin() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = spin_machine();
+                assert_eq!(machine.run_with_limit(&mut env, 1000),
+                           Ok(StepOutcome::StepLimit { steps: 1000 }));
+                assert!(machine.get_running());
+                assert_eq!(machine.get_step_count(), 1000);
+            }
+
+            #[test]
+            fn limit_not_reached() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                assert_eq!(machine.run_with_limit(&mut env, 19), Ok(StepOutcome::Halted));
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                assert_eq!(machine.run_with_limit(&mut env, 18),
+                           Ok(StepOutcome::StepLimit { steps: 18 }));
+            }
+
+            #[test]
+            fn deadline_stops_spin() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = spin_machine();
+                let limits = RunLimits {
+                    deadline: Some(Instant::now() + Duration::from_millis(20)),
+                    ..RunLimits::default()
+                };
+                let outcome = machine.run_with_limits(&mut env, &limits).unwrap();
+                assert!(matches!(outcome, StepOutcome::DeadlineExceeded { .. }));
+            }
+
+            #[test]
+            fn detects_spin_loop() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = spin_machine();
+                let limits = RunLimits { detect_loops: true, ..RunLimits::default() };
+                assert_eq!(machine.run_with_limits(&mut env, &limits),
+                           Ok(StepOutcome::InfiniteLoop { pc: 0x10, period: 1 }));
+            }
+
+            #[test]
+            fn counting_loop_is_not_reported() {
+                // R[2] <- 1; R[1] <- R[1] - R[2]; if (R[1] == 0) goto 14; goto 11; halt
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                for (i, word) in [0x7201, 0x2112, 0xC114, 0xC011, 0x0000].iter().enumerate() {
+                    machine.set_memory_word(0x10 + i, *word);
+                }
+                // R[1] counts down through every value until it reaches 0
+                let limits = RunLimits { detect_loops: true, ..RunLimits::default() };
+                assert_eq!(machine.run_with_limits(&mut env, &limits), Ok(StepOutcome::Halted));
+                assert_eq!(machine.get_regs()[1], 0);
+            }
+
+            #[test]
+            fn detects_long_period_loop() {
+                // as above but the test is on R[2], so R[1] wraps around forever
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                for (i, word) in [0x7201, 0x2112, 0xC214, 0xC011, 0x0000].iter().enumerate() {
+                    machine.set_memory_word(0x10 + i, *word);
+                }
+                let limits = RunLimits { detect_loops: true, ..RunLimits::default() };
+                let outcome = machine.run_with_limits(&mut env, &limits).unwrap();
+                assert!(matches!(outcome, StepOutcome::InfiniteLoop { period: 0x30000, .. }));
+            }
+
+            #[test]
+            fn loop_with_output_is_not_reported() {
+                // 10: 91FF write R[1]; 11: C010 goto 10
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = Machine::new();
+                machine.set_memory_word(0x10, 0x91FF);
+                machine.set_memory_word(0x11, 0xC010);
+                let limits = RunLimits { max_steps: Some(5000), detect_loops: true, ..RunLimits::default() };
+                assert_eq!(machine.run_with_limits(&mut env, &limits),
+                           Ok(StepOutcome::StepLimit { steps: 5000 }));
+            }
+        }
+
+        mod debugging {
+            use super::*;
+            use crate::debugger::debugger::{Access, Condition, WatchTarget};
+
+            fn started_machine() -> Machine {
+                let mut machine = loaded_machine();
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                machine
+            }
+
+            #[test]
+            fn breakpoint_stops_each_pass() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = started_machine();
+                let id = machine.debugger_mut().add_breakpoint(0x15);
+                for _ in 0..3 {
+                    assert_eq!(machine.run_until_event(&mut env),
+                               Ok(DebugEvent::Breakpoint { id, pc: 0x15 }));
+                }
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Halted));
+                assert_eq!(env.peek_at_last_output(), 12);
+            }
+
+            #[test]
+            fn conditional_breakpoint() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = started_machine();
+                let condition = Condition::parse("R[A] == 0").unwrap();
+                let id = machine.debugger_mut().add_conditional_breakpoint(0x14, condition);
+                assert_eq!(machine.run_until_event(&mut env),
+                           Ok(DebugEvent::Breakpoint { id, pc: 0x14 }));
+                assert_eq!(machine.get_regs()[0xC], 12);
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Halted));
+            }
+
+            #[test]
+            fn register_write_watchpoint() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = started_machine();
+                let id = machine.debugger_mut().add_watchpoint(WatchTarget::Register(0xC), Access::Write);
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x12, target: WatchTarget::Register(0xC), access: Access::Write, old: 0, new: 0 }));
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x15, target: WatchTarget::Register(0xC), access: Access::Write, old: 0, new: 4 }));
+                assert_eq!(machine.get_program_counter(), 0x16);
+            }
+
+            #[test]
+            fn memory_read_and_write_watchpoints() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = started_machine();
+                let id = machine.debugger_mut().add_watchpoint(WatchTarget::Memory(0xFF), Access::ReadWrite);
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x10, target: WatchTarget::Memory(0xFF), access: Access::Read, old: 0, new: 3 }));
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x11, target: WatchTarget::Memory(0xFF), access: Access::Read, old: 3, new: 4 }));
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x18, target: WatchTarget::Memory(0xFF), access: Access::Write, old: 4, new: 12 }));
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Halted));
+            }
+
+            #[test]
+            fn register_read_watchpoint() {
+                let mut env = ExternalEnv::new(vec![2, 4]);
+                let mut machine = started_machine();
+                let id = machine.debugger_mut().add_watchpoint(WatchTarget::Register(1), Access::Read);
+                assert_eq!(machine.run_until_event(&mut env), Ok(DebugEvent::Watchpoint {
+                    id, pc: 0x16, target: WatchTarget::Register(1), access: Access::Read, old: 1, new: 1 }));
+            }
+
+            #[test]
+            fn debug_step_reports_errors() {
+                let mut env = ExternalEnv::new(vec![]);
+                let mut machine = started_machine();
+                assert!(machine.debug_step(&mut env).is_err());
+                assert_eq!(machine.debug_step(&mut env), Ok(DebugEvent::Halted));
+            }
+        }
+
+        mod reverse_execution {
+            use super::*;
+
+            type State = (u16, Vec<u16>, Vec<u16>, bool, String, String);
+
+            fn state(machine: &Machine, env: &ExternalEnv) -> State {
+                (machine.get_program_counter(), machine.get_regs(), machine.get_memory(),
+                 machine.get_running(), env.input_for_display(), env.output_for_display())
+            }
+
+            #[test]
+            fn step_back_restores_every_step() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                machine.set_journal_enabled(true);
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                let mut history = vec![state(&machine, &env)];
+                while machine.get_running() {
+                    machine.run_one_step(&mut env, false).unwrap();
+                    history.push(state(&machine, &env));
+                }
+                assert_eq!(env.peek_at_last_output(), 12);
+                assert_eq!(machine.steps_recorded(), history.len() - 1);
+                history.pop();
+                while let Some(expected) = history.pop() {
+                    assert_eq!(machine.step_back(&mut env), StepBack::Undone);
+                    assert_eq!(state(&machine, &env), expected);
+                }
+                assert_eq!(machine.step_back(&mut env), StepBack::NoHistory);
+                assert_eq!(env.input_for_display(), "3(0003), 4(0004)");
+                assert_eq!(env.output_for_display(), "(No output)");
+            }
+
+            #[test]
+            fn rewind_and_replay() {
+                let mut env = ExternalEnv::new(vec![2, 5]);
+                let mut machine = loaded_machine();
+                machine.set_journal_enabled(true);
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                for _ in 0..3 {
+                    machine.run_one_step(&mut env, false).unwrap();
+                }
+                let after_three = state(&machine, &env);
+                while machine.get_running() {
+                    machine.run_one_step(&mut env, false).unwrap();
+                }
+                assert!(!machine.rewind_to(machine.steps_recorded() + 1, &mut env));
+                assert!(machine.rewind_to(3, &mut env));
+                assert_eq!(state(&machine, &env), after_three);
+                while machine.get_running() {
+                    machine.run_one_step(&mut env, false).unwrap();
+                }
+                assert_eq!(env.output_for_display(), "10(000A)");
+            }
+
+            #[test]
+            fn step_back_after_trap() {
+                let mut env = ExternalEnv::new(vec![7]);
+                let mut machine = loaded_machine();
+                machine.set_journal_enabled(true);
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                machine.run_one_step(&mut env, false).unwrap();
+                assert!(machine.run_one_step(&mut env, false).is_err());
+                assert_eq!(machine.step_back(&mut env), StepBack::Undone);
+                assert!(machine.get_running());
+                assert_eq!(machine.get_program_counter(), 0x10);
+                assert_eq!(env.input_for_display(), "7(0007)");
+            }
+
+            #[test]
+            fn bounded_journal_keeps_the_latest_steps() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                machine.set_journal_enabled(true);
+                machine.set_journal_capacity(Some(2));
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                let mut history = vec![state(&machine, &env)];
+                while machine.get_running() {
+                    machine.run_one_step(&mut env, false).unwrap();
+                    history.push(state(&machine, &env));
+                }
+                assert_eq!(machine.steps_recorded(), 2);
+                assert!(!machine.rewind_to(3, &mut env));
+                assert!(machine.rewind_to(0, &mut env));
+                assert_eq!(state(&machine, &env), history[history.len() - 3]);
+                assert_eq!(machine.step_back(&mut env), StepBack::NoHistory);
+            }
+
+            #[test]
+            fn journal_is_off_by_default() {
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                let mut machine = loaded_machine();
+                machine.run(&mut env).unwrap();
+                assert_eq!(machine.steps_recorded(), 0);
+                assert_eq!(machine.step_back(&mut env), StepBack::NoHistory);
+            }
+        }
+
+        mod devices {
+            use super::*;
+            use crate::bus::bus::{CharConsole, CycleCounter, DeviceBus};
+            use crate::device::device::{ClosureDevice, StreamDevice};
+            use std::cell::RefCell;
+            use std::io::Cursor;
+            use std::rc::Rc;
+
+            #[test]
+            fn run_with_stream_device() {
+                let mut device = StreamDevice::new(Cursor::new("0003\n0004\n"), vec![]);
+                let mut machine = loaded_machine();
+                assert_eq!(machine.run_with_limit(&mut device, 1000), Ok(StepOutcome::Halted));
+                let (_, written) = device.into_inner();
+                assert_eq!(String::from_utf8(written).unwrap(), "000C\n");
+            }
+
+            #[test]
+            fn stream_device_exhausted() {
+                let mut device = StreamDevice::new(Cursor::new("0003\n"), vec![]);
+                let mut machine = loaded_machine();
+                assert_eq!(machine.run_with_limit(&mut device, 1000),
+                           Err(MachineError::InputExhausted { pc: 0x11, instruction: 0x8BFF }));
+            }
+
+            #[test]
+            fn run_with_closure_device() {
+                let written = Rc::new(RefCell::new(vec![]));
+                let sink = written.clone();
+                let mut device = ClosureDevice::new(
+                    |_| Some(5),
+                    move |address, word| sink.borrow_mut().push((address, word)));
+                let mut machine = loaded_machine();
+                machine.set_journal_enabled(true);
+                assert_eq!(machine.run_with_limit(&mut device, 1000), Ok(StepOutcome::Halted));
+                assert_eq!(*written.borrow(), vec![(0xFF, 25)]);
+                // the machine steps back even though the device cannot
+                assert_eq!(machine.step_back(&mut device), StepBack::Undone);
+                assert_eq!(machine.step_back(&mut device), StepBack::DeviceKept(0xFF));
+                assert_eq!(machine.get_program_counter(), 0x18);
+                assert_eq!(*written.borrow(), vec![(0xFF, 25)]);
+            }
+
+            #[test]
+            fn no_loop_detection_without_io_position() {
+                // 10: 81FF read R[1]; 11: C010 goto 10
+                let mut device = ClosureDevice::new(|_| Some(0), |_, _| ());
+                let mut machine = Machine::new();
+                machine.set_memory_word(0x10, 0x81FF);
+                machine.set_memory_word(0x11, 0xC010);
+                let limits = RunLimits { max_steps: Some(100), detect_loops: true, ..RunLimits::default() };
+                assert_eq!(machine.run_with_limits(&mut device, &limits),
+                           Ok(StepOutcome::StepLimit { steps: 100 }));
+            }
+
+            #[test]
+            fn ports_on_a_bus() {
+                // 10: 7A48 R[A] <- 'H'; 11: 7BFE R[B] <- FE; 12: BABB mem[R[B]] <- R[A]
+                // 13: 8CFD read counter to R[C]; 14: 8DFF read R[D]; 15: 0000 halt
+                let mut machine = Machine::new();
+                for (address, word) in [0x7A48, 0x7BFE, 0xBABB, 0x8CFD, 0x8DFF, 0x0000].iter().enumerate() {
+                    machine.set_memory_word(0x10 + address, *word);
+                }
+                let mut external = ExternalEnv::new(vec![0x0042]);
+                let mut screen = vec![];
+                let mut bus = DeviceBus::new();
+                bus.bind(IO_ADDRESS, "stdio", &mut external);
+                bus.bind(0xFE, "console", CharConsole::new(&mut screen));
+                bus.bind(0xFD, "counter", CycleCounter::new());
+                assert_eq!(machine.current_instruction_pp_on(0x8CFD, &bus),
+                           "Op: Load - input to d:R[C] via mem[FD] (counter)");
+                assert_eq!(machine.current_instruction_pp(0x8CFD),
+                           "Op: Load - d:R[C] set from mem[FD]=0(0000)");
+                machine.set_journal_enabled(true);
+                assert_eq!(machine.run_with_limit(&mut bus, 100), Ok(StepOutcome::Halted));
+                assert_eq!(machine.get_regs()[0xC], 3);
+                assert_eq!(machine.get_regs()[0xD], 0x0042);
+                assert_eq!(machine.get_memory_word(0xFD), 3);
+                let undone: Vec<_> = std::iter::from_fn(|| Some(machine.step_back(&mut bus)))
+                    .take_while(StepBack::stepped)
+                    .collect();
+                // the console cannot take back a printed character
+                assert_eq!(undone.iter().filter(|&&undo| undo != StepBack::Undone).collect::<Vec<_>>(),
+                           vec![&StepBack::DeviceKept(0xFE)]);
+                assert_eq!(bus.io_position(), None);
+                drop(bus);
+                assert_eq!(screen, b"H");
+                assert_eq!(external.input_for_display(), "66(0042)");
+            }
+        }
+
+        mod configs {
+            use super::*;
+            use crate::config::config::{ConfigError, MachineConfig};
+            use crate::snapshot::snapshot::SnapshotError;
+
+            #[test]
+            fn standard_toy_by_default() {
+                let machine = Machine::new();
+                assert_eq!(machine.get_config(), &MachineConfig::toy());
+                assert_eq!(machine.get_memory().len(), 256);
+                assert_eq!(machine.get_regs().len(), 16);
+            }
+
+            #[test]
+            fn rejects_configs_the_instruction_set_cannot_use() {
+                let config = MachineConfig { word_bits: 8, ..MachineConfig::toy() };
+                assert_eq!(Machine::with_config(config).err(), Some(ConfigError::WordBits(8)));
+                let config = MachineConfig { register_count: 32, ..MachineConfig::toy() };
+                assert_eq!(Machine::with_config(config).err(), Some(ConfigError::RegisterCount(32)));
+            }
+
+            #[test]
+            fn larger_memory_through_indirect_addressing() {
+                // 20: 7A03 R[A] <- 3; 21: 7B08 R[B] <- 8; 22: 5BBB R[B] <- R[B] << R[B]
+                // 23: 1BBA R[B] <- R[B] + R[A], so R[B] = 803; 24: A10B R[1] <- mem[R[B]]
+                // 25: 1111 R[1] <- R[1] + R[1]; 26: B10B mem[R[B]] <- R[1]
+                // 27: 91FF mem[FF] <- R[1], plain memory here; 28: 0000 halt
+                let config = MachineConfig { address_bits: 12, entry_pc: 0x20, io_address: 0xFFF,
+                    ..MachineConfig::toy() };
+                let mut machine = Machine::with_config(config).unwrap();
+                let program = [0x7A03, 0x7B08, 0x5BBB, 0x1BBA, 0xA10B, 0x1111, 0xB10B, 0x91FF, 0x0000];
+                for (address, word) in program.iter().enumerate() {
+                    machine.set_memory_word(0x20 + address, *word);
+                }
+                machine.set_memory_word(0x803, 21);
+                let mut env = ExternalEnv::new(vec![]);
+                assert_eq!(machine.run(&mut env), Ok(StepOutcome::Halted));
+                assert_eq!(machine.get_memory_word(0x803), 42);
+                assert_eq!(machine.get_memory_word(0xFF), 42);
+                assert!(env.get_output().is_empty());
+            }
+
+            #[test]
+            fn relocated_io_address() {
+                // 10: 8AFE read R[A]; 11: 1AAA R[A] <- R[A] + R[A]; 12: 9AFE write R[A]; 13: 0000 halt
+                let config = MachineConfig { io_address: 0xFE, ..MachineConfig::toy() };
+                let mut machine = Machine::with_config(config).unwrap();
+                for (address, word) in [0x8AFE, 0x1AAA, 0x9AFE, 0x0000].iter().enumerate() {
+                    machine.set_memory_word(0x10 + address, *word);
+                }
+                let env = ExternalEnv::new(vec![]);
+                assert_eq!(machine.current_instruction_pp_on(0x8AFE, &env),
+                           "Op: Load - input to d:R[A] via mem[FE]");
+                assert_eq!(machine.current_instruction_pp_on(0x8AFF, &env),
+                           "Op: Load - d:R[A] set from mem[FF]=0(0000)");
+                let mut env = ExternalEnv::new(vec![5]);
+                assert_eq!(machine.run(&mut env), Ok(StepOutcome::Halted));
+                assert_eq!(env.get_output(), &[10]);
+                // the i/o word cannot be fetched from
+                machine.set_program_counter(0xFE);
+                machine.set_running();
+                assert!(matches!(machine.run_one_step(&mut env, false),
+                                 Err(MachineError::InvalidAddress { pc: 0xFE, .. })));
+            }
+
+            #[test]
+            fn missing_registers_trap() {
+                // 10: 7101 R[1] <- 1; 11: 1981 R[9] <- R[8] + R[1]
+                let config = MachineConfig { register_count: 8, ..MachineConfig::toy() };
+                let mut machine = Machine::with_config(config).unwrap();
+                machine.set_memory_word(0x10, 0x7101);
+                machine.set_memory_word(0x11, 0x1981);
+                let mut env = ExternalEnv::new(vec![]);
+                assert_eq!(machine.run(&mut env),
+                           Err(MachineError::InvalidRegister { pc: 0x11, instruction: 0x1981, register: 8 }));
+                assert_eq!(machine.get_program_counter(), 0x11);
+                assert_eq!(machine.get_regs(), vec![0, 1, 0, 0, 0, 0, 0, 0]);
+            }
+
+            #[test]
+            fn snapshots_must_match_the_config() {
+                let env = ExternalEnv::new(vec![]);
+                let snapshot = Machine::new().snapshot(&env);
+                let config = MachineConfig { address_bits: 10, ..MachineConfig::toy() };
+                let mut machine = Machine::with_config(config).unwrap();
+                let mut env = ExternalEnv::new(vec![]);
+                assert_eq!(machine.restore(&snapshot, &mut env),
+                           Err(SnapshotError::WrongMemorySize { found: 256, expected: 1024 }));
+                let snapshot = machine.snapshot(&env);
+                assert_eq!(machine.restore(&snapshot, &mut env), Ok(()));
+            }
+        }
+
+        mod interrupts {
+            use super::*;
+            use crate::bus::bus::{DeviceBus, IntervalTimer};
+            use crate::config::config::ConfigError;
+            use crate::interrupt::interrupt::InterruptConfig;
+
+            const CONFIG: InterruptConfig = InterruptConfig {
+                vector: 0x30, saved_pc_register: 0xB, control_address: 0xFC };
+
+            // 10: 7101 R[1] <- 1; 11: 91FC enable interrupts
+            // 12: 1331 R[3] <- R[3] + R[1]; 13: C012 goto 12
+            // handler: 30: 1221 R[2] <- R[2] + R[1]; 31: 91FC enable; 32: EB00 return
+            fn counting_machine() -> Machine {
+                let mut machine = Machine::new();
+                for (address, word) in [0x7101, 0x91FC, 0x1331, 0xC012].iter().enumerate() {
+                    machine.set_memory_word(0x10 + address, *word);
+                }
+                for (address, word) in [0x1221, 0x91FC, 0xEB00].iter().enumerate() {
+                    machine.set_memory_word(0x30 + address, *word);
+                }
+                machine.set_interrupts(CONFIG).unwrap();
+                machine
+            }
+
+            #[test]
+            fn off_by_default() {
+                let mut machine = counting_machine();
+                machine.clear_interrupts();
+                assert_eq!(machine.get_interrupts(), None);
+                let mut external = ExternalEnv::new(vec![]);
+                let mut bus = DeviceBus::new();
+                bus.bind(IO_ADDRESS, "stdio", &mut external);
+                bus.bind(0xFB, "timer", IntervalTimer::new(2));
+                assert_eq!(machine.run_with_limit(&mut bus, 100), Ok(StepOutcome::StepLimit { steps: 100 }));
+                assert_eq!(machine.get_regs()[2], 0);
+                assert_eq!(machine.get_regs()[3], 49);
+            }
+
+            #[test]
+            fn bad_configs() {
+                let mut machine = Machine::new();
+                let config = InterruptConfig { saved_pc_register: 0, ..CONFIG };
+                assert_eq!(machine.set_interrupts(config), Err(ConfigError::SavedPcRegister(0)));
+                let mut machine = Machine::with_config(MachineConfig { address_bits: 5, io_address: 0x1F,
+                    ..MachineConfig::toy() }).unwrap();
+                assert_eq!(machine.set_interrupts(CONFIG), Err(ConfigError::OutsideMemory(0x30)));
+            }
+
+            #[test]
+            fn timer_interrupts() {
+                let mut machine = counting_machine();
+                let mut bus = DeviceBus::new();
+                bus.bind(0xFB, "timer", IntervalTimer::new(10));
+                assert_eq!(machine.run_with_limit(&mut bus, 100), Ok(StepOutcome::StepLimit { steps: 100 }));
+                // an interrupt every 10 steps, each taking 3 of the steps
+                assert_eq!(machine.get_regs()[2], 9);
+                assert_eq!(machine.get_regs()[3], 36);
+            }
+
+            #[test]
+            fn enabling_waits_for_the_return() {
+                // with an interrupt every step, the program still gets one
+                // instruction in between
+                let mut machine = counting_machine();
+                let mut bus = DeviceBus::new();
+                bus.bind(0xFB, "timer", IntervalTimer::new(1));
+                assert_eq!(machine.run_with_limit(&mut bus, 100), Ok(StepOutcome::StepLimit { steps: 100 }));
+                assert!(machine.get_regs()[3] > 0);
+                assert!((0x12..=0x13).contains(&machine.get_regs()[0xB]));
+            }
+
+            #[test]
+            fn input_raises_an_interrupt() {
+                // handler: 30: 82FF read R[2]; 31: 1222 R[2] <- R[2] + R[2]; 32: 92FF write R[2]
+                // 33: 91FC enable; 34: EB00 return
+                let mut machine = counting_machine();
+                for (address, word) in [0x82FF, 0x1222, 0x92FF, 0x91FC, 0xEB00].iter().enumerate() {
+                    machine.set_memory_word(0x30 + address, *word);
+                }
+                machine.set_journal_enabled(true);
+                let mut env = ExternalEnv::new(vec![]);
+                machine.set_program_counter(0x10);
+                machine.set_running();
+                for _ in 0..5 {
+                    machine.run_one_step(&mut env, false).unwrap();
+                }
+                env.push_to_input(21);
+                for _ in 0..10 {
+                    machine.run_one_step(&mut env, false).unwrap();
+                }
+                assert_eq!(env.get_output(), &[42]);
+                assert!(machine.get_interrupts().is_some_and(|interrupts| interrupts.is_enabled()));
+                while machine.step_back(&mut env).stepped() {}
+                assert_eq!(machine.get_program_counter(), 0x10);
+                assert_eq!(machine.get_regs(), vec![0; 16]);
+                assert!(env.get_output().is_empty());
+                assert_eq!(env.get_input(), &[21]);
+                assert!(machine.get_interrupts().is_some_and(|interrupts| !interrupts.is_enabled()));
+            }
+
+            #[test]
+            fn raised_from_outside() {
+                let mut machine = counting_machine();
+                let mut env = ExternalEnv::new(vec![]);
+                machine.set_program_counter(0x12);
+                machine.set_reg(1, 1);
+                machine.set_running();
+                let interrupts = machine.interrupts_mut().unwrap();
+                interrupts.set_enabled(true);
+                interrupts.raise();
+                machine.run_one_step(&mut env, false).unwrap();
+                assert_eq!(machine.get_regs()[2], 1);
+                assert_eq!(machine.get_regs()[0xB], 0x12);
+                assert_eq!(machine.get_program_counter(), 0x31);
+            }
+        }
+
+        mod profiling {
+            use super::*;
+
+            #[test]
+            fn counts_a_run() {
+                let mut machine = loaded_machine();
+                machine.set_profiling(true);
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                machine.run(&mut env).unwrap();
+                let profile = machine.get_profile().unwrap();
+                assert_eq!(profile.get_steps(), 19);
+                assert_eq!(profile.get_executions(0x14), 4);
+                assert_eq!(profile.get_executions(0x15), 3);
+                assert_eq!(profile.get_operation_count(OpCode::Add), 3);
+                assert_eq!(profile.get_reads(0xFF), 2);
+                assert_eq!(profile.get_writes(0xFF), 1);
+                assert_eq!(profile.hot_spots(1), vec![(0x14, 4)]);
+                let report = profile.report(&machine.get_memory(), 3);
+                assert!(report.starts_with("19 steps\n"));
+                assert!(report.contains("       3   15.8%      0      0  15: 1CCB"));
+                assert!(report.contains("  Add: 3 (15.8%)"));
+            }
+
+            #[test]
+            fn off_by_default() {
+                let mut machine = loaded_machine();
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                machine.run(&mut env).unwrap();
+                assert!(machine.get_profile().is_none());
+                machine.set_profiling(true);
+                let mut env = ExternalEnv::new(vec![1, 1]);
+                machine.run(&mut env).unwrap();
+                assert_eq!(machine.take_profile().unwrap().get_steps(), 11);
+                assert_eq!(machine.get_profile().unwrap().get_steps(), 0);
+            }
+        }
+
+        mod coverage_runs {
+            use super::*;
+            use crate::test_programs::MULTIPLY;
+
+            fn covered_run(input: Vec<u16>) -> Coverage {
+                let mut machine = loaded_machine();
+                machine.set_coverage(true);
+                let mut env = ExternalEnv::new(input);
+                machine.run(&mut env).unwrap();
+                machine.take_coverage().unwrap()
+            }
+
+            #[test]
+            fn merges_across_inputs() {
+                let mut reader = ProgramReader::new();
+                let mut lines = vec!["# multiply".to_string(), "".to_string()];
+                lines.extend(MULTIPLY.iter().map(|(address, word)| format!("{:02X}: {:04X}", address, word)));
+                reader.load_from_vec(lines);
+                let program = reader.parse_with_lines();
+                assert_eq!(program[0].0, 3);
+                let mut coverage = covered_run(vec![0, 4]);
+                assert_eq!(coverage.get_branch(0x14), (1, 0));
+                assert!(!coverage.is_executed(0x15));
+                assert_eq!(coverage.summary(&program), (7, 10, 1, 2));
+                coverage.merge(&covered_run(vec![2, 3]));
+                assert_eq!(coverage.get_branch(0x14), (2, 2));
+                // R[0] is always zero, so the branch at 17 is a jump and
+                // its direction is not counted
+                assert_eq!(coverage.get_branch(0x17), (2, 0));
+                assert_eq!(coverage.summary(&program), (10, 10, 2, 2));
+                assert!(coverage.lcov("multiply.txt", &program).contains("DA:7,4\n"));
+            }
+        }
+
+        mod tracing {
+            use super::*;
+            use crate::trace::trace::TraceBuffer;
+
+            #[test]
+            fn events_of_a_run() {
+                let mut machine = loaded_machine();
+                let mut env = ExternalEnv::new(vec![1, 5]);
+                let mut trace = TraceBuffer::new();
+                machine.run_traced(&mut env, &mut trace).unwrap();
+                let events = trace.get_events();
+                assert_eq!(&events[..2], &[TraceEvent::Fetch { pc: 0x10, word: 0x8AFF },
+                                           TraceEvent::Input { address: 0xFF, port: 0xFF, word: 1 }]);
+                assert!(matches!(&events[2], TraceEvent::Decode { pc: 0x10, word: 0x8AFF, .. }));
+                assert_eq!(&events[3..5], &[TraceEvent::MemRead { address: 0xFF, value: 1 },
+                                            TraceEvent::RegWrite { register: 0xA, value: 1 }]);
+                assert!(events.contains(&TraceEvent::Output { address: 0xFF, port: 0xFF, word: 5 }));
+                assert_eq!(events.last(), Some(&TraceEvent::Halt { pc: 0x19 }));
+                let fetches = events.iter().filter(|event| matches!(event, TraceEvent::Fetch { .. })).count();
+                assert_eq!(fetches as u64, machine.get_step_count());
+            }
+
+            #[test]
+            fn untraced_steps_match() {
+                let mut traced = loaded_machine();
+                let mut untraced = loaded_machine();
+                let mut env = ExternalEnv::new(vec![2, 3]);
+                traced.run_traced(&mut env, &mut TraceBuffer::new()).unwrap();
+                let mut env = ExternalEnv::new(vec![2, 3]);
+                untraced.run_traced(&mut env, &mut NoTrace).unwrap();
+                assert_eq!(traced.get_regs(), untraced.get_regs());
+                assert_eq!(env.get_output(), &[6]);
+            }
+        }
+
+        mod toy_java_traces {
+            use super::*;
+            use crate::toy_java::toy_java::{diff_traces, parse_trace, CoreDump, Divergence, ParsedTrace,
+                                            ToyJavaTrace};
+
+            fn traced_run(input: Vec<u16>) -> ParsedTrace {
+                let mut machine = loaded_machine();
+                let mut env = ExternalEnv::new(input);
+                let mut trace = ToyJavaTrace::new(vec![], &machine.get_regs());
+                machine.run_traced(&mut env, &mut trace).unwrap();
+                let mut text = String::from_utf8(trace.into_inner()).unwrap();
+                text.push_str(&CoreDump::new(machine.get_program_counter(), &machine.get_regs(),
+                                             &machine.get_memory()).to_string());
+                parse_trace(&text).unwrap()
+            }
+
+            #[test]
+            fn diverges_at_the_first_different_input() {
+                let ours = traced_run(vec![2, 3]);
+                assert_eq!(ours.steps.len(), 15);
+                assert_eq!(ours.steps[13].output, vec![6]);
+                assert_eq!(ours.dump.as_ref().unwrap().pc, 0x1A);
+                assert_eq!(diff_traces(&ours, &traced_run(vec![2, 3])), None);
+                assert_eq!(diff_traces(&ours, &traced_run(vec![2, 4])),
+                           Some(Divergence::Input { step: 2, pc: 0x11, ours: vec![3], reference: vec![4] }));
+            }
+        }
+
+        mod sanitizing {
+            use super::*;
+            use crate::sanitizer::sanitizer::Finding;
+
+            #[test]
+            fn clean_program() {
+                let mut machine = Machine::new();
+                machine.set_sanitizing(true);
+                machine.load(multiply_loads());
+                let mut env = ExternalEnv::new(vec![3, 4]);
+                machine.run(&mut env).unwrap();
+                assert_eq!(env.get_output(), &[12]);
+                assert!(machine.get_sanitizer().unwrap().get_findings().is_empty());
+            }
+
+            #[test]
+            fn finds_mistakes_with_their_pc() {
+                let mut machine = Machine::new();
+                machine.set_sanitizing(true);
+                machine.load(vec![
+                    ProgramLoadWord::new(0x10, 0x8A30),   // R[A] <- mem[30], never written
+                    ProgramLoadWord::new(0x11, 0x9A10),   // mem[10] <- R[A], over the first instruction
+                    ProgramLoadWord::new(0x12, 0xC020),   // goto 20, which was never loaded
+                ]);
+                let mut env = ExternalEnv::new(vec![]);
+                machine.run(&mut env).unwrap();
+                assert_eq!(machine.get_sanitizer().unwrap().get_findings(), &[
+                    Finding::UninitializedRead { pc: 0x10, address: 0x30 },
+                    Finding::WriteToCode { pc: 0x11, address: 0x10 },
+                    Finding::ExecutedData { pc: 0x20 },
+                ]);
+            }
+        }
+
+        mod fast_steps {
+            use super::*;
+
+            // the same run stepped one at a time and left to the fast path
+            fn both_ways(loads: fn() -> Vec<ProgramLoadWord>, input: Vec<u16>) -> [(Machine, ExternalEnv); 2] {
+                let mut stepped = Machine::new();
+                stepped.load(loads());
+                let mut stepped_env = ExternalEnv::new(input.clone());
+                stepped.set_program_counter(0x10);
+                stepped.set_running();
+                while stepped.get_running() {
+                    stepped.run_one_step(&mut stepped_env, false).unwrap();
+                }
+                let mut fast = Machine::new();
+                fast.load(loads());
+                let mut fast_env = ExternalEnv::new(input);
+                assert_eq!(fast.run_with_limit(&mut fast_env, 1000), Ok(StepOutcome::Halted));
+                [(stepped, stepped_env), (fast, fast_env)]
+            }
+
+            #[test]
+            fn same_as_stepping() {
+                let [(stepped, stepped_env), (fast, fast_env)] = both_ways(multiply_loads, vec![7, 6]);
+                assert_eq!(fast_env.get_output(), &[42]);
+                assert_eq!(fast_env.get_output(), stepped_env.get_output());
+                assert_eq!(fast.get_step_count(), stepped.get_step_count());
+                assert_eq!(fast.get_program_counter(), stepped.get_program_counter());
+                assert_eq!(fast.get_regs(), stepped.get_regs());
+                assert_eq!(fast.get_memory(), stepped.get_memory());
+            }
+
+            fn self_modifying() -> Vec<ProgramLoadWord> {
+                vec![
+                    ProgramLoadWord::new(0x10, 0x7B02),   // R[B] <- 2
+                    ProgramLoadWord::new(0x11, 0x7101),   // R[1] <- 1
+                    ProgramLoadWord::new(0x12, 0x8C20),   // R[C] <- mem[20]
+                    ProgramLoadWord::new(0x13, 0x1AA1),   // R[A] <- R[A] + R[1], then R[A] <- 9
+                    ProgramLoadWord::new(0x14, 0x9C13),   // mem[13] <- R[C]
+                    ProgramLoadWord::new(0x15, 0x2BB1),   // R[B] <- R[B] - R[1]
+                    ProgramLoadWord::new(0x16, 0xDB13),   // if (R[B] > 0) goto 13
+                    ProgramLoadWord::new(0x17, 0x9AFF),   // write R[A]
+                    ProgramLoadWord::new(0x18, 0x0000),
+                    ProgramLoadWord::new(0x20, 0x7A09),
+                ]
+            }
+
+            #[test]
+            fn stores_over_decoded_words() {
+                let [(stepped, stepped_env), (fast, fast_env)] = both_ways(self_modifying, vec![]);
+                // the second pass runs the word stored over the first
+                assert_eq!(fast_env.get_output(), &[9]);
+                assert_eq!(stepped_env.get_output(), &[9]);
+                assert_eq!(fast.get_step_count(), 13);
+                assert_eq!(stepped.get_step_count(), 13);
+            }
+
+            #[test]
+            fn traps_where_stepping_does() {
+                let mut machine = Machine::new();
+                machine.load(vec![ProgramLoadWord::new(0x10, 0x8AFF)]);
+                let mut env = ExternalEnv::new(vec![]);
+                assert_eq!(machine.run_with_limit(&mut env, 10),
+                           Err(MachineError::InputExhausted { pc: 0x10, instruction: 0x8AFF }));
+                assert_eq!(machine.get_program_counter(), 0x10);
+                assert!(!machine.get_running());
             }
         }
     }
-}
\ No newline at end of file
+}
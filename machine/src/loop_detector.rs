@@ -0,0 +1,179 @@
+pub mod loop_detector {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    /// Everything that decides what the machine does next, borrowed from
+    /// the machine; the device's `io_position` stands in for the state of
+    /// its input and output.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LoopState<'a> {
+        pub pc: u16,
+        pub regs: &'a [u16; 16],
+        pub memory: &'a [u16],
+        pub io_position: (usize, usize),
+    }
+
+    // the one state the detector keeps, in buffers reused each time it moves
+    #[derive(Debug)]
+    struct SavedState {
+        pc: u16,
+        regs: [u16; 16],
+        memory: Vec<u16>,
+        io_position: (usize, usize),
+        hash: u64,
+    }
+
+    impl SavedState {
+        fn matches(&self, state: &LoopState, hash: u64) -> bool {
+            hash == self.hash && state.pc == self.pc && state.io_position == self.io_position
+                && *state.regs == self.regs && state.memory == self.memory.as_slice()
+        }
+        fn copy_from(&mut self, state: &LoopState, hash: u64) {
+            self.pc = state.pc;
+            self.regs = *state.regs;
+            self.memory.clear();
+            self.memory.extend_from_slice(state.memory);
+            self.io_position = state.io_position;
+            self.hash = hash;
+        }
+    }
+
+    /// Brent's cycle detection over the sequence of machine states. The
+    /// machine is deterministic, so once a state repeats exactly the run can
+    /// never halt. Only one saved state is kept; the hash is a cheap first
+    /// test before the full comparison, so a report is never a false alarm.
+    /// The memory part of the hash is kept up to date from the one word a
+    /// step can change, so a step costs the same whatever the memory size.
+    #[derive(Debug)]
+    pub struct LoopDetector {
+        saved: SavedState,
+        // xor of word_hash over every memory word
+        memory_hash: u64,
+        power: u64,
+        length: u64,
+    }
+
+    // a word's share of the memory hash; the xor of these over all words
+    // can be updated one word at a time
+    fn word_hash(address: u16, value: u16) -> u64 {
+        // the splitmix64 finalizer
+        let mut x = ((address as u64) << 16 | value as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^ (x >> 31)
+    }
+
+    fn memory_hash_of(memory: &[u16]) -> u64 {
+        memory.iter().enumerate()
+            .fold(0, |hash, (address, value)| hash ^ word_hash(address as u16, *value))
+    }
+
+    impl LoopDetector {
+        pub fn new(initial: LoopState) -> Self {
+            let saved = SavedState {
+                pc: 0,
+                regs: [0; 16],
+                memory: Vec::with_capacity(initial.memory.len()),
+                io_position: (0, 0),
+                hash: 0,
+            };
+            let mut detector = Self {
+                saved,
+                memory_hash: memory_hash_of(initial.memory),
+                power: 1,
+                length: 0,
+            };
+            let hash = detector.hash_of(&initial);
+            detector.saved.copy_from(&initial, hash);
+            detector
+        }
+
+        fn hash_of(&self, state: &LoopState) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            (state.pc, state.regs, state.io_position, self.memory_hash).hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // Feed the state after each step, with the memory word the step
+        // may have changed and its value before the step. Returns the period
+        // of the loop in steps once a state has been seen twice. A change
+        // left out can only hide a loop, never report one.
+        pub fn observe(&mut self, state: LoopState, changed: Option<(u16, u16)>) -> Option<u64> {
+            if let Some((address, old)) = changed {
+                let new = state.memory[address as usize];
+                self.memory_hash ^= word_hash(address, old) ^ word_hash(address, new);
+            }
+            self.length += 1;
+            let hash = self.hash_of(&state);
+            if self.saved.matches(&state, hash) {
+                return Some(self.length);
+            }
+            if self.length == self.power {
+                self.saved.copy_from(&state, hash);
+                self.power *= 2;
+                self.length = 0;
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod loop_detector_tests {
+    use super::loop_detector::{LoopDetector, LoopState};
+
+    fn state(pc: u16) -> LoopState<'static> {
+        LoopState {
+            pc,
+            regs: &[0; 16],
+            memory: &[0; 256],
+            io_position: (0, 0),
+        }
+    }
+
+    #[test]
+    fn finds_period_after_a_lead_in() {
+        // 0, 1, 2, 3, 4, 5, 3, 4, 5, ...
+        let mut detector = LoopDetector::new(state(0));
+        let mut found = None;
+        for step in 1..100u16 {
+            let pc = if step < 3 { step } else { 3 + (step - 3) % 3 };
+            if let Some(period) = detector.observe(state(pc), None) {
+                found = Some(period);
+                break;
+            }
+        }
+        assert_eq!(found, Some(3));
+    }
+
+    #[test]
+    fn no_report_without_repeat() {
+        let mut detector = LoopDetector::new(state(0));
+        for pc in 1..=255u16 {
+            assert_eq!(detector.observe(state(pc), None), None);
+        }
+    }
+
+    #[test]
+    fn memory_decides_a_repeat() {
+        // the pc stays put while a word in memory changes, then stays too
+        let mut memory = vec![0u16; 256];
+        let mut detector = LoopDetector::new(LoopState { memory: &memory, ..state(0x10) });
+        memory[0x30] = 1;
+        assert_eq!(detector.observe(LoopState { memory: &memory, ..state(0x10) }, Some((0x30, 0))), None);
+        assert_eq!(detector.observe(LoopState { memory: &memory, ..state(0x10) }, None), Some(1));
+    }
+
+    #[test]
+    fn memory_changed_back_repeats() {
+        // a word set and cleared again leaves the hash where it started
+        let mut memory = vec![0u16; 256];
+        let mut detector = LoopDetector::new(LoopState { memory: &memory, ..state(0x10) });
+        memory[0x30] = 7;
+        assert_eq!(detector.observe(LoopState { memory: &memory, ..state(0x10) }, Some((0x30, 0))), None);
+        memory[0x30] = 0;
+        assert_eq!(detector.observe(LoopState { memory: &memory, ..state(0x10) }, Some((0x30, 7))), None);
+        memory[0x30] = 7;
+        assert_eq!(detector.observe(LoopState { memory: &memory, ..state(0x10) }, Some((0x30, 0))), Some(2));
+    }
+}
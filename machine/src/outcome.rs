@@ -0,0 +1,98 @@
+pub mod outcome {
+    use std::fmt;
+
+    /// How a call to `Machine::run_one_step` or `Machine::run` finished
+    /// when nothing went wrong. The last three only come from the limited
+    /// runs (`Machine::run_with_limits` and friends), which leave the
+    /// machine running so that it can be resumed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StepOutcome {
+        // the instruction executed and the machine is still running
+        Running,
+        // a halt instruction executed, or the machine was not running
+        Halted,
+        // the step budget ran out after this many steps
+        StepLimit { steps: u64 },
+        // the wall-clock deadline passed after this many steps
+        DeadlineExceeded { steps: u64 },
+        // the state at pc repeats every `period` steps, so the run cannot halt
+        InfiniteLoop { pc: u16, period: u64 },
+    }
+
+    impl fmt::Display for StepOutcome {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                StepOutcome::Running => write!(f, "Running"),
+                StepOutcome::Halted => write!(f, "Halted"),
+                StepOutcome::StepLimit { steps } => write!(f, "Step limit reached after {} steps", steps),
+                StepOutcome::DeadlineExceeded { steps } => write!(
+                    f, "Deadline exceeded after {} steps", steps),
+                StepOutcome::InfiniteLoop { pc, period } => write!(
+                    f, "Infinite loop @ pc = {:02X}x; state repeats every {} steps", pc, period),
+            }
+        }
+    }
+
+    /// A trap raised by the machine. Each variant records the pc of the
+    /// instruction that caused it and the instruction word itself; the
+    /// machine is stopped with its pc left on that instruction.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MachineError {
+        // a read from mem[FF] with no words left in the input
+        InputExhausted { pc: u16, instruction: u16 },
+        // an attempt to use an address that the instruction cannot reach
+        InvalidAddress { pc: u16, instruction: u16, address: u16 },
+        // a register past the machine's register count
+        InvalidRegister { pc: u16, instruction: u16, register: u8 },
+    }
+
+    impl MachineError {
+        pub fn pc(&self) -> u16 {
+            match self {
+                MachineError::InputExhausted { pc, .. } => *pc,
+                MachineError::InvalidAddress { pc, .. } => *pc,
+                MachineError::InvalidRegister { pc, .. } => *pc,
+            }
+        }
+        pub fn instruction(&self) -> u16 {
+            match self {
+                MachineError::InputExhausted { instruction, .. } => *instruction,
+                MachineError::InvalidAddress { instruction, .. } => *instruction,
+                MachineError::InvalidRegister { instruction, .. } => *instruction,
+            }
+        }
+    }
+
+    impl fmt::Display for MachineError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                MachineError::InputExhausted { pc, instruction } => write!(
+                    f, "Input exhausted @ pc = {:02X}x; instruction {:04X}",
+                    pc, instruction),
+                MachineError::InvalidAddress { pc, instruction, address } => write!(
+                    f, "Invalid address {:02X}x @ pc = {:02X}x; instruction {:04X}",
+                    address, pc, instruction),
+                MachineError::InvalidRegister { pc, instruction, register } => write!(
+                    f, "Invalid register R[{:X}] @ pc = {:02X}x; instruction {:04X}",
+                    register, pc, instruction),
+            }
+        }
+    }
+
+    impl std::error::Error for MachineError {}
+}
+
+#[cfg(test)]
+mod outcome_tests {
+    use super::outcome::MachineError;
+
+    #[test]
+    fn error_display() {
+        let error = MachineError::InputExhausted { pc: 0x10, instruction: 0x8AFF };
+        assert_eq!(error.to_string(), "Input exhausted @ pc = 10x; instruction 8AFF");
+        assert_eq!(error.pc(), 0x10);
+        let error = MachineError::InvalidAddress { pc: 0xFF, instruction: 0x0000, address: 0xFF };
+        assert_eq!(error.to_string(), "Invalid address FFx @ pc = FFx; instruction 0000");
+        assert_eq!(error.instruction(), 0x0000);
+    }
+}
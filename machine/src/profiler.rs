@@ -0,0 +1,156 @@
+pub mod profiler {
+    use crate::disassembler::disassembler::{disassemble_memory, pseudocode};
+    use crate::machine::{Instruction, OpCode};
+    use std::fmt::Write;
+
+    /// What a run spent its steps on: how often each address was executed,
+    /// read and written, and how often each operation ran. Steps that are
+    /// later undone with `Machine::step_back` stay counted.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Profile {
+        executions: Vec<u64>,
+        reads: Vec<u64>,
+        writes: Vec<u64>,
+        // indexed by op code
+        operations: [u64; 16],
+        steps: u64,
+    }
+
+    impl Profile {
+        pub fn new(memory_size: usize) -> Self {
+            Self {
+                executions: vec![0; memory_size],
+                reads: vec![0; memory_size],
+                writes: vec![0; memory_size],
+                operations: [0; 16],
+                steps: 0,
+            }
+        }
+        pub(crate) fn record(&mut self, pc: u16, instruction: &Instruction,
+                             read: Option<u16>, written: Option<u16>) {
+            self.executions[pc as usize] += 1;
+            let (op, ..) = instruction.get_values();
+            self.operations[*op as usize] += 1;
+            if let Some(address) = read {
+                self.reads[address as usize] += 1;
+            }
+            if let Some(address) = written {
+                self.writes[address as usize] += 1;
+            }
+            self.steps += 1;
+        }
+        pub fn get_steps(&self) -> u64 {
+            self.steps
+        }
+        pub fn get_executions(&self, address: u16) -> u64 {
+            self.executions[address as usize]
+        }
+        pub fn get_reads(&self, address: u16) -> u64 {
+            self.reads[address as usize]
+        }
+        pub fn get_writes(&self, address: u16) -> u64 {
+            self.writes[address as usize]
+        }
+        pub fn get_operation_count(&self, op: OpCode) -> u64 {
+            self.operations[op as usize]
+        }
+        // the operations that ran, most frequent first
+        pub fn operations(&self) -> Vec<(OpCode, u64)> {
+            let mut counts: Vec<_> = self.operations.iter().enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(op, count)| (num::FromPrimitive::from_usize(op).unwrap(), *count))
+                .collect();
+            counts.sort_by(|a: &(OpCode, u64), b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+            counts
+        }
+        // the `count` most executed addresses, most executed first
+        pub fn hot_spots(&self, count: usize) -> Vec<(u16, u64)> {
+            let mut spots: Vec<_> = self.executions.iter().enumerate()
+                .filter(|(_, executions)| **executions > 0)
+                .map(|(address, executions)| (address as u16, *executions))
+                .collect();
+            spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            spots.truncate(count);
+            spots
+        }
+        pub fn merge(&mut self, other: &Profile) {
+            for (mine, theirs) in [(&mut self.executions, &other.executions),
+                                   (&mut self.reads, &other.reads),
+                                   (&mut self.writes, &other.writes)] {
+                for (count, more) in mine.iter_mut().zip(theirs) {
+                    *count += more;
+                }
+            }
+            for (count, more) in self.operations.iter_mut().zip(other.operations) {
+                *count += more;
+            }
+            self.steps += other.steps;
+        }
+
+        fn percent(&self, count: u64) -> f64 {
+            if self.steps == 0 { 0.0 } else { 100.0 * count as f64 / self.steps as f64 }
+        }
+
+        // The program listing with each line's execution count, share of the
+        // steps and memory reads and writes, then the hot spots and the
+        // operation counts. A 256 word memory is listed as the disassembler
+        // lists it; any other is listed only where it was used.
+        pub fn report(&self, memory: &[u16], hot_spots: usize) -> String {
+            let mut text = String::new();
+            let _ = writeln!(text, "{} steps", self.steps);
+            let _ = writeln!(text, "   count       %  reads writes");
+            let lines: Vec<(u16, String)> = match <&[u16; 256]>::try_from(memory) {
+                Ok(memory) => disassemble_memory(memory).lines().iter()
+                    .map(|line| (line.address as u16,
+                                 format!("{:02X}: {:04X}   {}", line.address, line.word, line.text)))
+                    .collect(),
+                Err(_) => (0..memory.len())
+                    .filter(|&address| self.executions[address] + self.reads[address] + self.writes[address] > 0)
+                    .map(|address| (address as u16, format!("{:02X}: {:04X}   {}", address, memory[address],
+                                                            pseudocode(&Instruction::decode(memory[address])))))
+                    .collect(),
+            };
+            for (address, line) in lines {
+                let executions = self.get_executions(address);
+                let _ = writeln!(text, "{:>8} {:>6.1}% {:>6} {:>6}  {}", executions, self.percent(executions),
+                                 self.get_reads(address), self.get_writes(address), line);
+            }
+            let _ = writeln!(text, "hot spots:");
+            for (address, executions) in self.hot_spots(hot_spots) {
+                let _ = writeln!(text, "  {:02X}: {} ({:.1}%)", address, executions, self.percent(executions));
+            }
+            let _ = writeln!(text, "operations:");
+            for (op, count) in self.operations() {
+                let _ = writeln!(text, "  {:?}: {} ({:.1}%)", op, count, self.percent(count));
+            }
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod profiler_tests {
+    use super::profiler::Profile;
+    use crate::machine::{Instruction, OpCode};
+
+    #[test]
+    fn counts_and_merges() {
+        let mut profile = Profile::new(256);
+        let load = Instruction::decode(0x8A30);
+        let store = Instruction::decode(0x9A31);
+        profile.record(0x10, &load, Some(0x30), None);
+        profile.record(0x11, &store, None, Some(0x31));
+        profile.record(0x10, &load, Some(0x30), None);
+        assert_eq!(profile.get_steps(), 3);
+        assert_eq!(profile.get_executions(0x10), 2);
+        assert_eq!(profile.get_reads(0x30), 2);
+        assert_eq!(profile.get_writes(0x31), 1);
+        assert_eq!(profile.operations(), vec![(OpCode::Load, 2), (OpCode::Store, 1)]);
+        assert_eq!(profile.hot_spots(1), vec![(0x10, 2)]);
+        let copy = profile.clone();
+        profile.merge(&copy);
+        assert_eq!(profile.get_steps(), 6);
+        assert_eq!(profile.get_operation_count(OpCode::Load), 4);
+        assert_eq!(profile.get_reads(0x30), 4);
+    }
+}
@@ -9,6 +9,12 @@ pub mod program_reader {
         lines: Vec<String>,
     }
 
+    impl Default for ProgramReader {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl ProgramReader {
         pub fn new() -> Self {
             let lines: Vec<String> = vec![];
@@ -34,6 +40,13 @@ pub mod program_reader {
                 .filter_map(|line| self.parse_line(line))
                 .collect()
         }
+        // each load with the line of the file it came from, counting from 1
+        pub fn parse_with_lines(&self) -> Vec<(usize, ProgramLoadWord)> {
+            self.lines.iter().enumerate()
+                .filter_map(|(index, line)| Some((index + 1, self.parse_line(line)?)))
+                .collect()
+        }
+        #[allow(clippy::ptr_arg)]
         fn parse_line(&self, line: &String) -> Option<ProgramLoadWord> {
             let re = Regex::new(r"^([[:xdigit:]]{2}): *([[:xdigit:]]{4})").unwrap();
             let flag = re.is_match(line);
@@ -0,0 +1,113 @@
+pub mod sanitizer {
+    use std::fmt;
+
+    const LOADED: u8 = 1;
+    const WRITTEN: u8 = 2;
+    const READ: u8 = 4;
+    const EXECUTED: u8 = 8;
+
+    /// Something a program did to memory that is usually a mistake, with
+    /// the pc of the instruction that did it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Finding {
+        // a word neither loaded with the program nor written since
+        UninitializedRead { pc: u16, address: u16 },
+        // a word that was not loaded, or was used as data, run as an instruction
+        ExecutedData { pc: u16 },
+        // a store over a word that has run as an instruction
+        WriteToCode { pc: u16, address: u16 },
+    }
+
+    impl fmt::Display for Finding {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Finding::UninitializedRead { pc, address } =>
+                    write!(f, "pc {:02X}: read of mem[{:02X}], which was never loaded or written", pc, address),
+                Finding::ExecutedData { pc } =>
+                    write!(f, "pc {:02X}: executed a data word", pc),
+                Finding::WriteToCode { pc, address } =>
+                    write!(f, "pc {:02X}: wrote over the instruction at mem[{:02X}]", pc, address),
+            }
+        }
+    }
+
+    /// Shadow memory: what has happened to each word, and the findings so
+    /// far, each reported once. It learns which words are the program when
+    /// the program is loaded, so turn it on before loading.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Sanitizer {
+        shadow: Vec<u8>,
+        findings: Vec<Finding>,
+    }
+
+    impl Sanitizer {
+        pub fn new(memory_size: usize) -> Self {
+            Self { shadow: vec![0; memory_size], findings: vec![] }
+        }
+        pub fn get_findings(&self) -> &[Finding] {
+            &self.findings
+        }
+        pub fn is_loaded(&self, address: u16) -> bool {
+            self.shadow[address as usize] & LOADED != 0
+        }
+        pub fn is_executed(&self, address: u16) -> bool {
+            self.shadow[address as usize] & EXECUTED != 0
+        }
+
+        pub(crate) fn loaded(&mut self, address: u16) {
+            self.shadow[address as usize] |= LOADED;
+        }
+        // A step at pc, before it runs. Reads and writes of device addresses
+        // are i/o, not memory, and are left out by the caller.
+        pub(crate) fn step(&mut self, pc: u16, read: Option<u16>, written: Option<u16>) {
+            let flags = self.shadow[pc as usize];
+            if flags & LOADED == 0 || flags & (WRITTEN | READ) != 0 {
+                self.report(Finding::ExecutedData { pc });
+            }
+            self.shadow[pc as usize] |= EXECUTED;
+            if let Some(address) = read {
+                if self.shadow[address as usize] & (LOADED | WRITTEN) == 0 {
+                    self.report(Finding::UninitializedRead { pc, address });
+                }
+                self.shadow[address as usize] |= READ;
+            }
+            if let Some(address) = written {
+                if self.shadow[address as usize] & EXECUTED != 0 {
+                    self.report(Finding::WriteToCode { pc, address });
+                }
+                self.shadow[address as usize] |= WRITTEN;
+            }
+        }
+        fn report(&mut self, finding: Finding) {
+            if !self.findings.contains(&finding) {
+                self.findings.push(finding);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod sanitizer_tests {
+    use super::sanitizer::{Finding, Sanitizer};
+
+    #[test]
+    fn finds_each_kind_once() {
+        let mut sanitizer = Sanitizer::new(256);
+        for address in 0x10..0x13 {
+            sanitizer.loaded(address);
+        }
+        sanitizer.step(0x10, Some(0x30), None);
+        sanitizer.step(0x10, Some(0x30), None);
+        sanitizer.step(0x11, None, Some(0x10));
+        sanitizer.step(0x10, None, None);
+        sanitizer.step(0x13, None, None);
+        assert_eq!(sanitizer.get_findings(), &[
+            Finding::UninitializedRead { pc: 0x10, address: 0x30 },
+            Finding::WriteToCode { pc: 0x11, address: 0x10 },
+            Finding::ExecutedData { pc: 0x10 },
+            Finding::ExecutedData { pc: 0x13 },
+        ]);
+        assert_eq!(sanitizer.get_findings()[0].to_string(),
+                   "pc 10: read of mem[30], which was never loaded or written");
+    }
+}
@@ -0,0 +1,228 @@
+pub mod snapshot {
+    use crate::config::config::{ConfigError, MachineConfig};
+    use crate::interrupt::interrupt::InterruptController;
+    use std::fmt;
+
+    // bump whenever a field is added, removed or changes meaning; 2 widened
+    // pc to an address in a configured memory, 3 added the step count and
+    // interrupt state
+    pub const SNAPSHOT_VERSION: u32 = 3;
+
+    /// The full state of a `Machine` and its device at one moment, as
+    /// produced by `Machine::snapshot` and consumed by `Machine::restore`.
+    /// The i/o words are empty for a device that does not keep them. With
+    /// the `serde` feature it can be written out as JSON and read back.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MachineSnapshot {
+        pub version: u32,
+        pub pc: u16,
+        pub regs: Vec<u16>,
+        pub memory: Vec<u16>,
+        pub running: bool,
+        pub steps: u64,
+        // None when the machine has no interrupts installed
+        pub interrupts: Option<InterruptController>,
+        // input words not yet read by the program
+        pub input: Vec<u16>,
+        // output words written so far
+        pub output: Vec<u16>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum SnapshotError {
+        UnsupportedVersion(u32),
+        WrongRegisterCount { found: usize, expected: usize },
+        WrongMemorySize { found: usize, expected: usize },
+        PcOutOfRange { pc: u16, memory_size: usize },
+        // the saved interrupts do not fit the machine
+        Interrupts(ConfigError),
+        // the snapshot has i/o words the device cannot take back
+        DeviceCannotRestore,
+    }
+
+    impl MachineSnapshot {
+        // check the snapshot fits a machine of this shape
+        pub(crate) fn validate(&self, config: &MachineConfig) -> Result<(), SnapshotError> {
+            if self.version != SNAPSHOT_VERSION {
+                return Err(SnapshotError::UnsupportedVersion(self.version));
+            }
+            if self.regs.len() != config.register_count {
+                return Err(SnapshotError::WrongRegisterCount {
+                    found: self.regs.len(), expected: config.register_count });
+            }
+            if self.memory.len() != config.memory_size() {
+                return Err(SnapshotError::WrongMemorySize {
+                    found: self.memory.len(), expected: config.memory_size() });
+            }
+            if self.pc as usize >= config.memory_size() {
+                return Err(SnapshotError::PcOutOfRange { pc: self.pc, memory_size: config.memory_size() });
+            }
+            if let Some(interrupts) = &self.interrupts {
+                config.validate_interrupts(interrupts.get_config()).map_err(SnapshotError::Interrupts)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for SnapshotError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                SnapshotError::UnsupportedVersion(version) => write!(
+                    f, "Snapshot version {} is not supported (expected {})",
+                    version, SNAPSHOT_VERSION),
+                SnapshotError::WrongRegisterCount { found, expected } => write!(
+                    f, "Snapshot has {} registers (expected {})", found, expected),
+                SnapshotError::WrongMemorySize { found, expected } => write!(
+                    f, "Snapshot has {} memory words (expected {})", found, expected),
+                SnapshotError::PcOutOfRange { pc, memory_size } => write!(
+                    f, "Snapshot pc {:02X} is past the end of {} memory words", pc, memory_size),
+                SnapshotError::Interrupts(error) => write!(f, "Snapshot interrupts: {}", error),
+                SnapshotError::DeviceCannotRestore => write!(
+                    f, "Snapshot has i/o the device cannot restore"),
+            }
+        }
+    }
+
+    impl std::error::Error for SnapshotError {}
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::snapshot::{MachineSnapshot, SnapshotError, SNAPSHOT_VERSION};
+    use crate::config::config::ConfigError;
+    use crate::device::device::StreamDevice;
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::interrupt::interrupt::{InterruptConfig, InterruptController};
+    use crate::machine::Machine;
+    use crate::test_programs::{loads, MULTIPLY};
+    use std::io::Cursor;
+
+    fn multiply_machine() -> Machine {
+        let mut machine = Machine::new();
+        machine.load(loads(&MULTIPLY));
+        machine.set_program_counter(0x10);
+        machine.set_running();
+        machine
+    }
+
+    #[test]
+    fn restore_continues_mid_run() {
+        let mut env = ExternalEnv::new(vec![3, 4]);
+        let mut machine = multiply_machine();
+        for _ in 0..6 {
+            machine.run_one_step(&mut env, false).unwrap();
+        }
+        let snapshot = machine.snapshot(&env);
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.pc, 0x16);
+        assert!(snapshot.input.is_empty());
+
+        let mut restored = Machine::new();
+        let mut restored_env = ExternalEnv::new(vec![]);
+        restored.restore(&snapshot, &mut restored_env).unwrap();
+        assert_eq!(restored.snapshot(&restored_env), snapshot);
+        while restored.get_running() {
+            restored.run_one_step(&mut restored_env, false).unwrap();
+        }
+        while machine.get_running() {
+            machine.run_one_step(&mut env, false).unwrap();
+        }
+        assert_eq!(restored.snapshot(&restored_env), machine.snapshot(&env));
+        assert_eq!(restored_env.output_for_display(), "12(000C)");
+    }
+
+    #[test]
+    fn snapshot_keeps_pending_input_and_output() {
+        let mut env = ExternalEnv::new(vec![1, 2, 3]);
+        env.put_word(9);
+        let machine = Machine::new();
+        let snapshot = machine.snapshot(&env);
+        assert_eq!(snapshot.input, vec![1, 2, 3]);
+        assert_eq!(snapshot.output, vec![9]);
+    }
+
+    #[test]
+    fn restore_rejects_bad_snapshots() {
+        let mut env = ExternalEnv::new(vec![]);
+        let mut machine = Machine::new();
+        let good = machine.snapshot(&env);
+        let bad = MachineSnapshot { version: 99, ..good.clone() };
+        assert_eq!(machine.restore(&bad, &mut env), Err(SnapshotError::UnsupportedVersion(99)));
+        let bad = MachineSnapshot { regs: vec![0; 8], ..good.clone() };
+        assert_eq!(machine.restore(&bad, &mut env), Err(SnapshotError::WrongRegisterCount { found: 8, expected: 16 }));
+        let bad = MachineSnapshot { memory: vec![0; 16], ..good.clone() };
+        assert_eq!(machine.restore(&bad, &mut env), Err(SnapshotError::WrongMemorySize { found: 16, expected: 256 }));
+        let bad = MachineSnapshot { pc: 0x100, ..good };
+        assert_eq!(machine.restore(&bad, &mut env), Err(SnapshotError::PcOutOfRange { pc: 0x100, memory_size: 256 }));
+    }
+
+    #[test]
+    fn restore_keeps_step_count_and_interrupts() {
+        let mut env = ExternalEnv::new(vec![3, 4]);
+        let mut machine = multiply_machine();
+        machine.set_interrupts(InterruptConfig { vector: 0x30, saved_pc_register: 0xE, control_address: 0xFC })
+            .unwrap();
+        // pending until the program enables interrupts
+        machine.interrupts_mut().unwrap().raise();
+        for _ in 0..2 {
+            machine.run_one_step(&mut env, false).unwrap();
+        }
+        let snapshot = machine.snapshot(&env);
+        assert_eq!(snapshot.steps, 2);
+        assert_eq!(snapshot.interrupts, machine.get_interrupts().copied());
+
+        let mut restored = Machine::new();
+        let mut restored_env = ExternalEnv::new(vec![]);
+        restored.restore(&snapshot, &mut restored_env).unwrap();
+        assert_eq!(restored.get_step_count(), 2);
+        assert_eq!(restored.get_interrupts(), machine.get_interrupts());
+        assert!(restored.get_interrupts().unwrap().is_pending());
+
+        // a snapshot without interrupts takes them away
+        let plain = Machine::new().snapshot(&env);
+        restored.restore(&plain, &mut restored_env).unwrap();
+        assert_eq!(restored.get_interrupts(), None);
+        assert_eq!(restored.get_step_count(), 0);
+
+        let config = InterruptConfig { vector: 0x30, saved_pc_register: 0, control_address: 0xFC };
+        let bad = MachineSnapshot { interrupts: Some(InterruptController::new(config)), ..plain };
+        assert_eq!(restored.restore(&bad, &mut restored_env),
+                   Err(SnapshotError::Interrupts(ConfigError::SavedPcRegister(0))));
+    }
+
+    #[test]
+    fn snapshot_of_any_device() {
+        let mut device = StreamDevice::new(Cursor::new("0003\n0004\n"), vec![]);
+        let mut machine = multiply_machine();
+        machine.run_one_step(&mut device, false).unwrap();
+        let snapshot = machine.snapshot(&device);
+        assert!(snapshot.input.is_empty() && snapshot.output.is_empty());
+        let mut restored = Machine::new();
+        restored.restore(&snapshot, &mut device).unwrap();
+        while restored.get_running() {
+            restored.run_one_step(&mut device, false).unwrap();
+        }
+        let (_, written) = device.into_inner();
+        assert_eq!(String::from_utf8(written).unwrap(), "000C\n");
+
+        // i/o words cannot be put back into a stream
+        let mut env = ExternalEnv::new(vec![5]);
+        let snapshot = machine.snapshot(&env);
+        let mut device = StreamDevice::new(Cursor::new(""), vec![]);
+        assert_eq!(restored.restore(&snapshot, &mut device), Err(SnapshotError::DeviceCannotRestore));
+        assert_eq!(restored.restore(&snapshot, &mut env), Ok(()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let mut env = ExternalEnv::new(vec![3, 4]);
+        let mut machine = multiply_machine();
+        machine.run_one_step(&mut env, false).unwrap();
+        let snapshot = machine.snapshot(&env);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let read_back: MachineSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(read_back, snapshot);
+    }
+}
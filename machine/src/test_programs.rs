@@ -0,0 +1,13 @@
+// Programs shared by the tests of several modules, as (address, word)
+// pairs.
+
+use crate::machine::ProgramLoadWord;
+
+// reads a and b, then writes a * b by repeated addition
+pub(crate) const MULTIPLY: [(u8, u16); 10] = [
+    (0x10, 0x8AFF), (0x11, 0x8BFF), (0x12, 0x7C00), (0x13, 0x7101), (0x14, 0xCA18),
+    (0x15, 0x1CCB), (0x16, 0x2AA1), (0x17, 0xC014), (0x18, 0x9CFF), (0x19, 0x0000)];
+
+pub(crate) fn loads(words: &[(u8, u16)]) -> Vec<ProgramLoadWord> {
+    words.iter().map(|(address, word)| ProgramLoadWord::new(*address, *word)).collect()
+}
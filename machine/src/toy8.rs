@@ -0,0 +1,524 @@
+pub mod toy8 {
+    use crate::config::config::{ConfigError, MachineConfig};
+    use crate::device::device::{Device, IO_ADDRESS};
+    use crate::machine::{RunLimits, DEADLINE_CHECK_INTERVAL};
+    use crate::outcome::outcome::{MachineError, StepOutcome};
+    use crate::trace::trace::{NoTrace, TextTrace, TraceEvent, TraceSink};
+    use regex::Regex;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+    use std::time::Instant;
+
+    #[derive(FromPrimitive, ToPrimitive)]
+    #[derive(Debug, Clone, Copy)]
+    #[derive(PartialEq, Eq, Hash)]
+    #[repr(u8)]
+    pub enum Toy8OpCode {
+        Halt, Add, Subtract, And, Load, Store, BranchZero, BranchPositive,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Toy8Instruction {
+        op: Toy8OpCode,
+        // really u5
+        address: u8,
+    }
+
+    impl Toy8Instruction {
+        // panics when the address does not fit in five bits
+        pub fn new(op: Toy8OpCode, address: u8) -> Self {
+            assert!(address < 32, "address out of range");
+            Self { op, address }
+        }
+        // every word is a canonical instruction
+        pub fn decode(word: u8) -> Self {
+            let op: Toy8OpCode = num::FromPrimitive::from_u8(word >> 5).unwrap();
+            Self { op, address: word & 0x1F }
+        }
+        pub fn encode(&self) -> u8 {
+            (self.op as u8) << 5 | self.address
+        }
+        pub fn get_values(&self) -> (&Toy8OpCode, u8) {
+            (&self.op, self.address)
+        }
+        pub fn format_for_pp(&self, accumulator: u8, memory: &[u8], io_address: u16) -> String {
+            let (op, addr) = (self.op, self.address);
+            let is_io = addr as u16 == io_address;
+            let mc = memory[addr as usize % memory.len()];
+            let binary = |symbol: &str| format!(
+                "Op: {:?} - A={}({:02X}) {} mem[{:02X}]={}({:02X})",
+                op, accumulator as i8, accumulator, symbol, addr, mc as i8, mc);
+            match op {
+                Toy8OpCode::Halt => format!("Op: {:?}", op),
+                Toy8OpCode::Add => binary("+"),
+                Toy8OpCode::Subtract => binary("-"),
+                Toy8OpCode::And => binary("&"),
+                Toy8OpCode::Load if is_io => format!(
+                    "Op: {:?} - input to A via mem[{:02X}]", op, addr),
+                Toy8OpCode::Load => format!(
+                    "Op: {:?} - A set from mem[{:02X}]={}({:02X})", op, addr, mc as i8, mc),
+                Toy8OpCode::Store if is_io => format!(
+                    "Op: {:?} - output from A via mem[{:02X}]", op, addr),
+                Toy8OpCode::Store => format!(
+                    "Op: {:?} - mem[{:02X}] set from A={}({:02X})", op, addr, accumulator as i8, accumulator),
+                Toy8OpCode::BranchZero => format!(
+                    "Op: {:?} - pc becomes {:02X} when A={}({:02X}) == 0", op, addr, accumulator as i8, accumulator),
+                Toy8OpCode::BranchPositive => format!(
+                    "Op: {:?} - pc becomes {:02X} when A={}({:02X}) > 0", op, addr, accumulator as i8, accumulator),
+            }
+        }
+        // the instruction as the pseudocode column of a TOY-8 listing writes it
+        pub fn pseudocode(&self, io_address: u16) -> String {
+            let addr = self.address;
+            let is_io = addr as u16 == io_address;
+            match self.op {
+                Toy8OpCode::Halt => "halt".into(),
+                Toy8OpCode::Add => format!("A <- A + mem[{:02X}]", addr),
+                Toy8OpCode::Subtract => format!("A <- A - mem[{:02X}]", addr),
+                Toy8OpCode::And => format!("A <- A & mem[{:02X}]", addr),
+                Toy8OpCode::Load if is_io => "read A".into(),
+                Toy8OpCode::Load => format!("A <- mem[{:02X}]", addr),
+                Toy8OpCode::Store if is_io => "write A".into(),
+                Toy8OpCode::Store => format!("mem[{:02X}] <- A", addr),
+                Toy8OpCode::BranchZero => format!("if (A == 0) goto {:02X}", addr),
+                Toy8OpCode::BranchPositive => format!("if (A > 0) goto {:02X}", addr),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Toy8LoadWord {
+        address: u8,
+        content: u8,
+    }
+
+    impl Toy8LoadWord {
+        pub fn new(address: u8, content: u8) -> Self {
+            Self { address, content }
+        }
+        pub fn get_address(&self) -> u8 {
+            self.address
+        }
+        pub fn get_content(&self) -> u8 {
+            self.content
+        }
+    }
+
+    /// Reads TOY-8 programs written as `XX: YY` lines, two hex digits of
+    /// address and two of word; anything after the word is a comment and
+    /// other lines are skipped.
+    #[derive(Default)]
+    pub struct Toy8Reader {
+        lines: Vec<String>,
+    }
+
+    impl Toy8Reader {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn load_from_file(&mut self, filename: impl AsRef<Path>) {
+            let file = File::open(filename).expect("no such file");
+            self.lines = BufReader::new(file).lines()
+                .map(|l| l.expect("Could not parse line"))
+                .collect();
+        }
+        pub fn load_from_vec(&mut self, lines: Vec<String>) {
+            self.lines = lines;
+        }
+        pub fn parse(&self) -> Vec<Toy8LoadWord> {
+            // the word must not run on into a third hex digit, which would
+            // make it a TOY word
+            let re = Regex::new(r"^([[:xdigit:]]{2}): *([[:xdigit:]]{2})(?:[^[:xdigit:]]|$)").unwrap();
+            self.lines.iter()
+                .filter_map(|line| {
+                    let cap = re.captures(line)?;
+                    let address = u8::from_str_radix(&cap[1], 16).unwrap();
+                    let content = u8::from_str_radix(&cap[2], 16).unwrap();
+                    Some(Toy8LoadWord::new(address, content))
+                })
+                .collect()
+        }
+    }
+
+    // a listing of the loaded words in the reader's format, so that it reads
+    // back as the same program
+    pub fn listing(loads: &[Toy8LoadWord], io_address: u16) -> String {
+        loads.iter()
+            .map(|load| format!("{:02X}: {:02X}   {}\n", load.address, load.content,
+                                Toy8Instruction::decode(load.content).pseudocode(io_address)))
+            .collect()
+    }
+
+    /// TOY-8, the 8-bit cousin of TOY. Each word is one instruction or one
+    /// 8-bit two's complement value. There is a single register, the
+    /// accumulator A, and every instruction is a 3-bit op code followed by
+    /// a 5-bit address:
+    ///
+    /// ```text
+    /// 0 halt                          4 load             A <- mem[addr]
+    /// 1 add       A <- A + mem[addr]  5 store            mem[addr] <- A
+    /// 2 subtract  A <- A - mem[addr]  6 branch zero      if (A == 0) pc <- addr
+    /// 3 and       A <- A & mem[addr]  7 branch positive  if (A > 0) pc <- addr
+    /// ```
+    ///
+    /// Loads from and stores to the i/o address, mem[1F] on the standard
+    /// profile, read and write the device as mem[FF] does on TOY.
+    ///
+    /// This table is the crate's own definition of TOY-8, modelled on TOY;
+    /// it is not taken from a published Princeton reference, so programs
+    /// written for another TOY-8 may not run unchanged. The cases in
+    /// toy8_conformance_tests.rs check each row of it.
+    #[derive(Debug)]
+    pub struct Toy8Machine {
+        config: MachineConfig,
+        pc: u16,
+        accumulator: u8,
+        memory: Vec<u8>,
+        running: bool,
+        steps: u64,
+    }
+
+    impl Default for Toy8Machine {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Toy8Machine {
+        pub fn new() -> Self {
+            Self::build(MachineConfig::toy8())
+        }
+        // TOY-8 needs 8 bit words and a single register; its addresses reach
+        // the first 32 words, but the pc can run on through a larger memory
+        pub fn with_config(config: MachineConfig) -> Result<Self, ConfigError> {
+            config.validate(8, 0)?;
+            if config.address_bits < 5 {
+                return Err(ConfigError::AddressBits(config.address_bits));
+            }
+            Ok(Self::build(config))
+        }
+        fn build(config: MachineConfig) -> Self {
+            Self {
+                config,
+                pc: 0,
+                accumulator: 0,
+                memory: vec![0; config.memory_size()],
+                running: false,
+                steps: 0,
+            }
+        }
+        pub fn get_config(&self) -> &MachineConfig {
+            &self.config
+        }
+        pub fn load(&mut self, loads: Vec<Toy8LoadWord>) {
+            let mask = self.config.address_mask();
+            for word in loads {
+                self.memory[(word.address as u16 & mask) as usize] = word.content
+            }
+        }
+
+        pub fn set_running(&mut self) { self.running = true; }
+        pub fn reset_running(&mut self) { self.running = false; }
+        pub fn get_running(&self) -> bool { self.running }
+        pub fn set_program_counter(&mut self, pc: u16) {
+            self.pc = pc & self.config.address_mask();
+        }
+        pub fn get_program_counter(&self) -> u16 {
+            self.pc
+        }
+        pub fn get_step_count(&self) -> u64 {
+            self.steps
+        }
+        pub fn get_accumulator(&self) -> u8 {
+            self.accumulator
+        }
+        pub fn set_accumulator(&mut self, value: u8) {
+            self.accumulator = value;
+        }
+        pub fn get_memory(&self) -> Vec<u8> {
+            self.memory.clone()
+        }
+        pub fn set_memory_word(&mut self, index: usize, value: u8) {
+            assert!(index < self.memory.len());
+            self.memory[index] = value;
+        }
+        pub fn get_memory_word(&self, index: usize) -> u8 {
+            assert!(index < self.memory.len());
+            self.memory[index]
+        }
+        pub fn current_instruction_pp(&self, word: u8) -> String {
+            Toy8Instruction::decode(word)
+                .format_for_pp(self.accumulator, &self.memory, self.config.io_address)
+        }
+
+        pub fn run(&mut self, env: &mut dyn Device) -> Result<StepOutcome, MachineError> {
+            self.run_traced(env, &mut TextTrace::stdout().with_word_bits(8))
+        }
+        pub fn run_traced(&mut self, env: &mut dyn Device, trace: &mut dyn TraceSink)
+            -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(self.config.entry_pc);
+            self.set_running();
+            while self.get_running() {
+                self.run_one_step_traced(env, trace)?;
+            }
+            Ok(StepOutcome::Halted)
+        }
+
+        // Like run, but without tracing and stopping at the step limit or
+        // deadline; loops are not detected.
+        pub fn run_with_limits(&mut self, env: &mut dyn Device, limits: &RunLimits)
+            -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(self.config.entry_pc);
+            self.set_running();
+            let start = self.steps;
+            while self.get_running() {
+                let taken = self.steps - start;
+                if limits.max_steps.is_some_and(|max_steps| taken >= max_steps) {
+                    return Ok(StepOutcome::StepLimit { steps: taken });
+                }
+                if let Some(deadline) = limits.deadline {
+                    if taken.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                        return Ok(StepOutcome::DeadlineExceeded { steps: taken });
+                    }
+                }
+                self.run_one_step(env, false)?;
+            }
+            Ok(StepOutcome::Halted)
+        }
+
+        fn trap(&mut self, pc: u16, error: MachineError) -> Result<StepOutcome, MachineError> {
+            self.set_program_counter(pc);
+            self.reset_running();
+            Err(error)
+        }
+
+        // One step, printing its trace when print_trace is set.
+        pub fn run_one_step(&mut self, env: &mut dyn Device, print_trace: bool)
+            -> Result<StepOutcome, MachineError> {
+            if print_trace {
+                self.run_one_step_traced(env, &mut TextTrace::stdout().with_word_bits(8))
+            } else {
+                self.run_one_step_traced(env, &mut NoTrace)
+            }
+        }
+        // A step sending the same events as Machine::run_one_step_traced, the
+        // accumulator as register 0 and words widened to 16 bits.
+        pub fn run_one_step_traced(&mut self, env: &mut dyn Device, trace: &mut dyn TraceSink)
+            -> Result<StepOutcome, MachineError> {
+            if !self.get_running() {
+                return Ok(StepOutcome::Halted)
+            }
+            let pc = self.pc;
+            let word = self.memory[pc as usize];
+            let tracing = trace.is_enabled();
+            if tracing {
+                trace.event(TraceEvent::Fetch { pc, word: word.into() });
+            }
+            if pc == self.config.io_address {
+                return self.trap(pc, MachineError::InvalidAddress {
+                    pc, instruction: word.into(), address: pc });
+            }
+            let instruction = Toy8Instruction::decode(word);
+            let (op, address) = (instruction.op, instruction.address);
+            let is_io = address as u16 == self.config.io_address;
+            if op == Toy8OpCode::Load && is_io {
+                let word_read = match env.read(IO_ADDRESS) {
+                    Some(value) => value as u8,
+                    None => return self.trap(pc, MachineError::InputExhausted {
+                        pc, instruction: word.into() }),
+                };
+                self.memory[address as usize] = word_read;
+                if tracing {
+                    trace.event(TraceEvent::Input { address: address.into(), port: IO_ADDRESS, word: word_read.into() });
+                }
+            }
+            let reads_memory = matches!(op, Toy8OpCode::Add | Toy8OpCode::Subtract | Toy8OpCode::And | Toy8OpCode::Load);
+            if tracing {
+                trace.event(TraceEvent::Decode { pc, word: word.into(), text: self.current_instruction_pp(word) });
+                if reads_memory {
+                    trace.event(TraceEvent::MemRead { address: address.into(), value: self.memory[address as usize].into() });
+                }
+            }
+            self.set_program_counter(pc + 1);
+            let value = self.memory[address as usize];
+            match op {
+                Toy8OpCode::Halt => {},
+                Toy8OpCode::Add => self.accumulator = self.accumulator.wrapping_add(value),
+                Toy8OpCode::Subtract => self.accumulator = self.accumulator.wrapping_sub(value),
+                Toy8OpCode::And => self.accumulator &= value,
+                Toy8OpCode::Load => self.accumulator = value,
+                Toy8OpCode::Store => self.memory[address as usize] = self.accumulator,
+                Toy8OpCode::BranchZero => if self.accumulator == 0 { self.pc = address.into() },
+                Toy8OpCode::BranchPositive => if (self.accumulator as i8) > 0 { self.pc = address.into() },
+            }
+            if tracing && reads_memory {
+                trace.event(TraceEvent::RegWrite { register: 0, value: self.accumulator.into() });
+            }
+            if tracing && op == Toy8OpCode::Store {
+                trace.event(TraceEvent::MemWrite { address: address.into(), value: self.accumulator.into() });
+            }
+            if op == Toy8OpCode::Store && is_io {
+                env.write(IO_ADDRESS, self.accumulator.into());
+                if tracing {
+                    trace.event(TraceEvent::Output { address: address.into(), port: IO_ADDRESS, word: self.accumulator.into() });
+                }
+            }
+            if tracing && op == Toy8OpCode::Halt {
+                trace.event(TraceEvent::Halt { pc });
+            }
+            self.steps += 1;
+            env.tick();
+            if op == Toy8OpCode::Halt {
+                self.reset_running();
+                Ok(StepOutcome::Halted)
+            } else {
+                Ok(StepOutcome::Running)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod toy8_tests {
+    use super::toy8::*;
+    use crate::config::config::{ConfigError, MachineConfig};
+    use crate::external_env::external_env::ExternalEnv;
+    use crate::outcome::outcome::{MachineError, StepOutcome};
+    use crate::trace::trace::{TraceBuffer, TraceEvent};
+
+    // multiply two inputs by repeated addition; mem[1A] is zero and
+    // mem[1E] is one
+    const MULTIPLY: &str = "\
+00: 9F   read A
+01: BC   mem[1C] <- A
+02: 9F   read A
+03: BD   mem[1D] <- A
+04: 9A   A <- mem[1A]
+05: BB   mem[1B] <- A
+06: 9C   A <- mem[1C]
+07: CF   if (A == 0) goto 0F
+08: 5E   A <- A - mem[1E]
+09: BC   mem[1C] <- A
+0A: 9B   A <- mem[1B]
+0B: 3D   A <- A + mem[1D]
+0C: BB   mem[1B] <- A
+0D: 9A   A <- mem[1A]
+0E: C6   if (A == 0) goto 06
+0F: 9B   A <- mem[1B]
+10: BF   write A
+11: 00   halt
+1E: 01
+";
+
+    fn reader(text: &str) -> Toy8Reader {
+        let mut reader = Toy8Reader::new();
+        reader.load_from_vec(text.lines().map(String::from).collect());
+        reader
+    }
+
+    #[test]
+    fn round_trip_every_word() {
+        for word in 0..=255u8 {
+            assert_eq!(Toy8Instruction::decode(word).encode(), word);
+        }
+        assert_eq!(Toy8Instruction::new(Toy8OpCode::Store, 0x1F).encode(), 0xBF);
+    }
+
+    #[test]
+    fn reader_skips_toy_words_and_comments() {
+        let loads = reader("00: 9F read\n10: 8AFF\n; 01: 00\n1E:01").parse();
+        let loads: Vec<_> = loads.iter().map(|load| (load.get_address(), load.get_content())).collect();
+        assert_eq!(loads, vec![(0x00, 0x9F), (0x1E, 0x01)]);
+    }
+
+    #[test]
+    fn listing_reads_back() {
+        let loads = reader(MULTIPLY).parse();
+        let text = listing(&loads, 0x1F);
+        assert_eq!(text.lines().next(), Some("00: 9F   read A"));
+        assert_eq!(reader(&text).parse().len(), loads.len());
+    }
+
+    #[test]
+    fn pp_each_kind() {
+        let mut machine = Toy8Machine::new();
+        machine.set_accumulator(0xFE);
+        machine.set_memory_word(0x1E, 3);
+        assert_eq!(machine.current_instruction_pp(0x3E), "Op: Add - A=-2(FE) + mem[1E]=3(03)");
+        assert_eq!(machine.current_instruction_pp(0x9F), "Op: Load - input to A via mem[1F]");
+        assert_eq!(machine.current_instruction_pp(0xBF), "Op: Store - output from A via mem[1F]");
+        assert_eq!(machine.current_instruction_pp(0xE4), "Op: BranchPositive - pc becomes 04 when A=-2(FE) > 0");
+        assert_eq!(machine.current_instruction_pp(0x00), "Op: Halt");
+    }
+
+    #[test]
+    fn multiply_runs() {
+        let mut machine = Toy8Machine::new();
+        machine.load(reader(MULTIPLY).parse());
+        let mut env = ExternalEnv::new(vec![6, 7]);
+        assert_eq!(machine.run(&mut env), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[42]);
+    }
+
+    #[test]
+    fn arithmetic_wraps_at_eight_bits() {
+        // 00: 9D A <- mem[1D]; 01: 3D A <- A + mem[1D]; 02: BF write A; 03: 00 halt
+        let mut machine = Toy8Machine::new();
+        machine.load(vec![Toy8LoadWord::new(0x00, 0x9D), Toy8LoadWord::new(0x01, 0x3D),
+                          Toy8LoadWord::new(0x02, 0xBF), Toy8LoadWord::new(0x1D, 0x90)]);
+        let mut env = ExternalEnv::new(vec![]);
+        assert_eq!(machine.run(&mut env), Ok(StepOutcome::Halted));
+        assert_eq!(env.get_output(), &[0x20]);
+        assert_eq!(machine.get_step_count(), 4);
+    }
+
+    #[test]
+    fn traced_events() {
+        // 00: 9F read A; 01: BF write A; 02: 00 halt
+        let mut machine = Toy8Machine::new();
+        machine.load(vec![Toy8LoadWord::new(0x00, 0x9F), Toy8LoadWord::new(0x01, 0xBF)]);
+        let mut env = ExternalEnv::new(vec![0xFE]);
+        let mut trace = TraceBuffer::new();
+        assert_eq!(machine.run_traced(&mut env, &mut trace), Ok(StepOutcome::Halted));
+        let events = trace.get_events();
+        assert_eq!(events[..2], [
+            TraceEvent::Fetch { pc: 0, word: 0x9F },
+            TraceEvent::Input { address: 0x1F, port: 0xFF, word: 0xFE }]);
+        assert!(events.contains(&TraceEvent::RegWrite { register: 0, value: 0xFE }));
+        assert!(events.contains(&TraceEvent::Output { address: 0x1F, port: 0xFF, word: 0xFE }));
+        assert_eq!(events.last(), Some(&TraceEvent::Halt { pc: 2 }));
+    }
+
+    #[test]
+    fn traps() {
+        let mut machine = Toy8Machine::new();
+        machine.set_memory_word(0x00, 0x9F);
+        let mut env = ExternalEnv::new(vec![]);
+        assert_eq!(machine.run(&mut env), Err(MachineError::InputExhausted { pc: 0, instruction: 0x9F }));
+        // 00: DF goto 1F, which is the i/o word
+        machine.set_memory_word(0x00, 0xDF);
+        assert_eq!(machine.run(&mut env),
+                   Err(MachineError::InvalidAddress { pc: 0x1F, instruction: 0, address: 0x1F }));
+    }
+
+    #[test]
+    fn step_limit() {
+        // 00: C0 goto 00
+        let mut machine = Toy8Machine::new();
+        machine.set_memory_word(0x00, 0xC0);
+        let limits = crate::machine::RunLimits { max_steps: Some(50), ..Default::default() };
+        assert_eq!(machine.run_with_limits(&mut ExternalEnv::new(vec![]), &limits),
+                   Ok(StepOutcome::StepLimit { steps: 50 }));
+    }
+
+    #[test]
+    fn configs() {
+        assert_eq!(Toy8Machine::new().get_memory().len(), 32);
+        assert_eq!(Toy8Machine::with_config(MachineConfig::toy()).err(), Some(ConfigError::WordBits(16)));
+        let small = MachineConfig { address_bits: 4, io_address: 0xF, ..MachineConfig::toy8() };
+        assert_eq!(Toy8Machine::with_config(small).err(), Some(ConfigError::AddressBits(4)));
+        let large = MachineConfig { address_bits: 8, ..MachineConfig::toy8() };
+        assert_eq!(Toy8Machine::with_config(large).map(|machine| machine.get_memory().len()), Ok(256));
+    }
+}
@@ -0,0 +1,184 @@
+// Conformance of each TOY-8 opcode against the instruction table in the
+// `Toy8Machine` docs, at the edge values of 8-bit two's complement words.
+// Every case loads a single instruction at 00, runs one step and checks the
+// accumulator, memory, pc and i/o that result.
+
+use crate::external_env::external_env::ExternalEnv;
+use crate::outcome::outcome::{MachineError, StepOutcome};
+use crate::toy8::toy8::Toy8Machine;
+
+struct Case {
+    name: &'static str,
+    word: u8,
+    accumulator: u8,
+    memory: Vec<(usize, u8)>,
+    input: Vec<u16>,
+    expect_accumulator: u8,
+    expect_memory: Vec<(usize, u8)>,
+    expect_output: Vec<u16>,
+    expect_pc: u16,
+    expect_outcome: Result<StepOutcome, MachineError>,
+}
+
+impl Case {
+    fn new(name: &'static str, word: u8) -> Self {
+        Self {
+            name,
+            word,
+            accumulator: 0,
+            memory: vec![],
+            input: vec![],
+            expect_accumulator: 0,
+            expect_memory: vec![],
+            expect_output: vec![],
+            expect_pc: 0x01,
+            expect_outcome: Ok(StepOutcome::Running),
+        }
+    }
+    // the accumulator before the step, and after it unless expect_a says otherwise
+    fn a(mut self, value: u8) -> Self {
+        self.accumulator = value;
+        self.expect_accumulator = value;
+        self
+    }
+    fn mem(mut self, index: usize, value: u8) -> Self {
+        self.memory.push((index, value));
+        self
+    }
+    fn input(mut self, word: u16) -> Self {
+        self.input.push(word);
+        self
+    }
+    fn expect_a(mut self, value: u8) -> Self {
+        self.expect_accumulator = value;
+        self
+    }
+    fn expect_mem(mut self, index: usize, value: u8) -> Self {
+        self.expect_memory.push((index, value));
+        self
+    }
+    fn expect_output(mut self, word: u16) -> Self {
+        self.expect_output.push(word);
+        self
+    }
+    fn expect_pc(mut self, pc: u16) -> Self {
+        self.expect_pc = pc;
+        self
+    }
+    fn expect_halt(mut self) -> Self {
+        self.expect_outcome = Ok(StepOutcome::Halted);
+        self
+    }
+    fn expect_error(mut self, error: MachineError) -> Self {
+        self.expect_outcome = Err(error);
+        self.expect_pc = 0x00;
+        self
+    }
+
+    fn check(&self) {
+        let name = self.name;
+        let mut machine = Toy8Machine::new();
+        let mut env = ExternalEnv::new(self.input.clone());
+        machine.set_accumulator(self.accumulator);
+        for (index, value) in &self.memory {
+            machine.set_memory_word(*index, *value);
+        }
+        machine.set_memory_word(0x00, self.word);
+        machine.set_program_counter(0x00);
+        machine.set_running();
+        let outcome = machine.run_one_step(&mut env, false);
+        assert_eq!(outcome, self.expect_outcome, "{}: outcome", name);
+        assert_eq!(machine.get_accumulator(), self.expect_accumulator,
+                   "{}: A is {:02X}, expected {:02X}", name, machine.get_accumulator(), self.expect_accumulator);
+        for (index, value) in &self.expect_memory {
+            let word = machine.get_memory_word(*index);
+            assert_eq!(word, *value, "{}: mem[{:02X}] is {:02X}, expected {:02X}", name, index, word, value);
+        }
+        assert_eq!(machine.get_program_counter(), self.expect_pc, "{}: pc", name);
+        assert_eq!(env.get_output(), self.expect_output.as_slice(), "{}: output", name);
+    }
+}
+
+fn check_all(cases: Vec<Case>) {
+    for case in cases {
+        case.check();
+    }
+}
+
+#[test]
+fn halt() {
+    check_all(vec![
+        Case::new("halt", 0x00).expect_halt(),
+        Case::new("halt ignores the address", 0x1F).a(0x12).expect_halt(),
+    ]);
+}
+
+#[test]
+fn add() {
+    check_all(vec![
+        Case::new("1 + 1", 0x30).a(1).mem(0x10, 1).expect_a(2),
+        Case::new("7F + 1 overflows to 80", 0x30).a(0x7F).mem(0x10, 1).expect_a(0x80),
+        Case::new("FF + 1 wraps to 0", 0x30).a(0xFF).mem(0x10, 1).expect_a(0x00),
+        Case::new("80 + 80 wraps to 0", 0x30).a(0x80).mem(0x10, 0x80).expect_a(0x00),
+        Case::new("memory is left alone", 0x30).a(2).mem(0x10, 3).expect_a(5).expect_mem(0x10, 3),
+    ]);
+}
+
+#[test]
+fn subtract() {
+    check_all(vec![
+        Case::new("5 - 3", 0x50).a(5).mem(0x10, 3).expect_a(2),
+        Case::new("0 - 1", 0x50).mem(0x10, 1).expect_a(0xFF),
+        Case::new("80 - 1 overflows to 7F", 0x50).a(0x80).mem(0x10, 1).expect_a(0x7F),
+        Case::new("7F - FF overflows to 80", 0x50).a(0x7F).mem(0x10, 0xFF).expect_a(0x80),
+    ]);
+}
+
+#[test]
+fn and() {
+    check_all(vec![
+        Case::new("and", 0x70).a(0xF0).mem(0x10, 0x3C).expect_a(0x30),
+        Case::new("and with 0", 0x70).a(0xFF).expect_a(0x00),
+    ]);
+}
+
+#[test]
+fn load_and_store() {
+    check_all(vec![
+        Case::new("load", 0x90).a(0x12).mem(0x10, 0x80).expect_a(0x80),
+        Case::new("load own instruction", 0x80).expect_a(0x80),
+        Case::new("store", 0xB0).a(0xFF).expect_mem(0x10, 0xFF),
+        Case::new("store over next instruction", 0xA1).a(0xAB).expect_mem(0x01, 0xAB),
+    ]);
+}
+
+#[test]
+fn input_and_output() {
+    check_all(vec![
+        Case::new("load 1F reads input", 0x9F).input(0x42).expect_a(0x42).expect_mem(0x1F, 0x42),
+        Case::new("input keeps the low 8 bits", 0x9F).input(0x1234).expect_a(0x34),
+        Case::new("load 1F without input traps", 0x9F)
+            .expect_error(MachineError::InputExhausted { pc: 0x00, instruction: 0x9F }),
+        Case::new("store 1F writes output", 0xBF).a(0x80).expect_mem(0x1F, 0x80).expect_output(0x80),
+    ]);
+}
+
+#[test]
+fn branch_zero() {
+    check_all(vec![
+        Case::new("taken on 0", 0xD5).expect_pc(0x15),
+        Case::new("not taken on 1", 0xD5).a(1),
+        Case::new("not taken on 80", 0xD5).a(0x80),
+    ]);
+}
+
+#[test]
+fn branch_positive() {
+    check_all(vec![
+        Case::new("taken on 1", 0xF5).a(1).expect_pc(0x15),
+        Case::new("taken on 7F", 0xF5).a(0x7F).expect_pc(0x15),
+        Case::new("not taken on 0", 0xF5),
+        Case::new("not taken on 80", 0xF5).a(0x80),
+        Case::new("not taken on FF", 0xF5).a(0xFF),
+    ]);
+}
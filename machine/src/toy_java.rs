@@ -0,0 +1,393 @@
+pub mod toy_java {
+    use crate::trace::trace::{TraceEvent, TraceSink};
+    use std::fmt;
+    use std::io;
+
+    // The trace format shared with the reference simulator, TOY.java from
+    // introcs.cs.princeton.edu. Each step is a line giving the pc and
+    // instruction before it ran and the registers after, followed by any
+    // input, memory write or output it made:
+    //
+    //     PC: 10  IR: 8AFF  R: 0000 0000 ... 0000
+    //       in 0003
+    //       mem[30] <- 0003
+    //       out 000C
+    //
+    // A run ends with TOY.java's core dump: "PC:", the pc, then
+    // "Registers:" eight words to a line and "Main memory:" sixteen words to
+    // a line. Any other line is ignored, so the Java program's own output
+    // can stay in the file.
+
+    /// Writes the steps of a run in the TOY.java format. It follows the
+    /// registers from the events, so it starts from the machine's
+    /// registers.
+    pub struct ToyJavaTrace<W: io::Write> {
+        out: W,
+        regs: [u16; 16],
+        step: Option<TraceStep>,
+    }
+
+    impl<W: io::Write> ToyJavaTrace<W> {
+        pub fn new(out: W, regs: &[u16]) -> Self {
+            let mut all = [0; 16];
+            all[..regs.len()].copy_from_slice(regs);
+            Self { out, regs: all, step: None }
+        }
+        // write the step in progress, as when a run ended in a trap
+        pub fn flush(&mut self) {
+            if let Some(mut step) = self.step.take() {
+                step.regs = self.regs;
+                let _ = write!(self.out, "{}", step);
+            }
+        }
+        pub fn into_inner(mut self) -> W {
+            self.flush();
+            self.out
+        }
+    }
+
+    impl<W: io::Write> TraceSink for ToyJavaTrace<W> {
+        fn event(&mut self, event: TraceEvent) {
+            match event {
+                TraceEvent::Fetch { pc, word } => {
+                    self.flush();
+                    self.step = Some(TraceStep { pc, word, ..TraceStep::default() });
+                },
+                TraceEvent::RegWrite { register, value } => self.regs[register as usize] = value,
+                TraceEvent::Halt { .. } => self.flush(),
+                event => if let Some(step) = self.step.as_mut() {
+                    match event {
+                        TraceEvent::Input { word, .. } => step.input.push(word),
+                        TraceEvent::MemWrite { address, value } => step.writes.push((address, value)),
+                        // the word was stored to the i/o address on its way out
+                        TraceEvent::Output { address, word, .. } => {
+                            step.writes.retain(|(written, _)| *written != address);
+                            step.output.push(word);
+                        },
+                        _ => {},
+                    }
+                },
+            }
+        }
+    }
+
+    /// One step of a trace.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct TraceStep {
+        pub pc: u16,
+        pub word: u16,
+        // after the step
+        pub regs: [u16; 16],
+        pub input: Vec<u16>,
+        pub writes: Vec<(u16, u16)>,
+        pub output: Vec<u16>,
+    }
+
+    impl fmt::Display for TraceStep {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "PC: {:02X}  IR: {:04X}  R:", self.pc, self.word)?;
+            for reg in self.regs {
+                write!(f, " {:04X}", reg)?;
+            }
+            writeln!(f)?;
+            for word in &self.input {
+                writeln!(f, "  in {:04X}", word)?;
+            }
+            for (address, value) in &self.writes {
+                writeln!(f, "  mem[{:02X}] <- {:04X}", address, value)?;
+            }
+            for word in &self.output {
+                writeln!(f, "  out {:04X}", word)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The machine state TOY.java dumps at the end of a run.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CoreDump {
+        pub pc: u16,
+        pub regs: [u16; 16],
+        pub memory: Vec<u16>,
+    }
+
+    impl CoreDump {
+        // registers past those given are zero
+        pub fn new(pc: u16, regs: &[u16], memory: &[u16]) -> Self {
+            let mut all = [0; 16];
+            all[..regs.len()].copy_from_slice(regs);
+            Self { pc, regs: all, memory: memory.to_vec() }
+        }
+    }
+
+    impl fmt::Display for CoreDump {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "PC:")?;
+            writeln!(f, "{:02X}", self.pc)?;
+            writeln!(f)?;
+            writeln!(f, "Registers:")?;
+            write_rows(f, &self.regs, 8)?;
+            writeln!(f)?;
+            writeln!(f, "Main memory:")?;
+            write_rows(f, &self.memory, 16)?;
+            writeln!(f)
+        }
+    }
+
+    fn write_rows(f: &mut fmt::Formatter, words: &[u16], width: usize) -> fmt::Result {
+        for row in words.chunks(width) {
+            for word in row {
+                write!(f, "{:04X} ", word)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct ParsedTrace {
+        pub steps: Vec<TraceStep>,
+        pub dump: Option<CoreDump>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TraceParseError {
+        pub line: usize,
+        pub message: String,
+    }
+
+    impl fmt::Display for TraceParseError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+
+    fn hex(text: &str, line: usize) -> Result<u16, TraceParseError> {
+        u16::from_str_radix(text, 16)
+            .map_err(|_| TraceParseError { line, message: format!("`{}` is not a hex word", text) })
+    }
+
+    // the words of the lines after a dump heading, up to a blank line
+    fn dump_words<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, heading: usize, count: usize)
+        -> Result<Vec<u16>, TraceParseError> {
+        let mut words = vec![];
+        for (number, line) in lines.by_ref() {
+            if line.trim().is_empty() && !words.is_empty() {
+                break;
+            }
+            for word in line.split_whitespace() {
+                words.push(hex(word, number)?);
+            }
+        }
+        if words.len() != count {
+            return Err(TraceParseError { line: heading,
+                                         message: format!("the dump has {} words, not {}", words.len(), count) });
+        }
+        Ok(words)
+    }
+
+    pub fn parse_trace(text: &str) -> Result<ParsedTrace, TraceParseError> {
+        let mut trace = ParsedTrace::default();
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+        while let Some((number, line)) = lines.next() {
+            let trimmed = line.trim();
+            if let Some(rest) = trimmed.strip_prefix("PC:").filter(|rest| rest.contains("IR:")) {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                if fields.len() != 20 || fields[1] != "IR:" || fields[3] != "R:" {
+                    return Err(TraceParseError { line: number, message: "a step line has a pc, IR and 16 registers".into() });
+                }
+                let mut step = TraceStep { pc: hex(fields[0], number)?, word: hex(fields[2], number)?,
+                                           ..TraceStep::default() };
+                for (reg, field) in step.regs.iter_mut().zip(&fields[4..]) {
+                    *reg = hex(field, number)?;
+                }
+                trace.steps.push(step);
+            } else if trimmed == "PC:" {
+                let pc = match lines.next() {
+                    Some((number, line)) => hex(line.trim(), number)?,
+                    None => return Err(TraceParseError { line: number, message: "the dump has no pc".into() }),
+                };
+                let mut regs = [0; 16];
+                let mut memory = vec![];
+                while let Some((number, line)) = lines.next() {
+                    match line.trim() {
+                        "Registers:" => regs.copy_from_slice(&dump_words(&mut lines, number, 16)?),
+                        "Main memory:" => memory = dump_words(&mut lines, number, 256)?,
+                        _ => {},
+                    }
+                    if !memory.is_empty() {
+                        break;
+                    }
+                }
+                trace.dump = Some(CoreDump { pc, regs, memory });
+            } else if let Some(step) = trace.steps.last_mut() {
+                if let Some(word) = trimmed.strip_prefix("in ") {
+                    step.input.push(hex(word, number)?);
+                } else if let Some(word) = trimmed.strip_prefix("out ") {
+                    step.output.push(hex(word, number)?);
+                } else if let Some((address, value)) = trimmed.strip_prefix("mem[")
+                    .and_then(|rest| rest.split_once("] <- ")) {
+                    step.writes.push((hex(address, number)?, hex(value, number)?));
+                }
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Where two traces first differ.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Divergence {
+        // steps are counted from 1
+        Pc { step: usize, ours: u16, reference: u16 },
+        Instruction { step: usize, pc: u16, ours: u16, reference: u16 },
+        Register { step: usize, pc: u16, register: u8, ours: u16, reference: u16 },
+        Memory { step: usize, pc: u16, ours: Vec<(u16, u16)>, reference: Vec<(u16, u16)> },
+        Input { step: usize, pc: u16, ours: Vec<u16>, reference: Vec<u16> },
+        Output { step: usize, pc: u16, ours: Vec<u16>, reference: Vec<u16> },
+        // one trace stopped while the other went on
+        Length { ours: usize, reference: usize },
+        // differences in the final core dumps
+        FinalPc { ours: u16, reference: u16 },
+        FinalRegister { register: u8, ours: u16, reference: u16 },
+        FinalMemory { address: u16, ours: u16, reference: u16 },
+    }
+
+    impl fmt::Display for Divergence {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Divergence::Pc { step, ours, reference } =>
+                    write!(f, "step {}: pc {:02X}, reference {:02X}", step, ours, reference),
+                Divergence::Instruction { step, pc, ours, reference } =>
+                    write!(f, "step {} @ {:02X}: instruction {:04X}, reference {:04X}", step, pc, ours, reference),
+                Divergence::Register { step, pc, register, ours, reference } =>
+                    write!(f, "step {} @ {:02X}: R[{:X}] = {:04X}, reference {:04X}", step, pc, register, ours, reference),
+                Divergence::Memory { step, pc, ours, reference } =>
+                    write!(f, "step {} @ {:02X}: memory writes {}, reference {}", step, pc,
+                           writes(ours), writes(reference)),
+                Divergence::Input { step, pc, ours, reference } =>
+                    write!(f, "step {} @ {:02X}: input {}, reference {}", step, pc, words(ours), words(reference)),
+                Divergence::Output { step, pc, ours, reference } =>
+                    write!(f, "step {} @ {:02X}: output {}, reference {}", step, pc, words(ours), words(reference)),
+                Divergence::Length { ours, reference } =>
+                    write!(f, "{} steps, reference {}", ours, reference),
+                Divergence::FinalPc { ours, reference } =>
+                    write!(f, "final pc {:02X}, reference {:02X}", ours, reference),
+                Divergence::FinalRegister { register, ours, reference } =>
+                    write!(f, "final R[{:X}] = {:04X}, reference {:04X}", register, ours, reference),
+                Divergence::FinalMemory { address, ours, reference } =>
+                    write!(f, "final mem[{:02X}] = {:04X}, reference {:04X}", address, ours, reference),
+            }
+        }
+    }
+
+    fn words(words: &[u16]) -> String {
+        if words.is_empty() {
+            return "none".into();
+        }
+        words.iter().map(|word| format!("{:04X}", word)).collect::<Vec<_>>().join(" ")
+    }
+
+    fn writes(writes: &[(u16, u16)]) -> String {
+        if writes.is_empty() {
+            return "none".into();
+        }
+        writes.iter().map(|(address, value)| format!("mem[{:02X}] <- {:04X}", address, value))
+            .collect::<Vec<_>>().join(", ")
+    }
+
+    fn first_difference(ours: &[u16], reference: &[u16]) -> Option<usize> {
+        ours.iter().zip(reference).position(|(ours, reference)| ours != reference)
+    }
+
+    // Step through the two traces together and give the first difference:
+    // within a step the pc, the instruction, the input, the registers, the
+    // memory writes and the output, in that order; then the lengths, then
+    // the core dumps when both have one.
+    pub fn diff_traces(ours: &ParsedTrace, reference: &ParsedTrace) -> Option<Divergence> {
+        for (index, (mine, theirs)) in ours.steps.iter().zip(&reference.steps).enumerate() {
+            let (step, pc) = (index + 1, mine.pc);
+            if mine.pc != theirs.pc {
+                return Some(Divergence::Pc { step, ours: mine.pc, reference: theirs.pc });
+            }
+            if mine.word != theirs.word {
+                return Some(Divergence::Instruction { step, pc, ours: mine.word, reference: theirs.word });
+            }
+            if mine.input != theirs.input {
+                return Some(Divergence::Input { step, pc, ours: mine.input.clone(), reference: theirs.input.clone() });
+            }
+            if let Some(register) = first_difference(&mine.regs, &theirs.regs) {
+                return Some(Divergence::Register { step, pc, register: register as u8,
+                                                   ours: mine.regs[register], reference: theirs.regs[register] });
+            }
+            if mine.writes != theirs.writes {
+                return Some(Divergence::Memory { step, pc, ours: mine.writes.clone(), reference: theirs.writes.clone() });
+            }
+            if mine.output != theirs.output {
+                return Some(Divergence::Output { step, pc, ours: mine.output.clone(), reference: theirs.output.clone() });
+            }
+        }
+        if ours.steps.len() != reference.steps.len() {
+            return Some(Divergence::Length { ours: ours.steps.len(), reference: reference.steps.len() });
+        }
+        let (mine, theirs) = match (&ours.dump, &reference.dump) {
+            (Some(mine), Some(theirs)) => (mine, theirs),
+            _ => return None,
+        };
+        if mine.pc != theirs.pc {
+            return Some(Divergence::FinalPc { ours: mine.pc, reference: theirs.pc });
+        }
+        if let Some(register) = first_difference(&mine.regs, &theirs.regs) {
+            return Some(Divergence::FinalRegister { register: register as u8,
+                                                    ours: mine.regs[register], reference: theirs.regs[register] });
+        }
+        first_difference(&mine.memory, &theirs.memory).map(|address| Divergence::FinalMemory {
+            address: address as u16, ours: mine.memory[address], reference: theirs.memory[address] })
+    }
+}
+
+#[cfg(test)]
+mod toy_java_tests {
+    use super::toy_java::{diff_traces, parse_trace, CoreDump, Divergence, ToyJavaTrace};
+    use crate::trace::trace::{TraceEvent, TraceSink};
+
+    fn one_step() -> String {
+        let mut trace = ToyJavaTrace::new(vec![], &[0; 16]);
+        trace.event(TraceEvent::Fetch { pc: 0x10, word: 0x8AFF });
+        trace.event(TraceEvent::Input { address: 0xFF, port: 0xFF, word: 3 });
+        trace.event(TraceEvent::RegWrite { register: 0xA, value: 3 });
+        trace.event(TraceEvent::Fetch { pc: 0x11, word: 0x9AFF });
+        trace.event(TraceEvent::MemWrite { address: 0xFF, value: 3 });
+        trace.event(TraceEvent::Output { address: 0xFF, port: 0xFF, word: 3 });
+        trace.event(TraceEvent::Halt { pc: 0x11 });
+        String::from_utf8(trace.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn writes_and_reads_back() {
+        let text = one_step();
+        assert!(text.starts_with("PC: 10  IR: 8AFF  R: 0000 0000 0000 0000 0000 0000 0000 0000 \
+                                  0000 0000 0003 0000 0000 0000 0000 0000\n  in 0003\nPC: 11"));
+        assert!(text.ends_with("\n  out 0003\n"));
+        let dump = CoreDump { pc: 0x12, regs: [1; 16], memory: vec![2; 256] };
+        let parsed = parse_trace(&(text + "some program output\n" + &dump.to_string())).unwrap();
+        assert_eq!(parsed.steps.len(), 2);
+        assert_eq!(parsed.steps[0].input, vec![3]);
+        assert_eq!(parsed.steps[1].output, vec![3]);
+        assert!(parsed.steps[1].writes.is_empty());
+        assert_eq!(parsed.dump, Some(dump));
+    }
+
+    #[test]
+    fn finds_the_first_divergence() {
+        let ours = parse_trace(&one_step()).unwrap();
+        assert_eq!(diff_traces(&ours, &ours), None);
+        let reference = parse_trace(&one_step().replace("0003 0000 0000 0000 0000 0000\n", "0004 0000 0000 0000 0000 0000\n")
+                                    .replace("out 0003", "out 0004")).unwrap();
+        assert_eq!(diff_traces(&ours, &reference),
+                   Some(Divergence::Register { step: 1, pc: 0x10, register: 0xA, ours: 3, reference: 4 }));
+        let mut shorter = ours.clone();
+        shorter.steps.pop();
+        assert_eq!(diff_traces(&ours, &shorter), Some(Divergence::Length { ours: 2, reference: 1 }));
+        assert!(parse_trace("PC: 10  IR: 8AFF  R: 0000\n").is_err());
+    }
+}
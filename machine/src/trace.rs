@@ -0,0 +1,250 @@
+pub mod trace {
+    use std::fmt;
+    use std::io;
+
+    /// One thing that happened during a step, in the order it happened:
+    /// the fetch, any input read into memory, the decoded instruction, its
+    /// memory read, then what it wrote and any output. An interrupt taken
+    /// before the step comes first.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum TraceEvent {
+        Fetch { pc: u16, word: u16 },
+        // text is the instruction as the pretty-printer shows it
+        Decode { pc: u16, word: u16, text: String },
+        RegWrite { register: u8, value: u16 },
+        MemRead { address: u16, value: u16 },
+        MemWrite { address: u16, value: u16 },
+        // a word read from a device into memory at address
+        Input { address: u16, port: u8, word: u16 },
+        // a word written from memory at address to a device
+        Output { address: u16, port: u8, word: u16 },
+        Interrupt { pc: u16, register: u8, vector: u16 },
+        Halt { pc: u16 },
+    }
+
+    impl TraceEvent {
+        // one JSON object, without a newline
+        pub fn to_json(&self) -> String {
+            match self {
+                TraceEvent::Fetch { pc, word } =>
+                    format!(r#"{{"event":"fetch","pc":{},"word":{}}}"#, pc, word),
+                TraceEvent::Decode { pc, word, text } =>
+                    format!(r#"{{"event":"decode","pc":{},"word":{},"text":"{}"}}"#, pc, word, escape(text)),
+                TraceEvent::RegWrite { register, value } =>
+                    format!(r#"{{"event":"reg_write","register":{},"value":{}}}"#, register, value),
+                TraceEvent::MemRead { address, value } =>
+                    format!(r#"{{"event":"mem_read","address":{},"value":{}}}"#, address, value),
+                TraceEvent::MemWrite { address, value } =>
+                    format!(r#"{{"event":"mem_write","address":{},"value":{}}}"#, address, value),
+                TraceEvent::Input { address, port, word } =>
+                    format!(r#"{{"event":"input","address":{},"port":{},"word":{}}}"#, address, port, word),
+                TraceEvent::Output { address, port, word } =>
+                    format!(r#"{{"event":"output","address":{},"port":{},"word":{}}}"#, address, port, word),
+                TraceEvent::Interrupt { pc, register, vector } =>
+                    format!(r#"{{"event":"interrupt","pc":{},"register":{},"vector":{}}}"#, pc, register, vector),
+                TraceEvent::Halt { pc } =>
+                    format!(r#"{{"event":"halt","pc":{}}}"#, pc),
+            }
+        }
+    }
+
+    // The lines `run` has always printed. Events those lines never showed
+    // are not displayed.
+    impl fmt::Display for TraceEvent {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                TraceEvent::Input { address, word, .. } =>
+                    write!(f, "Read word to mem[{}]: {}({:04X}x)", address, word, word),
+                TraceEvent::Decode { pc, text, .. } =>
+                    write!(f, "Execute @ pc = {:02X}x; {}", pc, text),
+                TraceEvent::Output { address, word, .. } =>
+                    write!(f, "Write word from mem[{}]: {}({:04X}x)", address, word, word),
+                TraceEvent::Interrupt { pc, register, vector } =>
+                    write!(f, "Interrupt @ pc = {:02X}x; R[{:X}] <- pc; pc <- {:02X}", pc, register, vector),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    pub(crate) fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Where a traced step sends its events.
+    pub trait TraceSink {
+        fn event(&mut self, event: TraceEvent);
+        // false when events would be dropped, so the machine need not
+        // build them
+        fn is_enabled(&self) -> bool {
+            true
+        }
+    }
+
+    impl<T: TraceSink + ?Sized> TraceSink for &mut T {
+        fn event(&mut self, event: TraceEvent) {
+            (**self).event(event)
+        }
+        fn is_enabled(&self) -> bool {
+            (**self).is_enabled()
+        }
+    }
+
+    /// Drops every event.
+    pub struct NoTrace;
+
+    impl TraceSink for NoTrace {
+        fn event(&mut self, _event: TraceEvent) {}
+        fn is_enabled(&self) -> bool {
+            false
+        }
+    }
+
+    /// The human readable trace, one line per displayed event.
+    pub struct TextTrace<W: io::Write> {
+        out: W,
+        // 8 shows words read and written as TOY-8 does, signed and two
+        // hex digits wide
+        word_bits: u32,
+    }
+
+    impl<W: io::Write> TextTrace<W> {
+        pub fn new(out: W) -> Self {
+            Self { out, word_bits: 16 }
+        }
+        pub fn with_word_bits(self, word_bits: u32) -> Self {
+            Self { word_bits, ..self }
+        }
+        pub fn into_inner(self) -> W {
+            self.out
+        }
+    }
+
+    impl TextTrace<io::Stdout> {
+        pub fn stdout() -> Self {
+            Self::new(io::stdout())
+        }
+    }
+
+    impl<W: io::Write> TraceSink for TextTrace<W> {
+        fn event(&mut self, event: TraceEvent) {
+            let _ = match event {
+                TraceEvent::Input { address, word, .. } if self.word_bits == 8 =>
+                    writeln!(self.out, "Read word to mem[{}]: {}({:02X}x)", address, word as u8 as i8, word),
+                TraceEvent::Output { address, word, .. } if self.word_bits == 8 =>
+                    writeln!(self.out, "Write word from mem[{}]: {}({:02X}x)", address, word as u8 as i8, word),
+                TraceEvent::Input { .. } | TraceEvent::Decode { .. } |
+                TraceEvent::Output { .. } | TraceEvent::Interrupt { .. } => writeln!(self.out, "{}", event),
+                _ => Ok(()),
+            };
+        }
+    }
+
+    /// Every event as a line of JSON.
+    pub struct JsonLinesTrace<W: io::Write> {
+        out: W,
+    }
+
+    impl<W: io::Write> JsonLinesTrace<W> {
+        pub fn new(out: W) -> Self {
+            Self { out }
+        }
+        pub fn into_inner(self) -> W {
+            self.out
+        }
+    }
+
+    impl<W: io::Write> TraceSink for JsonLinesTrace<W> {
+        fn event(&mut self, event: TraceEvent) {
+            let _ = writeln!(self.out, "{}", event.to_json());
+        }
+    }
+
+    /// Keeps events in memory to be looked at later. With a limit only the
+    /// latest `limit` are kept.
+    #[derive(Debug, Clone, Default)]
+    pub struct TraceBuffer {
+        events: Vec<TraceEvent>,
+        limit: Option<usize>,
+    }
+
+    impl TraceBuffer {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn with_limit(limit: usize) -> Self {
+            Self { events: vec![], limit: Some(limit) }
+        }
+        pub fn get_events(&self) -> &[TraceEvent] {
+            &self.events
+        }
+        pub fn take_events(&mut self) -> Vec<TraceEvent> {
+            std::mem::take(&mut self.events)
+        }
+        pub fn clear(&mut self) {
+            self.events.clear();
+        }
+        pub fn len(&self) -> usize {
+            self.events.len()
+        }
+        pub fn is_empty(&self) -> bool {
+            self.events.is_empty()
+        }
+        // the events as JSON Lines
+        pub fn to_json_lines(&self) -> String {
+            self.events.iter().map(|event| event.to_json() + "\n").collect()
+        }
+    }
+
+    impl TraceSink for TraceBuffer {
+        fn event(&mut self, event: TraceEvent) {
+            if let Some(limit) = self.limit {
+                if self.events.len() >= limit {
+                    let excess = self.events.len() + 1 - limit;
+                    self.events.drain(..excess);
+                }
+            }
+            self.events.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::trace::{JsonLinesTrace, TextTrace, TraceBuffer, TraceEvent, TraceSink};
+
+    #[test]
+    fn formats() {
+        let decode = TraceEvent::Decode { pc: 0x10, word: 0x8AFF, text: "Op: \"x\"".into() };
+        assert_eq!(decode.to_string(), "Execute @ pc = 10x; Op: \"x\"");
+        assert_eq!(decode.to_json(), r#"{"event":"decode","pc":16,"word":35583,"text":"Op: \"x\""}"#);
+        let mut text = TextTrace::new(vec![]);
+        text.event(TraceEvent::Fetch { pc: 0x10, word: 0x8AFF });
+        text.event(TraceEvent::Output { address: 0xFF, port: 0xFF, word: 12 });
+        assert_eq!(String::from_utf8(text.into_inner()).unwrap(), "Write word from mem[255]: 12(000Cx)\n");
+        let mut text = TextTrace::new(vec![]).with_word_bits(8);
+        text.event(TraceEvent::Input { address: 0x1F, port: 0xFF, word: 0xFE });
+        assert_eq!(String::from_utf8(text.into_inner()).unwrap(), "Read word to mem[31]: -2(FEx)\n");
+        let mut json = JsonLinesTrace::new(vec![]);
+        json.event(TraceEvent::Halt { pc: 0x19 });
+        assert_eq!(String::from_utf8(json.into_inner()).unwrap(), "{\"event\":\"halt\",\"pc\":25}\n");
+    }
+
+    #[test]
+    fn buffer_keeps_the_latest() {
+        let mut buffer = TraceBuffer::with_limit(2);
+        for pc in 0..3 {
+            buffer.event(TraceEvent::Halt { pc });
+        }
+        assert_eq!(buffer.get_events(), &[TraceEvent::Halt { pc: 1 }, TraceEvent::Halt { pc: 2 }]);
+    }
+}
@@ -1,20 +1,670 @@
-use machine::machine::Machine;
+use machine::machine::{Machine, RunLimits};
+use machine::outcome::outcome::{MachineError, StepOutcome};
 use machine::program_reader::program_reader::ProgramReader;
+use machine::analyzer::analyzer::analyze;
+use machine::config::config::MachineConfig;
+use machine::assembler::assembler::{assemble, assemble_listing, AssemblyError};
+use machine::disassembler::disassembler::disassemble;
+use machine::machine::ProgramLoadWord;
 use machine::external_env::external_env::ExternalEnv;
+use machine::device::device::{Device, StreamDevice, IO_ADDRESS};
+use machine::batch::batch::{run_batch, BatchProgram};
+use machine::block_engine::block_engine::BlockEngine;
+use machine::bus::bus::{CharConsole, CycleCounter, DeviceBus, InputStream, IntervalTimer, RandomSource};
+use machine::interrupt::interrupt::InterruptConfig;
+use machine::cfg::cfg::ControlFlowGraph;
+use machine::coverage::coverage::Coverage;
+use machine::trace::trace::JsonLinesTrace;
+use machine::toy_java::toy_java::{diff_traces, parse_trace, CoreDump, ToyJavaTrace};
+use machine::debugger::debugger::{Access, Condition, DebugEvent, WatchTarget};
+use machine::toy8::toy8::{listing as toy8_listing, Toy8Machine, Toy8Reader};
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
 
-fn main() {
-    let filename: &str = "program.txt";
+const USAGE: &str = "usage: main [program.txt | program.asm] [--arch toy|toy8] [--listing | --cfg | --analyze] \
+[--input XXXX,XXXX,... | --stdin] \
+[--break XX[:condition]]... [--watch R[x]|mem[xx][:read|write]]... \
+[--max-steps N] [--timeout-ms N] [--detect-loops] \
+[--map XX=console|counter|random[:seed]|input:XXXX,...|timer:period]... \
+[--interrupts vector,register,control] [--bench] [--profile] [--sanitize] [--coverage] [--lcov file] [--trace-json | --trace-java | --compare reference.txt]
+       main batch [--input XXXX,XXXX,...]... [--inputs file] [--max-steps N] [--threads N] [--format json|csv] program...";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arch {
+    Toy,
+    Toy8,
+}
+
+// a device to map into memory next to the standard i/o at mem[FF]
+enum PortSpec {
+    Console,
+    Counter,
+    Random(u16),
+    Input(Vec<u16>),
+    Timer(u16),
+}
+
+struct Options {
+    filename: String,
+    arch: Arch,
+    input: Vec<u16>,
+    // read input words from stdin and print output words as they are written
+    stdin: bool,
+    breakpoints: Vec<(u16, Option<Condition>)>,
+    watchpoints: Vec<(WatchTarget, Access)>,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    detect_loops: bool,
+    ports: Vec<(u8, PortSpec)>,
+    interrupts: Option<InterruptConfig>,
+    // print the program as a listing instead of running it
+    listing: bool,
+    // print the program's control-flow graph as DOT instead of running it
+    cfg: bool,
+    // warn of what may go wrong in some run, instead of running it
+    analyze: bool,
+    // time runs of the program one step at a time, on the fast path and
+    // on the block engine
+    bench: bool,
+    // print execution counts and hot spots after the run
+    profile: bool,
+    // report reads of uninitialized memory, executed data and code overwritten
+    sanitize: bool,
+    // print which lines of the program file ran and which way branches went
+    coverage: bool,
+    // write that coverage as an lcov tracefile
+    lcov: Option<String>,
+    // print the run's trace as JSON Lines instead of text
+    trace_json: bool,
+    // print the run's trace and core dump as TOY.java does
+    trace_java: bool,
+    // compare the run's TOY.java trace with this reference trace
+    compare: Option<String>,
+}
+
+fn parse_hex_word(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim(), 16).map_err(|_| format!("`{}` is not a hex word", text))
+}
+
+fn parse_breakpoint(text: &str) -> Result<(u16, Option<Condition>), String> {
+    let (address, condition) = match text.split_once(':') {
+        Some((address, condition)) => (address, Some(Condition::parse(condition)?)),
+        None => (text, None),
+    };
+    let address = u16::from_str_radix(address.trim(), 16)
+        .map_err(|_| format!("`{}` is not a hex address", address))?;
+    Ok((address, condition))
+}
+
+fn parse_watchpoint(text: &str) -> Result<(WatchTarget, Access), String> {
+    let (target, access) = match text.rsplit_once(':') {
+        Some((target, "read")) => (target, Access::Read),
+        Some((target, "write")) => (target, Access::Write),
+        _ => (text, Access::ReadWrite),
+    };
+    Ok((WatchTarget::parse(target)?, access))
+}
+
+fn parse_hex_words(text: &str) -> Result<Vec<u16>, String> {
+    text.split(',')
+        .filter(|word| !word.trim().is_empty())
+        .map(parse_hex_word)
+        .collect()
+}
+
+fn parse_port(text: &str) -> Result<(u8, PortSpec), String> {
+    let (address, device) = text.split_once('=')
+        .ok_or_else(|| format!("`{}` is not XX=device", text))?;
+    let address = u8::from_str_radix(address.trim(), 16)
+        .map_err(|_| format!("`{}` is not a hex address", address))?;
+    let spec = match device.split_once(':') {
+        None if device == "console" => PortSpec::Console,
+        None if device == "counter" => PortSpec::Counter,
+        None if device == "random" => PortSpec::Random(1),
+        Some(("random", seed)) => PortSpec::Random(parse_hex_word(seed)?),
+        Some(("input", words)) => PortSpec::Input(parse_hex_words(words)?),
+        Some(("timer", period)) => PortSpec::Timer(parse_hex_word(period)?),
+        _ => return Err(format!("unknown device `{}`", device)),
+    };
+    Ok((address, spec))
+}
+
+// vector,register,control as hex, such as 30,B,FC
+fn parse_interrupts(text: &str) -> Result<InterruptConfig, String> {
+    let parts: Vec<_> = text.split(',').map(parse_hex_word).collect::<Result<_, _>>()?;
+    match parts[..] {
+        [vector, register, control_address] if register < 16 => Ok(InterruptConfig {
+            vector, saved_pc_register: register as u8, control_address }),
+        _ => Err(format!("`{}` is not vector,register,control", text)),
+    }
+}
+
+fn parse_count(text: &str) -> Result<u64, String> {
+    text.parse().map_err(|_| format!("`{}` is not a count", text))
+}
+
+// steps a batch run may take when --max-steps does not say
+const BATCH_STEP_LIMIT: u64 = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+struct BatchOptions {
+    programs: Vec<String>,
+    inputs: Vec<Vec<u16>>,
+    max_steps: u64,
+    // 0 for one per core
+    threads: usize,
+    format: Format,
+}
+
+// each line of the file an input, as XXXX,XXXX,...
+fn read_inputs(filename: &str) -> Result<Vec<Vec<u16>>, String> {
+    let text = fs::read_to_string(filename).map_err(|error| format!("{}: {}", filename, error))?;
+    text.lines().map(parse_hex_words).collect()
+}
+
+fn parse_batch_options(args: &[String]) -> Result<BatchOptions, String> {
+    let mut options = BatchOptions {
+        programs: vec![],
+        inputs: vec![],
+        max_steps: BATCH_STEP_LIMIT,
+        threads: 0,
+        format: Format::Json,
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--input" => options.inputs.push(parse_hex_words(value()?)?),
+            "--inputs" => options.inputs.extend(read_inputs(value()?)?),
+            "--max-steps" => options.max_steps = parse_count(value()?)?,
+            "--threads" => options.threads = parse_count(value()?)? as usize,
+            "--format" => options.format = match value()?.as_str() {
+                "json" => Format::Json,
+                "csv" => Format::Csv,
+                format => return Err(format!("unknown format `{}`", format)),
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.programs.push(arg.clone()),
+        }
+    }
+    if options.programs.is_empty() {
+        return Err("batch needs at least one program".into());
+    }
+    if options.inputs.is_empty() {
+        options.inputs.push(vec![]);
+    }
+    Ok(options)
+}
+
+// Run every program on every input and print the table of results.
+fn run_batch_command(args: &[String]) {
+    let options = parse_batch_options(args).unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(2);
+    });
+    // a program that cannot be loaded gets rows saying why, so one broken
+    // submission does not lose the others' results
+    let programs: Vec<_> = options.programs.iter()
+        .map(|filename| match try_load_program(filename) {
+            Ok(loads) => BatchProgram::new(filename.as_str(), loads),
+            Err(message) => BatchProgram::unloadable(filename.as_str(), message),
+        })
+        .collect();
+    let results = run_batch(&programs, &options.inputs, options.max_steps, options.threads);
+    match options.format {
+        Format::Json => print!("{}", results.to_json()),
+        Format::Csv => print!("{}", results.to_csv()),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        filename: String::from("program.txt"),
+        arch: Arch::Toy,
+        input: vec![25, 39],
+        stdin: false,
+        breakpoints: vec![],
+        watchpoints: vec![],
+        max_steps: None,
+        timeout: None,
+        detect_loops: false,
+        ports: vec![],
+        interrupts: None,
+        listing: false,
+        cfg: false,
+        analyze: false,
+        bench: false,
+        profile: false,
+        sanitize: false,
+        coverage: false,
+        lcov: None,
+        trace_json: false,
+        trace_java: false,
+        compare: None,
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--arch" => options.arch = match value()?.as_str() {
+                "toy" => Arch::Toy,
+                "toy8" => Arch::Toy8,
+                arch => return Err(format!("unknown architecture `{}`", arch)),
+            },
+            "--input" => options.input = parse_hex_words(value()?)?,
+            "--map" => options.ports.push(parse_port(value()?)?),
+            "--interrupts" => options.interrupts = Some(parse_interrupts(value()?)?),
+            "--stdin" => options.stdin = true,
+            "--break" => options.breakpoints.push(parse_breakpoint(value()?)?),
+            "--watch" => options.watchpoints.push(parse_watchpoint(value()?)?),
+            "--max-steps" => options.max_steps = Some(parse_count(value()?)?),
+            "--timeout-ms" => options.timeout = Some(Duration::from_millis(parse_count(value()?)?)),
+            "--detect-loops" => options.detect_loops = true,
+            "--listing" => options.listing = true,
+            "--cfg" => options.cfg = true,
+            "--analyze" => options.analyze = true,
+            "--bench" => options.bench = true,
+            "--profile" => options.profile = true,
+            "--sanitize" => options.sanitize = true,
+            "--coverage" => options.coverage = true,
+            "--lcov" => options.lcov = Some(value()?.clone()),
+            "--trace-json" => options.trace_json = true,
+            "--trace-java" => options.trace_java = true,
+            "--compare" => options.compare = Some(value()?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.filename = arg.clone(),
+        }
+    }
+    if options.arch == Arch::Toy8 {
+        let debugging = !options.breakpoints.is_empty() || !options.watchpoints.is_empty();
+        if debugging || options.detect_loops || !options.ports.is_empty() || options.profile || options.sanitize || options.cfg
+            || options.analyze || options.bench
+            || options.coverage || options.lcov.is_some() || options.trace_json
+            || options.trace_java || options.compare.is_some()
+            || options.interrupts.is_some() || options.filename.ends_with(".asm") {
+            return Err("toy8 runs XX: YY programs without breakpoints, watchpoints, \
+                        loop detection, mapped ports, interrupts, profiling, sanitizing, coverage, traces, graphs, analysis or benchmarks".into());
+        }
+    }
+    let limited = options.max_steps.is_some() || options.timeout.is_some() || options.detect_loops;
+    let debugging = !options.breakpoints.is_empty() || !options.watchpoints.is_empty();
+    if limited && (debugging || options.trace_json || options.trace_java || options.compare.is_some()) {
+        return Err("--max-steps, --timeout-ms and --detect-loops run without breakpoints, \
+                    watchpoints or traces".into());
+    }
+    if (options.coverage || options.lcov.is_some()) && options.filename.ends_with(".asm") {
+        return Err("coverage is kept by lines of XX: YYYY program files".into());
+    }
+    Ok(options)
+}
+
+fn read_source(filename: &str) -> String {
+    fs::read_to_string(filename).unwrap_or_else(|error| {
+        eprintln!("{}: {}", filename, error);
+        process::exit(2);
+    })
+}
+
+fn report_assembly_errors<T>(filename: &str, errors: Vec<AssemblyError>) -> T {
+    for error in errors {
+        eprintln!("{}:{}", filename, error);
+    }
+    process::exit(2);
+}
+
+// As try_load_program, exiting with what went wrong.
+fn load_program(filename: &str) -> Vec<ProgramLoadWord> {
+    try_load_program(filename).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        process::exit(2);
+    })
+}
+
+// A .asm file is assembled; anything else is read as XX: YYYY lines. What
+// went wrong comes back as one message.
+fn try_load_program(filename: &str) -> Result<Vec<ProgramLoadWord>, String> {
+    let source = fs::read_to_string(filename).map_err(|error| format!("{}: {}", filename, error))?;
+    if !filename.ends_with(".asm") {
+        let mut reader = ProgramReader::new();
+        reader.load_from_vec(source.lines().map(String::from).collect());
+        return Ok(reader.parse());
+    }
+    assemble(&source).map_err(|errors| {
+        errors.iter().map(|error| format!("{}:{}", filename, error)).collect::<Vec<_>>().join("; ")
+    })
+}
+
+// the graph as DOT, warning of code that cannot run or runs off the program
+fn print_cfg(loads: &[ProgramLoadWord]) {
+    let graph = ControlFlowGraph::build(loads);
+    for address in graph.unreachable() {
+        eprintln!("{:02X} is never reached", address);
+    }
+    for (from, to) in graph.falls_off() {
+        eprintln!("{:02X} can go on to {:02X}, outside the program", from, to);
+    }
+    print!("{}", graph.to_dot());
+}
+
+// the assembler's listing of a .asm file, or a disassembly of anything else
+fn print_listing(filename: &str) {
+    if filename.ends_with(".asm") {
+        let listing = assemble_listing(&read_source(filename))
+            .unwrap_or_else(|errors| report_assembly_errors(filename, errors));
+        print!("{}", listing);
+    } else {
+        print!("{}", disassemble(&load_program(filename)));
+    }
+}
+
+// steps a benchmark run may take when --max-steps does not say
+const BENCH_STEP_LIMIT: u64 = 1_000_000;
+// how long to keep running the program each way
+const BENCH_TIME: Duration = Duration::from_secs(1);
+
+// one benchmark run, to a halt or the step limit
+type BenchRun = fn(&mut Machine, &mut ExternalEnv, u64) -> Result<(), MachineError>;
+
+fn run_stepped(machine: &mut Machine, env: &mut ExternalEnv, limit: u64) -> Result<(), MachineError> {
+    machine.set_program_counter(machine.get_config().entry_pc);
+    machine.set_running();
+    while machine.get_running() && machine.get_step_count() < limit {
+        machine.run_one_step(env, false)?;
+    }
+    Ok(())
+}
+
+fn run_fast(machine: &mut Machine, env: &mut ExternalEnv, limit: u64) -> Result<(), MachineError> {
+    machine.run_with_limit(env, limit).map(|_| ())
+}
+
+fn run_blocks(machine: &mut Machine, env: &mut ExternalEnv, limit: u64) -> Result<(), MachineError> {
+    let limits = RunLimits { max_steps: Some(limit), ..RunLimits::default() };
+    BlockEngine::new().run_with_limits(machine, env, &limits).map(|_| ())
+}
+
+// Run the program over and over, fresh each time, for BENCH_TIME each
+// way: one run_one_step at a time, on the fast path that a limited run
+// takes, and a compiled block at a time. Prints the instructions per
+// second of each.
+fn run_benchmark(loads: &[ProgramLoadWord], options: &Options) -> Result<(), i32> {
+    let limit = options.max_steps.unwrap_or(BENCH_STEP_LIMIT);
+    let ways: [(&str, BenchRun); 3] = [("stepped", run_stepped), ("fast", run_fast), ("blocks", run_blocks)];
+    for (name, run) in ways {
+        let (mut steps, mut runs, mut elapsed) = (0u64, 0u64, Duration::ZERO);
+        while elapsed < BENCH_TIME {
+            let mut machine = Machine::new();
+            machine.load(loads.iter()
+                .map(|load| ProgramLoadWord::new(load.get_address(), load.get_content()))
+                .collect());
+            let mut env = ExternalEnv::new(options.input.clone());
+            let start = Instant::now();
+            let result = run(&mut machine, &mut env, limit);
+            elapsed += start.elapsed();
+            if let Err(error) = result {
+                eprintln!("{}", error);
+                return Err(1);
+            }
+            steps += machine.get_step_count();
+            runs += 1;
+        }
+        let rate = steps as f64 / elapsed.as_secs_f64();
+        println!("{:8} {:>12} instructions in {:>8} runs, {:>14.0} instructions/s", name, steps, runs, rate);
+    }
+    Ok(())
+}
+
+// Run as the options say. Returns whether the output should be printed
+// afterwards, or the exit code for a trap or a stopped run.
+fn run_program(machine: &mut Machine, device: &mut dyn Device, options: &Options) -> Result<bool, i32> {
+    let limited = options.max_steps.is_some() || options.timeout.is_some() || options.detect_loops;
+    if limited {
+        let limits = RunLimits {
+            max_steps: options.max_steps,
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            detect_loops: options.detect_loops,
+        };
+        match machine.run_with_limits(device, &limits) {
+            Ok(StepOutcome::Halted) => return Ok(true),
+            Ok(outcome) => {
+                eprintln!("{}", outcome);
+                return Err(3);
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                return Err(1);
+            },
+        }
+    }
+    if options.breakpoints.is_empty() && options.watchpoints.is_empty() {
+        let result = if options.trace_json {
+            machine.run_traced(device, &mut JsonLinesTrace::new(io::stdout()))
+        } else if options.trace_java || options.compare.is_some() {
+            let (result, trace) = run_toy_java_trace(machine, device);
+            if options.trace_java {
+                print!("{}", trace);
+            }
+            if let Some(reference) = &options.compare {
+                compare_with_reference(&trace, reference)?;
+            }
+            result
+        } else {
+            machine.run(device)
+        };
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            return Err(1);
+        }
+        return Ok(false);
+    }
+    for (address, condition) in &options.breakpoints {
+        match condition {
+            Some(condition) => machine.debugger_mut().add_conditional_breakpoint(*address, *condition),
+            None => machine.debugger_mut().add_breakpoint(*address),
+        };
+    }
+    for (target, access) in &options.watchpoints {
+        machine.debugger_mut().add_watchpoint(*target, *access);
+    }
+    machine.set_program_counter(machine.get_config().entry_pc);
+    machine.set_running();
+    loop {
+        match machine.run_until_event(device) {
+            Ok(DebugEvent::Halted) => return Ok(true),
+            Ok(event) => {
+                println!("{}", event);
+                machine.dump_regs();
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                return Err(1);
+            },
+        }
+    }
+}
+
+// the run's trace in the TOY.java format, ending with its core dump
+fn run_toy_java_trace(machine: &mut Machine, device: &mut dyn Device)
+    -> (Result<StepOutcome, MachineError>, String) {
+    let mut trace = ToyJavaTrace::new(vec![], &machine.get_regs());
+    let result = machine.run_traced(device, &mut trace);
+    let mut text = String::from_utf8(trace.into_inner()).expect("traces are ascii");
+    let dump = CoreDump::new(machine.get_program_counter(), &machine.get_regs(), &machine.get_memory());
+    text.push_str(&dump.to_string());
+    (result, text)
+}
+
+// Report the first difference from the reference trace, failing with exit
+// code 4 when there is one.
+fn compare_with_reference(trace: &str, reference: &str) -> Result<(), i32> {
+    let parse = |text: &str, name: &str| parse_trace(text).map_err(|error| {
+        eprintln!("{}: {}", name, error);
+        2
+    });
+    let ours = parse(trace, "trace")?;
+    let theirs = parse(&read_source(reference), reference)?;
+    match diff_traces(&ours, &theirs) {
+        None => {
+            eprintln!("{} steps match {}", ours.steps.len(), reference);
+            Ok(())
+        },
+        Some(divergence) => {
+            eprintln!("{}: {}", reference, divergence);
+            Err(4)
+        },
+    }
+}
+
+fn report_coverage(coverage: &Coverage, options: &Options) {
     let mut reader = ProgramReader::new();
-    reader.load_from_file(filename);
+    reader.load_from_file(&options.filename);
+    let program = reader.parse_with_lines();
+    if options.coverage {
+        print!("{}", coverage.report(&program));
+    }
+    if let Some(path) = &options.lcov {
+        if let Err(error) = fs::write(path, coverage.lcov(&options.filename, &program)) {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        }
+    }
+}
+
+// TOY-8 has no debugger or bus, so only the limits and i/o options apply
+fn run_toy8(options: &Options) {
+    let mut reader = Toy8Reader::new();
+    reader.load_from_file(&options.filename);
     let loads = reader.parse();
+    let mut machine = Toy8Machine::new();
+    if options.listing {
+        print!("{}", toy8_listing(&loads, machine.get_config().io_address));
+        return;
+    }
+    machine.load(loads);
+    let mut external = ExternalEnv::new(options.input.clone());
+    let mut stream = StreamDevice::new(io::stdin().lock(), io::stdout());
+    let device: &mut dyn Device = if options.stdin { &mut stream } else { &mut external };
+    let limits = RunLimits {
+        max_steps: options.max_steps,
+        deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+        detect_loops: false,
+    };
+    // as with TOY, an unlimited run's trace already shows the output
+    let limited = limits.max_steps.is_some() || limits.deadline.is_some();
+    let result = if limited {
+        machine.run_with_limits(device, &limits)
+    } else {
+        machine.run(device)
+    };
+    match result {
+        Ok(StepOutcome::Halted) => {},
+        Ok(outcome) => {
+            eprintln!("{}", outcome);
+            process::exit(3);
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        },
+    }
+    if limited && !options.stdin {
+        println!("{}", external.output_for_display());
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "batch") {
+        run_batch_command(&args[1..]);
+        return;
+    }
+    let mut options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.arch == Arch::Toy8 {
+        run_toy8(&options);
+        return;
+    }
+    if options.listing {
+        print_listing(&options.filename);
+        return;
+    }
+    let loads = load_program(&options.filename);
+    if options.cfg {
+        print_cfg(&loads);
+        return;
+    }
+    if options.analyze {
+        let analysis = analyze(&loads, &MachineConfig::toy()).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(2);
+        });
+        for warning in analysis.get_warnings() {
+            println!("{}", warning);
+        }
+        return;
+    }
+    if options.bench {
+        if let Err(code) = run_benchmark(&loads, &options) {
+            process::exit(code);
+        }
+        return;
+    }
     let mut machine = Machine::new();
-    let mut external = ExternalEnv::new(vec![25, 39]);
+    machine.set_sanitizing(options.sanitize);
     machine.load(loads);
-    // external.dump();
-    // helpers.dump_regs();
-    // helpers.dump_memory();
-    machine.run(&mut external);
-    // external.dump();
-    // helpers.dump_regs();
-    // helpers.dump_memory();
+    if let Some(config) = options.interrupts {
+        if let Err(error) = machine.set_interrupts(config) {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+    machine.set_profiling(options.profile);
+    machine.set_coverage(options.coverage || options.lcov.is_some());
+    let mut external = ExternalEnv::new(options.input.clone());
+    let mut stream = StreamDevice::new(io::stdin().lock(), io::stdout());
+    let device: &mut dyn Device = if options.stdin { &mut stream } else { &mut external };
+    let print_output = if options.ports.is_empty() {
+        run_program(&mut machine, device, &options)
+    } else {
+        let mut bus = DeviceBus::new();
+        bus.bind(IO_ADDRESS, "stdio", device);
+        for (address, spec) in options.ports.drain(..) {
+            match spec {
+                PortSpec::Console => bus.bind(address, "console", CharConsole::new(io::stdout())),
+                PortSpec::Counter => bus.bind(address, "counter", CycleCounter::new()),
+                PortSpec::Random(seed) => bus.bind(address, "random", RandomSource::new(seed)),
+                PortSpec::Input(words) => bus.bind(address, "input", InputStream::new(words)),
+                PortSpec::Timer(period) => bus.bind(address, "timer", IntervalTimer::new(period)),
+            };
+        }
+        run_program(&mut machine, &mut bus, &options)
+    };
+    if let Some(profile) = machine.get_profile() {
+        print!("{}", profile.report(&machine.get_memory(), 5));
+    }
+    if let Some(sanitizer) = machine.get_sanitizer() {
+        for finding in sanitizer.get_findings() {
+            eprintln!("{}", finding);
+        }
+    }
+    if let Some(coverage) = machine.get_coverage() {
+        report_coverage(coverage, &options);
+    }
+    let print_output = print_output.unwrap_or_else(|code| process::exit(code));
+    if print_output && !options.stdin {
+        println!("{}", external.output_for_display());
+    }
 }
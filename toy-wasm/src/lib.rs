@@ -4,38 +4,122 @@ use wasm_bindgen::prelude::*;
 use machine::machine::Machine as Toy;
 use machine::program_reader::program_reader::ProgramReader;
 use machine::external_env::external_env::ExternalEnv;
+use machine::device::device::{ClosureDevice, Device};
+use machine::outcome::outcome::{MachineError, StepOutcome};
+use machine::debugger::debugger::{Access, Condition, DebugEvent, WatchTarget};
+use machine::snapshot::snapshot::MachineSnapshot;
+use machine::toy8::toy8::{Toy8Machine, Toy8Reader};
+use machine::trace::trace::TraceBuffer;
 
 #[wasm_bindgen]
 pub struct Portal {
     backing: Toy,
+    // when set, the portal runs TOY-8 instead of `backing`; it has no
+    // debugger, history or snapshots
+    toy8: Option<Toy8Machine>,
     external: ExternalEnv,
+    // when set, i/o goes to the page's callbacks instead of `external`
+    callbacks: Option<ClosureDevice>,
+    error: Option<MachineError>,
+    last_event: Option<DebugEvent>,
+    step_budget: u64,
+    // when set, step_program keeps the events of its steps here
+    trace: Option<TraceBuffer>,
+}
+
+// steps run_until_event may take before giving the page back
+const DEFAULT_STEP_BUDGET: u64 = 100_000;
+// steps step_back can undo; older history is dropped
+const HISTORY_STEPS: usize = 10_000;
+
+impl Default for Portal {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
 impl Portal {
     pub fn new() -> Self {
-        let backing = Toy::new();
+        let mut backing = Toy::new();
+        backing.set_journal_enabled(true);
+        backing.set_journal_capacity(Some(HISTORY_STEPS));
         let external = ExternalEnv::new(vec![]);
         Self {
             backing,
+            toy8: None,
             external,
+            callbacks: None,
+            error: None,
+            last_event: None,
+            step_budget: DEFAULT_STEP_BUDGET,
+            trace: None,
+        }
+    }
+
+    // "toy" or "toy8"; switching clears the registers, memory and i/o
+    pub fn set_architecture(&mut self, name: &str) -> Result<(), JsValue> {
+        let toy8 = match name {
+            "toy" => false,
+            "toy8" => true,
+            _ => return Err(JsValue::from_str(&format!("unknown architecture `{}`", name))),
+        };
+        self.clear_regs_and_memory();
+        self.toy8 = toy8.then(Toy8Machine::new);
+        Ok(())
+    }
+
+    pub fn get_architecture(&self) -> String {
+        if self.toy8.is_some() { "toy8" } else { "toy" }.into()
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match &self.toy8 {
+            Some(toy8) => toy8.get_config().memory_size(),
+            None => self.backing.get_config().memory_size(),
+        }
+    }
+
+    pub fn register_count(&self) -> usize {
+        match &self.toy8 {
+            Some(toy8) => toy8.get_config().register_count,
+            None => self.backing.get_config().register_count,
         }
     }
 
+    #[allow(clippy::needless_range_loop)]
     pub fn load_regs(&mut self, regs: Vec<i32>) {
+        if let Some(toy8) = &mut self.toy8 {
+            if let Some(value) = regs.first() {
+                toy8.set_accumulator(*value as u8);
+            }
+            return;
+        }
         for i in 0..16.min(regs.len()){
             self.backing.set_reg(i, regs[i] as u16);
         }
     }
 
+    // On TOY-8, register 0 is the accumulator. A register or address past
+    // register_count or memory_size gives an empty string.
     pub fn reg_as_string(&self, index: usize) -> String {
-        let value = self.backing.get_regs()[index];
-        format!("{:04X}", value)
+        if index >= self.register_count() {
+            return "".into();
+        }
+        match &self.toy8 {
+            Some(toy8) => format!("{:02X}", toy8.get_accumulator()),
+            None => format!("{:04X}", self.backing.get_regs()[index]),
+        }
     }
 
     pub fn memory_as_string(&self, index: usize) -> String {
-        let value = self.backing.get_memory_word(index);
-        format!("{:04X}", value)
+        if index >= self.memory_size() {
+            return "".into();
+        }
+        match &self.toy8 {
+            Some(toy8) => format!("{:02X}", toy8.get_memory_word(index)),
+            None => format!("{:04X}", self.backing.get_memory_word(index)),
+        }
     }
 
     pub fn inputs_as_string(&self) -> String {
@@ -47,6 +131,12 @@ impl Portal {
     }
 
     pub fn next_instruction_as_string(&self) -> String{
+        if let Some(toy8) = &self.toy8 {
+            let pc = toy8.get_program_counter();
+            let instruction_word = toy8.get_memory_word(pc as usize);
+            let operation = toy8.current_instruction_pp(instruction_word);
+            return format!("{:02X}: {:02X} - {}", pc, instruction_word, operation);
+        }
         let pc = self.backing.get_program_counter();
         let instruction_word = self.backing.get_memory_word(pc as usize);
         let operation = self.backing.current_instruction_pp(instruction_word);
@@ -54,11 +144,35 @@ impl Portal {
     }
 
     pub fn get_pc(&self) -> i32 {
-        self.backing.get_program_counter().into()
+        match &self.toy8 {
+            Some(toy8) => toy8.get_program_counter().into(),
+            None => self.backing.get_program_counter().into(),
+        }
     }
 
     pub fn set_pc(&mut self, value: i32){
-        self.backing.set_program_counter(value as u8);
+        match &mut self.toy8 {
+            Some(toy8) => toy8.set_program_counter(value as u16),
+            None => self.backing.set_program_counter(value as u16),
+        }
+    }
+
+    // load XX: YYYY lines, or XX: YY lines on TOY-8
+    pub fn load_program(&mut self, text: &str) {
+        let lines = text.lines().map(String::from).collect();
+        match &mut self.toy8 {
+            Some(toy8) => {
+                let mut reader = Toy8Reader::new();
+                reader.load_from_vec(lines);
+                toy8.load(reader.parse());
+            },
+            None => {
+                let mut reader = ProgramReader::new();
+                reader.load_from_vec(lines);
+                self.backing.load(reader.parse());
+            },
+        }
+        self.error = None;
     }
 
     pub fn load_fixed_program(&mut self) {
@@ -82,6 +196,7 @@ impl Portal {
         reader.load_from_vec(program_text);
         let loads = reader.parse();
         self.backing.load(loads);
+        self.error = None;
     }
 
     pub fn push_to_input(&mut self, value: i32) {
@@ -89,26 +204,263 @@ impl Portal {
     }
 
     pub fn set_program_running(&mut self) {
-        self.backing.set_running();
+        match &mut self.toy8 {
+            Some(toy8) => toy8.set_running(),
+            None => self.backing.set_running(),
+        }
     }
 
     pub fn reset_program_running(&mut self) {
-        self.backing.reset_running();
+        match &mut self.toy8 {
+            Some(toy8) => toy8.reset_running(),
+            None => self.backing.reset_running(),
+        }
     }
 
     pub fn get_program_running(&mut self) -> bool {
-        self.backing.get_running()
+        match &self.toy8 {
+            Some(toy8) => toy8.get_running(),
+            None => self.backing.get_running(),
+        }
+    }
+
+    // Send i/o to JavaScript: read(address) returns the next word, or
+    // undefined when there is none, and write(address, word) takes a word.
+    pub fn set_io_callbacks(&mut self, read: js_sys::Function, write: js_sys::Function) {
+        let device = ClosureDevice::new(
+            move |address| {
+                read.call1(&JsValue::NULL, &JsValue::from(address)).ok()?
+                    .as_f64()
+                    .map(|word| word as u16)
+            },
+            move |address, word| {
+                let _ = write.call2(&JsValue::NULL, &JsValue::from(address), &JsValue::from(word));
+            });
+        self.callbacks = Some(device);
+    }
+
+    pub fn clear_io_callbacks(&mut self) {
+        self.callbacks = None;
     }
 
     pub fn step_program(&mut self) {
-        self.backing.run_one_step(&mut self.external, false);
+        let device = device(&mut self.callbacks, &mut self.external);
+        let result = match (&mut self.toy8, &mut self.trace) {
+            (Some(toy8), Some(trace)) => toy8.run_one_step_traced(device, trace),
+            (Some(toy8), None) => toy8.run_one_step(device, false),
+            (None, Some(trace)) => self.backing.run_one_step_traced(device, trace),
+            (None, None) => self.backing.run_one_step(device, false),
+        };
+        // a step that went through clears the last trap
+        self.error = result.err();
+    }
+
+    // Check the TOY program's memory use, starting with the next program
+    // loaded.
+    pub fn set_sanitizing(&mut self, enabled: bool) {
+        self.backing.set_sanitizing(enabled);
+    }
+
+    // what the check found so far, one finding per line
+    pub fn sanitizer_findings(&self) -> String {
+        self.backing.get_sanitizer().map_or_else(String::new, |sanitizer| {
+            sanitizer.get_findings().iter().map(|finding| format!("{}\n", finding)).collect()
+        })
+    }
+
+    // Keep the trace events of stepped instructions, at most limit of
+    // them, or stop and drop them with limit 0.
+    pub fn set_tracing(&mut self, limit: u32) {
+        self.trace = (limit > 0).then(|| TraceBuffer::with_limit(limit as usize));
+    }
+
+    pub fn trace_length(&self) -> usize {
+        self.trace.as_ref().map_or(0, |trace| trace.len())
+    }
+
+    // the kept events as JSON Lines, oldest first
+    pub fn trace_as_json_lines(&self) -> String {
+        self.trace.as_ref().map_or_else(String::new, |trace| trace.to_json_lines())
+    }
+
+    pub fn clear_trace(&mut self) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.clear();
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: i32) -> usize {
+        self.backing.debugger_mut().add_breakpoint(address as u16)
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, address: i32, condition: &str) -> Result<usize, JsValue> {
+        let condition = Condition::parse(condition).map_err(|message| JsValue::from_str(&message))?;
+        Ok(self.backing.debugger_mut().add_conditional_breakpoint(address as u16, condition))
+    }
+
+    // target is R[x] or mem[xx]; access is "read", "write" or anything else for both
+    pub fn add_watchpoint(&mut self, target: &str, access: &str) -> Result<usize, JsValue> {
+        let target = WatchTarget::parse(target).map_err(|message| JsValue::from_str(&message))?;
+        let access = match access {
+            "read" => Access::Read,
+            "write" => Access::Write,
+            _ => Access::ReadWrite,
+        };
+        Ok(self.backing.debugger_mut().add_watchpoint(target, access))
+    }
+
+    pub fn remove_breakpoint_or_watchpoint(&mut self, id: usize) -> bool {
+        self.backing.debugger_mut().remove(id)
+    }
+
+    // Step once and name what happened: "step", "halted", "breakpoint",
+    // "watchpoint" or "error"; the animation keeps going only on "step".
+    pub fn step_event(&mut self) -> String {
+        let device = device(&mut self.callbacks, &mut self.external);
+        let result = match &mut self.toy8 {
+            Some(toy8) => toy8_step(toy8, device),
+            None => self.backing.debug_step(device),
+        };
+        self.record_event(result)
+    }
+
+    // Like step_event but keeps going past plain steps; gives up with
+    // "limit" after the step budget so that a looping program cannot hang
+    // the page.
+    pub fn run_until_event(&mut self) -> String {
+        for _ in 0..self.step_budget {
+            let device = device(&mut self.callbacks, &mut self.external);
+            let result = match &mut self.toy8 {
+                Some(toy8) => toy8_step(toy8, device),
+                None => self.backing.debug_step(device),
+            };
+            if result != Ok(DebugEvent::Stepped) {
+                return self.record_event(result);
+            }
+        }
+        self.last_event = None;
+        "limit".into()
+    }
+
+    pub fn set_step_budget(&mut self, steps: u32) {
+        self.step_budget = steps.into();
+    }
+
+    pub fn get_step_count(&self) -> f64 {
+        match &self.toy8 {
+            Some(toy8) => toy8.get_step_count() as f64,
+            None => self.backing.get_step_count() as f64,
+        }
+    }
+
+    pub fn last_event_as_string(&self) -> String {
+        match (&self.error, &self.last_event) {
+            (Some(error), _) => error.to_string(),
+            (None, Some(DebugEvent::Stepped)) | (None, None) => "".into(),
+            (None, Some(event)) => event.to_string(),
+        }
+    }
+
+    pub fn step_back(&mut self) -> bool {
+        if self.toy8.is_some() {
+            return false;
+        }
+        let device = device(&mut self.callbacks, &mut self.external);
+        let stepped = self.backing.step_back(device).stepped();
+        if stepped {
+            self.error = None;
+        }
+        stepped
+    }
+
+    pub fn steps_taken(&self) -> usize {
+        self.backing.steps_recorded()
+    }
+
+    pub fn snapshot_as_json(&self) -> String {
+        if self.toy8.is_some() {
+            return "".into();
+        }
+        let device: &dyn Device = match &self.callbacks {
+            Some(callbacks) => callbacks,
+            None => &self.external,
+        };
+        let snapshot = self.backing.snapshot(device);
+        serde_json::to_string(&snapshot).unwrap_or_default()
+    }
+
+    pub fn restore_from_json(&mut self, json: &str) -> Result<(), JsValue> {
+        if self.toy8.is_some() {
+            return Err(JsValue::from_str("TOY-8 has no snapshots"));
+        }
+        let snapshot: MachineSnapshot = serde_json::from_str(json)
+            .map_err(|error| JsValue::from_str(&error.to_string()))?;
+        let device = device(&mut self.callbacks, &mut self.external);
+        self.backing.restore(&snapshot, device)
+            .map_err(|error| JsValue::from_str(&error.to_string()))?;
+        self.error = None;
+        Ok(())
+    }
+
+    pub fn error_as_string(&self) -> String {
+        match &self.error {
+            Some(error) => error.to_string(),
+            None => "".into(),
+        }
     }
 
     pub fn clear_regs_and_memory(&mut self) {
-        let backing = Toy::new();
+        let mut backing = Toy::new();
+        backing.set_journal_enabled(true);
+        backing.set_journal_capacity(Some(HISTORY_STEPS));
         let external = ExternalEnv::new(vec![]);
         self.backing = backing;
+        self.toy8 = self.toy8.as_ref().map(|_| Toy8Machine::new());
         self.external = external;
+        self.error = None;
+        self.last_event = None;
+    }
+}
+
+// one TOY-8 step reported as a debug event; TOY-8 has no breakpoints
+fn toy8_step(toy8: &mut Toy8Machine, device: &mut dyn Device) -> Result<DebugEvent, MachineError> {
+    if !toy8.get_running() {
+        return Ok(DebugEvent::Halted);
+    }
+    match toy8.run_one_step(device, false)? {
+        StepOutcome::Halted => Ok(DebugEvent::Halted),
+        _ => Ok(DebugEvent::Stepped),
+    }
+}
+
+// the callbacks when the page has set them, otherwise the input and output lists
+fn device<'a>(callbacks: &'a mut Option<ClosureDevice>, external: &'a mut ExternalEnv)
+    -> &'a mut dyn Device {
+    match callbacks {
+        Some(callbacks) => callbacks,
+        None => external,
+    }
+}
+
+impl Portal {
+    fn record_event(&mut self, result: Result<DebugEvent, MachineError>) -> String {
+        match result {
+            Ok(event) => {
+                self.error = None;
+                self.last_event = Some(event);
+                match event {
+                    DebugEvent::Stepped => "step",
+                    DebugEvent::Halted => "halted",
+                    DebugEvent::Breakpoint { .. } => "breakpoint",
+                    DebugEvent::Watchpoint { .. } => "watchpoint",
+                }.into()
+            },
+            Err(error) => {
+                self.error = Some(error);
+                self.last_event = None;
+                "error".into()
+            },
+        }
     }
 }
 
@@ -16,6 +16,8 @@ fn reg_dump() {
     assert_eq!(portal.reg_as_string(2), "1234");
     assert_eq!(portal.reg_as_string(3), "0000");
     assert_eq!(portal.reg_as_string(4), "0555");
+    assert_eq!(portal.reg_as_string(16), "");
+    assert_eq!(portal.memory_as_string(256), "");
 }
 
 #[wasm_bindgen_test]
@@ -48,4 +50,161 @@ fn step_program() {
     assert_eq!(running, true);
     let value = portal.memory_as_string(0xFF);
     assert_eq!(value, "0014");
-}
\ No newline at end of file
+}
+#[wasm_bindgen_test]
+fn error_clears_after_a_good_step() {
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.set_pc(0x10);
+    portal.set_program_running();
+    portal.step_program();
+    assert_eq!(portal.error_as_string(), "Input exhausted @ pc = 10x; instruction 8AFF");
+    portal.push_to_input(2);
+    portal.set_program_running();
+    portal.step_program();
+    assert_eq!(portal.error_as_string(), "");
+    portal.set_pc(0x10);
+    portal.set_program_running();
+    portal.step_program();
+    assert_ne!(portal.error_as_string(), "");
+    portal.load_program("10: 0000\n");
+    assert_eq!(portal.error_as_string(), "");
+}
+
+#[wasm_bindgen_test]
+fn step_back_program() {
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.set_pc(0x10);
+    portal.push_to_input(20);
+    portal.set_program_running();
+    portal.step_program();
+    assert_eq!(portal.steps_taken(), 1);
+    assert!(portal.step_back());
+    assert_eq!(portal.get_pc(), 0x10);
+    assert_eq!(portal.memory_as_string(0xFF), "0000");
+    assert_eq!(portal.inputs_as_string(), "20(0014)");
+    assert!(!portal.step_back());
+}
+
+#[wasm_bindgen_test]
+fn save_and_restore_session() {
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.set_pc(0x10);
+    portal.push_to_input(2);
+    portal.push_to_input(3);
+    portal.set_program_running();
+    portal.step_program();
+    let saved = portal.snapshot_as_json();
+    portal.clear_regs_and_memory();
+    assert_eq!(portal.memory_as_string(0x10), "0000");
+    portal.restore_from_json(&saved).unwrap();
+    assert_eq!(portal.memory_as_string(0x10), "8AFF");
+    assert_eq!(portal.get_pc(), 0x11);
+    assert_eq!(portal.inputs_as_string(), "3(0003)");
+    assert!(portal.restore_from_json("{}").is_err());
+}
+
+#[wasm_bindgen_test]
+fn run_to_breakpoint() {
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.set_pc(0x10);
+    portal.push_to_input(2);
+    portal.push_to_input(3);
+    portal.set_program_running();
+    portal.add_conditional_breakpoint(0x14, "R[A] == 0").unwrap();
+    assert_eq!(portal.run_until_event(), "breakpoint");
+    assert_eq!(portal.get_pc(), 0x14);
+    assert_eq!(portal.reg_as_string(0xC), "0006");
+    assert_eq!(portal.step_event(), "step");
+    assert_eq!(portal.run_until_event(), "halted");
+    assert!(portal.add_watchpoint("R[Q]", "write").is_err());
+}
+
+#[wasm_bindgen_test]
+fn run_until_event_gives_up() {
+    // 10: C010   goto 10
+    let mut memory = vec![0u16; 256];
+    memory[0x10] = 0xC010;
+    let json = format!(
+        "{{\"version\":3,\"pc\":16,\"regs\":{:?},\"memory\":{:?},\"running\":true,\"steps\":0,\"interrupts\":null,\"input\":[],\"output\":[]}}",
+        vec![0u16; 16], memory);
+    let mut portal = Portal::new();
+    portal.restore_from_json(&json).unwrap();
+    portal.set_step_budget(50);
+    assert_eq!(portal.run_until_event(), "limit");
+    assert_eq!(portal.get_step_count(), 50.0);
+    assert_eq!(portal.get_program_running(), true);
+}
+
+#[wasm_bindgen_test]
+fn io_through_callbacks() {
+    let read = js_sys::Function::new_with_args("address", "return 3;");
+    let write = js_sys::Function::new_with_args("address, word", "globalThis.written = word;");
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.set_pc(0x10);
+    portal.set_program_running();
+    portal.set_io_callbacks(read, write);
+    assert_eq!(portal.run_until_event(), "halted");
+    let written = js_sys::Reflect::get(&js_sys::global(), &"written".into()).unwrap();
+    assert_eq!(written.as_f64(), Some(9.0));
+    // the callbacks took the output, so the portal has none to show
+    assert_eq!(portal.outputs_as_string(), "(No output)");
+}
+
+#[wasm_bindgen_test]
+fn toy8_architecture() {
+    let mut portal = Portal::new();
+    assert!(portal.set_architecture("toy9").is_err());
+    portal.set_architecture("toy8").unwrap();
+    assert_eq!(portal.get_architecture(), "toy8");
+    assert_eq!(portal.memory_size(), 32);
+    assert_eq!(portal.register_count(), 1);
+    // 00: 9F read A; 01: 3F A <- A + mem[1F], the word just read; 02: BF write A; 03: 00 halt
+    portal.load_program("00: 9F\n01: 3F\n02: BF\n03: 00\n");
+    portal.push_to_input(21);
+    portal.set_pc(0);
+    portal.set_program_running();
+    assert_eq!(portal.next_instruction_as_string(), "00: 9F - Op: Load - input to A via mem[1F]");
+    assert_eq!(portal.run_until_event(), "halted");
+    assert_eq!(portal.reg_as_string(0), "2A");
+    assert_eq!(portal.reg_as_string(1), "");
+    assert_eq!(portal.memory_as_string(31), "2A");
+    assert_eq!(portal.memory_as_string(32), "");
+    assert_eq!(portal.outputs_as_string(), "42(002A)");
+    assert!(!portal.step_back());
+}
+
+#[wasm_bindgen_test]
+fn trace_buffer() {
+    let mut portal = Portal::new();
+    portal.load_fixed_program();
+    portal.push_to_input(3);
+    portal.set_pc(0x10);
+    portal.set_program_running();
+    portal.set_tracing(100);
+    portal.step_program();
+    // fetch, input, decode, memory read and register write
+    assert_eq!(portal.trace_length(), 5);
+    assert!(portal.trace_as_json_lines().starts_with("{\"event\":\"fetch\",\"pc\":16,"));
+    portal.clear_trace();
+    assert_eq!(portal.trace_as_json_lines(), "");
+}
+
+#[wasm_bindgen_test]
+fn toy8_trace_buffer() {
+    let mut portal = Portal::new();
+    portal.set_architecture("toy8").unwrap();
+    portal.load_program("00: 9F\n01: 00\n");
+    portal.push_to_input(3);
+    portal.set_pc(0);
+    portal.set_program_running();
+    portal.set_tracing(100);
+    portal.step_program();
+    // fetch, input, decode, memory read and register write, as for TOY
+    assert_eq!(portal.trace_length(), 5);
+    assert!(portal.trace_as_json_lines().starts_with("{\"event\":\"fetch\",\"pc\":0,\"word\":159}"));
+}
@@ -5,10 +5,15 @@ let pc = 0;
 let portal = wasm.Portal.new();
 let running_delay = 1000;
 
+// one column per register and 16 words per memory row, however many the
+// portal's architecture has
 function regs_header_to_table(table) {
-    let row_labels = ["Regs","0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C", "D", "E", "F"]
+    let row_labels = ["Regs"]
+    for(var i = 0; i < portal.register_count(); i++) {
+        row_labels.push(i.toString(16).toUpperCase());
+    }
     var row = document.createElement("TR");
-    for(var i = 0; i < 17; i++) {
+    for(var i = 0; i < row_labels.length; i++) {
         var th = document.createElement("TH");
         var text = document.createTextNode(row_labels[i]);
         th.appendChild(text);
@@ -26,7 +31,7 @@ function regs_header_to_table(table) {
 
 function collect_regs_data(portal) {
     let regs_array = []
-    for(var i = 0; i < 16; i++){
+    for(var i = 0; i < portal.register_count(); i++){
         let str = portal.reg_as_string(i);
         regs_array.push(str);
     }
@@ -34,7 +39,7 @@ function collect_regs_data(portal) {
 }
 
 function regs_add_data_to_table(table_row, string_array) {
-    for(var i = 0; i < 16; i++) {
+    for(var i = 0; i < string_array.length; i++) {
         var td = document.createElement("TD");
         var text = document.createTextNode(string_array[i]);
         td.appendChild(text);
@@ -43,7 +48,7 @@ function regs_add_data_to_table(table_row, string_array) {
 }
 
 function regs_update_data_in_table(table_row, string_array) {
-    for(var i = 0; i < 16; i++) {
+    for(var i = 0; i < string_array.length; i++) {
         table_row.cells[i + 1].innerHTML = string_array[i];
     }
 }
@@ -59,7 +64,7 @@ function memory_header_to_table(table) {
     }
     table.appendChild(row);
 
-    for (var index = 0; index < 256; index += 16) {
+    for (var index = 0; index < portal.memory_size(); index += 16) {
         var header = ("0000" + index.toString(16)).substr(-4);
         header = index.toString(16).toUpperCase().padStart(4, "0");
         var row = document.createElement("TR");
@@ -73,7 +78,7 @@ function memory_header_to_table(table) {
 
 function collect_memory_data(portal) {
     let memory_array = []
-    for(var i = 0; i < 256; i++){
+    for(var i = 0; i < portal.memory_size(); i++){
         let str = portal.memory_as_string(i);
         memory_array.push(str);
     }
@@ -83,7 +88,7 @@ function collect_memory_data(portal) {
 function memory_add_data_to_table(table_row, start_index, string_array) {
     for(var i = 0; i < 16; i++) {
         var td = document.createElement("TD");
-        var text = document.createTextNode(string_array[start_index + i]);
+        var text = document.createTextNode(string_array[start_index + i] ?? "");
         td.appendChild(text);
         table_row.appendChild(td);
     }
@@ -91,7 +96,7 @@ function memory_add_data_to_table(table_row, start_index, string_array) {
 
 function memory_update_data_in_table(table_row, start_index, string_array) {
     for(var i = 0; i < 16; i++) {
-        table_row.cells[i + 1].innerHTML = string_array[start_index + i];
+        table_row.cells[i + 1].innerHTML = string_array[start_index + i] ?? "";
     }
 }
 
@@ -110,12 +115,14 @@ function set_up_display() {
     let memory_data = collect_memory_data(portal);
 
     let table = document.getElementById("regsTable");
+    table.innerHTML = "";
     regs_header_to_table(table);
     regs_add_data_to_table(table.rows[1], regs_data);
 
     table = document.getElementById("memoryTable");
+    table.innerHTML = "";
     memory_header_to_table(table);
-    for (var i = 0; i < 16; i++) {
+    for (var i = 0; i * 16 < memory_data.length; i++) {
         memory_add_data_to_table(table.rows[1 + i], i*16, memory_data);
     }
     pc = portal.get_pc();
@@ -131,17 +138,22 @@ function refresh_display() {
     regs_update_data_in_table(table.rows[1], regs_data);
 
     table = document.getElementById("memoryTable");
-    for (var i = 0; i < 16; i++) {
+    for (var i = 0; i * 16 < memory_data.length; i++) {
         memory_update_data_in_table(table.rows[1 + i], i*16, memory_data);
     }
 
     let running = portal.get_program_running();
     step_button.disabled = !running;
     run_button.disabled = !running;
+    back_button.disabled = portal.steps_taken() == 0;
     let running_text = "running";
     if (!running) {
         running_text = "not " + running_text;
     }
+    let event_text = portal.last_event_as_string();
+    if (event_text.length > 0) {
+        running_text = running_text + " - " + event_text;
+    }
     document.getElementById("status").innerHTML = running_text;
     document.getElementById("input_values").innerHTML =
         portal.inputs_as_string();
@@ -157,44 +169,90 @@ function refresh_display() {
 }
 
 function step_on_click() {
-    portal.step_program();
+    portal.step_event();
+    refresh_display();
+}
+function back_on_click() {
+    portal.step_back();
     refresh_display();
 }
 function run_on_click() {
     run_button.disabled = true
-    let running = portal.get_program_running();
-    if (running){
-        portal.step_program();
-        refresh_display();
-    }
-    running = portal.get_program_running();
-    if (running) {
+    let event = portal.step_event();
+    refresh_display();
+    if (event == "step") {
         console.log("running delay", running_delay)
         setTimeout(run_on_click, running_delay);
     }
 }
 
+function save_on_click() {
+    window.localStorage.setItem("toy_session", portal.snapshot_as_json());
+}
+
+function load_on_click() {
+    let saved = window.localStorage.getItem("toy_session");
+    if (saved == null) {
+        return;
+    }
+    try {
+        portal.restore_from_json(saved);
+    } catch (error) {
+        console.log("could not restore session", error);
+    }
+    refresh_display();
+}
+
+// the sample program for the portal's architecture, ready to run
+function load_sample() {
+    if (portal.get_architecture() == "toy8") {
+        // 00: read A; 01: A <- A + mem[1F], the word just read; 02: write A; 03: halt
+        portal.load_program("00: 9F\n01: 3F\n02: BF\n03: 00\n");
+        portal.set_pc(0x00);
+        portal.push_to_input(21);
+    } else {
+        portal.load_fixed_program();
+        portal.set_pc(0x10);
+        portal.push_to_input(2);
+        portal.push_to_input(3);
+    }
+    portal.set_program_running();
+}
+
 function restart() {
     portal.clear_regs_and_memory();
-    portal.load_fixed_program();
-    portal.set_pc(0x10);
-    portal.push_to_input(2);
-    portal.push_to_input(3);
-    portal.set_program_running();
+    load_sample();
+    refresh_display();
+}
+
+// switching clears the machine and lays the tables out again for its sizes
+function architecture_on_change() {
+    try {
+        portal.set_architecture(architecture_select.value);
+    } catch (error) {
+        console.log("could not switch architecture", error);
+        architecture_select.value = portal.get_architecture();
+        return;
+    }
+    load_sample();
+    set_up_display();
     refresh_display();
 }
 
 let step_button = document.getElementById("step");
 step_button.addEventListener("click", step_on_click);
+let back_button = document.getElementById("back");
+back_button.addEventListener("click", back_on_click);
 let run_button = document.getElementById("run");
 run_button.addEventListener("click", run_on_click);
 let restart_button = document.getElementById("restart");
 restart_button.addEventListener("click", restart);
+document.getElementById("save").addEventListener("click", save_on_click);
+document.getElementById("load").addEventListener("click", load_on_click);
+let architecture_select = document.getElementById("architecture");
+architecture_select.addEventListener("change", architecture_on_change);
 
-portal.load_fixed_program();
-portal.set_pc(0x10);
-portal.push_to_input(2);
-portal.push_to_input(3);
-portal.set_program_running();
+portal.set_architecture(architecture_select.value);
+load_sample();
 set_up_display();
 refresh_display();